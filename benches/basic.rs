@@ -1,9 +1,11 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use keyell::{
-    render::{Background, Camera, Canvas, Color, Colorer, Degrees, Material, Plane, Sphere},
+    render::{
+        Background, Camera, Canvas, Color, Colorer, Degrees, Material, Plane, SamplerKind, Sphere,
+    },
     render_scene,
     types::{Normal, Point, Vec3},
-    Scene,
+    RenderSettings, Scene,
 };
 
 fn make_scene() -> Scene {
@@ -24,6 +26,7 @@ fn make_scene() -> Scene {
             material: Material::Dielectric {
                 refraction_index: 1.3,
                 colorer: Colorer::Solid(Color::WHITE),
+                absorption: Color::BLACK,
             },
         },
         Sphere {
@@ -32,12 +35,13 @@ fn make_scene() -> Scene {
             material: Material::Dielectric {
                 refraction_index: 0.4,
                 colorer: Colorer::Solid(Color::new(0.6, 0.3, 0.9)),
+                absorption: Color::BLACK,
             },
         },
         Sphere {
             center: Point::new(0., -0.5, 0.),
             radius: 0.3,
-            material: Material::Light(Colorer::Bubblegum),
+            material: Material::light(Colorer::Bubblegum),
         },
         Sphere {
             center: Point::new(0.1, 0.3, 0.1),
@@ -56,7 +60,7 @@ fn make_scene() -> Scene {
     }];
 
     const BACKGROUND: Background = Background {
-        material: Material::Light(Colorer::ZGradient {
+        material: Material::light(Colorer::ZGradient {
             top: Color::new(0.5, 0.7, 1.0),
             bottom: Color::BLACK,
         }),
@@ -66,21 +70,19 @@ fn make_scene() -> Scene {
         spheres,
         planes,
         background: BACKGROUND,
+        ..Default::default()
     }
 }
 
 fn benchmarked(pixels: &mut [Color], scene: &Scene, canvas: &Canvas) {
-    let samples_per_pixel = 10;
-    let maximum_bounces = 10;
+    let settings = RenderSettings {
+        samples_per_pixel: 10,
+        maximum_bounces: 10,
+        sampler: SamplerKind::Independent,
+        ..Default::default()
+    };
     let camera = Camera::from_canvas(canvas, Point::new(0., 0., 0.05), Degrees::new(90.));
-    render_scene(
-        pixels,
-        scene,
-        canvas,
-        &camera,
-        samples_per_pixel,
-        maximum_bounces,
-    );
+    render_scene(pixels, scene, canvas, &camera, &settings, 0..canvas.height);
 }
 
 fn benchmark(c: &mut Criterion) {
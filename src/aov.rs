@@ -0,0 +1,317 @@
+use serde::{Deserialize, Serialize};
+
+use crate::render::Color;
+use crate::RenderOutput;
+
+/// Additional buffer, or arbitrary output variable, which `render_scene` can fill.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Aov {
+    /// Distance along camera rays to the first hit, infinite where they hit nothing.
+    Depth,
+    /// Outward normal of the first non-specular surface seen, zero for the background.
+    Normal,
+    /// Albedo of the first non-specular surface seen, tinted by the specular ones on the way.
+    Albedo,
+    /// Kind of material at the first hit.
+    MaterialId,
+    /// Object at the first hit.
+    ObjectId,
+    /// Light emitted by the first hit, or reflected by it straight from an emitter, lights and
+    /// the background included.
+    Direct,
+    /// Light reaching the camera after bouncing more than once.
+    Indirect,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 7] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::MaterialId,
+        Aov::ObjectId,
+        Aov::Direct,
+        Aov::Indirect,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::MaterialId => "material_id",
+            Aov::ObjectId => "object_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+
+    /// Whether the buffer is filled from the first hit of camera rays.
+    pub(crate) fn is_first_hit(self) -> bool {
+        matches!(self, Aov::Depth | Aov::MaterialId | Aov::ObjectId)
+    }
+
+    /// Whether the buffer is one of the features guiding denoising, filled by following camera
+    /// rays through specular surfaces.
+    pub(crate) fn is_feature(self) -> bool {
+        matches!(self, Aov::Albedo | Aov::Normal)
+    }
+
+    /// Whether the buffer is filled by splitting the light of samples.
+    pub(crate) fn is_split(self) -> bool {
+        matches!(self, Aov::Direct | Aov::Indirect)
+    }
+
+    /// Visualizes the buffer in the output, if it was rendered.
+    ///
+    /// Depths are divided by the largest finite one, normals mapped from [-1, 1] to [0, 1] and
+    /// ids given arbitrary colors.
+    pub fn image(self, output: &RenderOutput) -> Option<Vec<Color>> {
+        let image: Vec<_> = match self {
+            Aov::Depth => {
+                let maximum = (output.depth.iter())
+                    .copied()
+                    .filter(|depth| depth.is_finite())
+                    .fold(0f32, f32::max);
+                (output.depth.iter())
+                    .map(|&depth| Color::grey((depth / maximum).min(1.)))
+                    .collect()
+            }
+            Aov::Normal => (output.normals.iter())
+                .map(|n| Color::new(0.5 + 0.5 * n.x, 0.5 + 0.5 * n.y, 0.5 + 0.5 * n.z))
+                .collect(),
+            Aov::Albedo => output.albedo.clone(),
+            Aov::MaterialId => (output.material_ids.iter())
+                .map(|id| id.map_or(Color::BLACK, |id| id_color(id as u64)))
+                .collect(),
+            Aov::ObjectId => (output.object_ids.iter())
+                .map(|id| id.map_or(Color::BLACK, |id| id_color(id.to_bits())))
+                .collect(),
+            Aov::Direct => output.direct.clone(),
+            Aov::Indirect => output.indirect.clone(),
+        };
+        (!image.is_empty()).then_some(image)
+    }
+}
+
+/// Object of the scene, by its position in its collection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ObjectId {
+    Sphere(usize),
+    Plane(usize),
+    Sdf(usize),
+    Medium(usize),
+    VoxelMedium(usize),
+    Fog,
+}
+
+impl ObjectId {
+    /// Kind of object in the upper 32 bits and index in the lower ones.
+    pub(crate) fn to_bits(self) -> u64 {
+        let (kind, index) = match self {
+            ObjectId::Sphere(i) => (0, i),
+            ObjectId::Plane(i) => (1, i),
+            ObjectId::Sdf(i) => (2, i),
+            ObjectId::Medium(i) => (3, i),
+            ObjectId::VoxelMedium(i) => (4, i),
+            ObjectId::Fog => (5, 0),
+        };
+        (kind << 32) | index as u64
+    }
+
+    pub(crate) fn from_bits(bits: u64) -> Option<ObjectId> {
+        let index = (bits & 0xffff_ffff) as usize;
+        match bits >> 32 {
+            0 => Some(ObjectId::Sphere(index)),
+            1 => Some(ObjectId::Plane(index)),
+            2 => Some(ObjectId::Sdf(index)),
+            3 => Some(ObjectId::Medium(index)),
+            4 => Some(ObjectId::VoxelMedium(index)),
+            5 => Some(ObjectId::Fog),
+            _ => None,
+        }
+    }
+}
+
+/// Arbitrary bright color, different for close ids.
+fn id_color(id: u64) -> Color {
+    // SplitMix64 finalizer
+    let mut x = id.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+    let channel = |shift: u32| 0.2 + 0.8 * ((x >> shift) & 0xff) as f32 / 255.;
+    Color::new(channel(0), channel(8), channel(16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{
+        Background, Camera, Canvas, Colorer, Degrees, Material, SamplerKind, Sphere,
+    };
+    use crate::types::Point;
+    use crate::{render_scene, RenderSettings, Scene};
+
+    #[test]
+    fn buffers_match_the_scene() {
+        let scene = Scene {
+            spheres: vec![Sphere {
+                center: Point::new(0., 2., 0.05),
+                radius: 0.5,
+                material: Material::Diffuse(Colorer::Solid(Color::new(0.8, 0.4, 0.2))),
+            }],
+            background: Background {
+                material: Material::light(Colorer::Solid(Color::grey(0.5))),
+            },
+            ..Default::default()
+        };
+        let canvas = Canvas {
+            width: 16,
+            height: 8,
+        };
+        let camera = Camera::from_canvas(&canvas, Point::new(0., 0., 0.05), Degrees::new(90.));
+        let settings = RenderSettings {
+            samples_per_pixel: 4,
+            maximum_bounces: 4,
+            aovs: Aov::ALL.to_vec(),
+            ..Default::default()
+        };
+        let mut pixels = vec![Color::BLACK; canvas.width * canvas.height];
+        let output = render_scene(&mut pixels, &scene, &canvas, &camera, &settings, 0..8);
+
+        let center = 4 * canvas.width + 8;
+        assert_eq!(output.object_ids[center], Some(ObjectId::Sphere(0)));
+        assert_eq!(output.object_ids[0], None);
+        assert!(
+            (output.depth[center] - 1.5).abs() < 0.05,
+            "{}",
+            output.depth[center]
+        );
+        assert!(output.depth[0].is_infinite());
+        assert_eq!(output.albedo[center], Color::new(0.8, 0.4, 0.2));
+        assert!(
+            output.normals[center].y < -0.9,
+            "{:?}",
+            output.normals[center]
+        );
+
+        // the background is seen directly, and lights the sphere directly as well, which cannot
+        // bounce light onto itself
+        assert_eq!(output.direct[0], Color::grey(0.5));
+        assert_eq!(output.indirect[0], Color::BLACK);
+        assert!(
+            output.direct[center].luminance() > 0.1,
+            "{:?}",
+            output.direct[center]
+        );
+        assert_eq!(output.indirect[center], Color::BLACK);
+        for (i, pixel) in pixels.iter().enumerate() {
+            let sum = output.direct[i].clone() + output.indirect[i].clone();
+            assert!((sum.luminance() - pixel.luminance()).abs() < 1e-4);
+        }
+        assert!(Aov::ALL.iter().all(|aov| aov.image(&output).is_some()));
+    }
+
+    const BEHIND_GLASS: Color = Color {
+        r: 0.8,
+        g: 0.4,
+        b: 0.2,
+    };
+
+    /// Diffuse sphere seen through a glass sphere, straight ahead of the camera.
+    fn glass_scene() -> Scene {
+        Scene {
+            spheres: vec![
+                Sphere {
+                    center: Point::new(0., 2., 0.05),
+                    radius: 0.5,
+                    material: Material::Dielectric {
+                        refraction_index: 0.67,
+                        colorer: Colorer::Solid(Color::WHITE),
+                        absorption: Color::BLACK,
+                    },
+                },
+                Sphere {
+                    center: Point::new(0., 6., 0.05),
+                    radius: 2.,
+                    material: Material::Diffuse(Colorer::Solid(BEHIND_GLASS)),
+                },
+            ],
+            background: Background {
+                material: Material::light(Colorer::Solid(Color::grey(0.8))),
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn feature_buffers_see_through_glass() {
+        let diffuse = BEHIND_GLASS;
+        let scene = glass_scene();
+        let canvas = Canvas {
+            width: 16,
+            height: 8,
+        };
+        let camera = Camera::from_canvas(&canvas, Point::new(0., 0., 0.05), Degrees::new(90.));
+        let settings = RenderSettings {
+            samples_per_pixel: 16,
+            maximum_bounces: 4,
+            feature_buffers: true,
+            aovs: vec![Aov::MaterialId],
+            ..Default::default()
+        };
+        let mut pixels = vec![Color::BLACK; canvas.width * canvas.height];
+        let output = render_scene(&mut pixels, &scene, &canvas, &camera, &settings, 0..8);
+
+        // the glass is seen first, but the guides show the diffuse sphere behind it, apart from
+        // the few samples reflected towards the background
+        let center = 4 * canvas.width + 8;
+        assert_eq!(output.material_ids[center], Some(2));
+        let albedo = &output.albedo[center];
+        assert!((albedo.r - diffuse.r).abs() < 0.1, "{:?}", albedo);
+        assert!((albedo.b - diffuse.b).abs() < 0.2, "{:?}", albedo);
+        assert!(
+            output.normals[center].y < -0.7,
+            "{:?}",
+            output.normals[center]
+        );
+        assert!(output.depth.is_empty() && output.direct.is_empty());
+    }
+
+    #[test]
+    fn buffers_leave_colors_unchanged() {
+        let scene = glass_scene();
+        let canvas = Canvas {
+            width: 8,
+            height: 4,
+        };
+        let camera = Camera::from_canvas(&canvas, Point::new(0., 0., 0.05), Degrees::new(90.));
+        for sampler in [SamplerKind::Independent, SamplerKind::Sobol] {
+            let render = |feature_buffers, aovs| {
+                let settings = RenderSettings {
+                    samples_per_pixel: 4,
+                    maximum_bounces: 4,
+                    sampler,
+                    feature_buffers,
+                    aovs,
+                    ..Default::default()
+                };
+                let mut pixels = vec![Color::BLACK; canvas.width * canvas.height];
+                render_scene(&mut pixels, &scene, &canvas, &camera, &settings, 0..4);
+                pixels
+            };
+            let plain = render(false, Vec::new());
+            let with_buffers = render(true, Aov::ALL.to_vec());
+            for (a, b) in plain.iter().zip(&with_buffers) {
+                // rows are merged in any order, which only rounds differently
+                assert!(
+                    (a.luminance() - b.luminance()).abs() < 1e-4,
+                    "{:?} {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+}
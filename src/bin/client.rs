@@ -2,28 +2,27 @@ use std::{fs::File, io::BufWriter};
 
 use keyell::{
     net::{render_scene_distributed, Remote},
-    render::{Background, Camera, Canvas, Color, Colorer, Material, Sphere},
+    render::{Background, Camera, Canvas, Color, Colorer, Material, SamplerKind, Sphere},
     types::Point,
-    Scene,
+    RenderSettings, Scene,
 };
 
 fn main() -> std::io::Result<()> {
     let mut scene = Scene {
-        spheres: Vec::new(),
-        planes: Vec::new(),
         background: Background {
-            material: Material::Light(Colorer::ZGradient {
+            material: Material::light(Colorer::ZGradient {
                 bottom: Color::WHITE,
                 top: Color::new(0.4, 0.3, 0.8),
             }),
         },
+        ..Default::default()
     };
 
     for i in 0..11 {
         scene.spheres.push(Sphere {
             center: Point::new(-1. + (2. * i as f32 / 10.), 1., 0.),
             radius: 0.1,
-            material: Material::Light(Colorer::Solid(Color::new(0.4, 0.6, 0.9))),
+            material: Material::light(Colorer::Solid(Color::new(0.4, 0.6, 0.9))),
         });
     }
 
@@ -53,8 +52,12 @@ fn main() -> std::io::Result<()> {
         &scene,
         &canvas,
         &camera,
-        100,
-        10,
+        &RenderSettings {
+            samples_per_pixel: 100,
+            maximum_bounces: 10,
+            sampler: SamplerKind::Sobol,
+            ..Default::default()
+        },
     );
 
     let mut writer =
@@ -1,43 +1,100 @@
 use std::{
-    f32::{INFINITY, MIN_POSITIVE},
     fs::{File, OpenOptions},
     io::{BufReader, BufWriter},
+    ops::RangeInclusive,
     sync::Arc,
 };
 
 use eframe::egui;
 use keyell::{
+    aov::{Aov, ObjectId},
+    integrator::{IntegratorKind, Preprocessed},
     net::Remote,
-    render::{Background, Color, Colorer, Hittable, Material, Plane, Ray, Sphere},
+    postprocess::Denoiser,
+    render::{
+        Background, Bump, Camera, Color, Colorer, ComplexIor, Filter, Fog, Light, Material, Plane,
+        Principled, SamplerKind, Sky, Sphere, Texture, Wrap,
+    },
     types::{Normal, Point, Vec3},
-    Scene,
+    AdaptiveSettings, RenderSettings, Scene,
 };
 
 #[derive(PartialEq)]
 enum Object {
     Sphere(usize),
     Plane(usize),
+    Light(usize),
 }
 
-fn get_hit_object(scene: &Scene, ray: &Ray) -> Option<Object> {
-    let mut hit_object = None;
-    let mut closest_travel = INFINITY;
+/// Distance in pixels from a light gizmo within which clicks select the light.
+const GIZMO_RADIUS: f32 = 8.;
 
-    for (i, sphere) in scene.spheres.iter().enumerate() {
-        if let Some(hit) = sphere.hit(ray, MIN_POSITIVE, closest_travel) {
-            hit_object = Some(Object::Sphere(i));
-            closest_travel = hit.travel;
-        }
+fn light_position_mut(light: &mut Light) -> Option<&mut Point> {
+    match light {
+        Light::Point { position, .. } | Light::Spot { position, .. } => Some(position),
+        Light::Directional { .. } => None,
     }
+}
+
+/// Position of `point` on the preview image, along with its distance along the camera ray.
+fn project_to_preview(
+    camera: &Camera,
+    canvas: &keyell::render::Canvas,
+    rect: egui::Rect,
+    point: &Point,
+) -> Option<(egui::Pos2, f32)> {
+    let (u, v, travel) = camera.project(point)?;
+    let pos = egui::pos2(
+        rect.min.x + u * canvas.width as f32,
+        rect.min.y + (1. - v) * canvas.height as f32,
+    );
+    Some((pos, travel))
+}
+
+fn get_light_gizmo(
+    scene: &Scene,
+    camera: &Camera,
+    canvas: &keyell::render::Canvas,
+    rect: egui::Rect,
+    pos: egui::Pos2,
+) -> Option<Object> {
+    scene.lights.iter().enumerate().find_map(|(i, light)| {
+        let (gizmo, _) = project_to_preview(camera, canvas, rect, light.position()?)?;
+        (gizmo.distance(pos) <= GIZMO_RADIUS).then_some(Object::Light(i))
+    })
+}
 
-    for (i, plane) in scene.planes.iter().enumerate() {
-        if let Some(hit) = plane.hit(ray, 0.001, closest_travel) {
-            hit_object = Some(Object::Plane(i));
-            closest_travel = hit.travel;
+fn draw_light_gizmos(
+    painter: &egui::Painter,
+    scene: &Scene,
+    camera: &Camera,
+    canvas: &keyell::render::Canvas,
+    rect: egui::Rect,
+    selected_object: &Option<Object>,
+) {
+    for (i, light) in scene.lights.iter().enumerate() {
+        let position = match light.position() {
+            Some(position) => position,
+            None => continue,
+        };
+        let (pos, _) = match project_to_preview(camera, canvas, rect, position) {
+            Some(projected) => projected,
+            None => continue,
+        };
+        let color = if *selected_object == Some(Object::Light(i)) {
+            egui::Color32::WHITE
+        } else {
+            egui::Color32::YELLOW
+        };
+        let stroke = egui::Stroke::new(2., color);
+        painter.circle_stroke(pos, GIZMO_RADIUS / 2., stroke);
+        if let (Light::Spot { .. }, Some(direction)) = (light, light.direction()) {
+            let target = position + 0.1 * direction;
+            if let Some((target, _)) = project_to_preview(camera, canvas, rect, &target) {
+                painter.line_segment([pos, target], stroke);
+            }
         }
     }
-
-    hit_object
 }
 
 #[derive(Debug, PartialEq)]
@@ -46,6 +103,11 @@ enum MaterialType {
     Metal,
     Dialectric,
     Light,
+    Isotropic,
+    HenyeyGreenstein,
+    Conductor,
+    RoughDielectric,
+    Principled,
 }
 
 impl MaterialType {
@@ -56,8 +118,25 @@ impl MaterialType {
             MaterialType::Dialectric => Material::Dielectric {
                 refraction_index: 0.8,
                 colorer,
+                absorption: Color::BLACK,
             },
-            MaterialType::Light => Material::Light(colorer),
+            MaterialType::Light => Material::light(colorer),
+            MaterialType::Isotropic => Material::Isotropic(colorer),
+            MaterialType::HenyeyGreenstein => Material::HenyeyGreenstein {
+                colorer,
+                asymmetry: 0.5,
+            },
+            MaterialType::Conductor => Material::Conductor {
+                colorer,
+                roughness: 0.3,
+                ior: ComplexIor::GOLD,
+            },
+            MaterialType::RoughDielectric => Material::RoughDielectric {
+                refraction_index: 0.8,
+                roughness: 0.3,
+                colorer,
+            },
+            MaterialType::Principled => Material::Principled(Box::new(Principled::new(colorer))),
         }
     }
 }
@@ -68,7 +147,13 @@ impl From<&Material> for MaterialType {
             Material::Diffuse(_) => Self::Diffuse,
             Material::Metal { .. } => Self::Metal,
             Material::Dielectric { .. } => Self::Dialectric,
-            Material::Light(_) => Self::Light,
+            Material::Light { .. } => Self::Light,
+            Material::Isotropic(_) => Self::Isotropic,
+            Material::HenyeyGreenstein { .. } => Self::HenyeyGreenstein,
+            Material::Conductor { .. } => Self::Conductor,
+            Material::RoughDielectric { .. } => Self::RoughDielectric,
+            Material::Principled(_) => Self::Principled,
+            Material::Bumped { material, .. } => Self::from(material.as_ref()),
         }
     }
 }
@@ -78,18 +163,57 @@ enum ColorerType {
     ZGradient,
     Solid,
     Bubblegum,
+    Checker,
+    Noise,
+    Marble,
+    Wood,
+    Image,
+    Environment,
+    Sky,
 }
 
 impl ColorerType {
-    fn to_colorer(&self, previous_color: Color) -> Colorer {
-        match self {
+    /// Returns `None` for colorers that can't be created from scratch, like images which need
+    /// to be loaded from a file and environment maps which need to be loaded from a scene file.
+    fn to_colorer(&self, previous_color: Color) -> Option<Colorer> {
+        let colorer = match self {
             ColorerType::ZGradient => Colorer::ZGradient {
                 top: previous_color,
                 bottom: Color::WHITE,
             },
             ColorerType::Solid => Colorer::Solid(previous_color),
             ColorerType::Bubblegum => Colorer::Bubblegum,
-        }
+            ColorerType::Checker => Colorer::Checker {
+                even: previous_color,
+                odd: Color::WHITE,
+                scale: 0.1,
+            },
+            ColorerType::Noise => Colorer::Noise {
+                low: Color::BLACK,
+                high: previous_color,
+                scale: 0.1,
+                octaves: 4,
+            },
+            ColorerType::Marble => Colorer::Marble {
+                base: previous_color,
+                vein: Color::BLACK,
+                scale: 0.1,
+                turbulence: 0.5,
+            },
+            ColorerType::Wood => Colorer::Wood {
+                light: previous_color,
+                dark: Color::new(0.3, 0.15, 0.05),
+                scale: 0.02,
+                turbulence: 0.5,
+            },
+            ColorerType::Sky => Colorer::Sky(Sky {
+                elevation: 30.,
+                azimuth: 45.,
+                turbidity: 3.,
+            }),
+            ColorerType::Image | ColorerType::Environment => return None,
+        };
+        Some(colorer)
     }
 }
 
@@ -99,12 +223,40 @@ impl From<&Colorer> for ColorerType {
             Colorer::ZGradient { .. } => ColorerType::ZGradient,
             Colorer::Solid(_) => ColorerType::Solid,
             Colorer::Bubblegum => ColorerType::Bubblegum,
+            Colorer::Checker { .. } => ColorerType::Checker,
+            Colorer::Noise { .. } => ColorerType::Noise,
+            Colorer::Marble { .. } => ColorerType::Marble,
+            Colorer::Wood { .. } => ColorerType::Wood,
+            Colorer::Image { .. } => ColorerType::Image,
+            Colorer::Environment(_) => ColorerType::Environment,
+            Colorer::Sky(_) => ColorerType::Sky,
         }
     }
 }
 
+fn show_color_settings(ui: &mut egui::Ui, color: &mut Color) -> bool {
+    let mut rgb = [color.r, color.g, color.b];
+    let changed = egui::color_picker::color_edit_button_rgb(ui, &mut rgb).changed();
+    color.r = rgb[0];
+    color.g = rgb[1];
+    color.b = rgb[2];
+    changed
+}
+
+/// Path of the image to load into an image colorer, kept while it has not been loaded.
+#[derive(Clone, Default)]
+struct ImagePath {
+    path: String,
+    error: Option<String>,
+}
+
 fn show_colorer_settings(ui: &mut egui::Ui, colorer: &mut Colorer) -> bool {
-    let mut colorer_type = ColorerType::from(colorer as &_);
+    let image_id = egui::Id::new(colorer as *const Colorer);
+    let mut image_path: Option<ImagePath> = ui.data_mut(|data| data.get_temp(image_id));
+    let mut colorer_type = match image_path {
+        Some(_) => ColorerType::Image,
+        None => ColorerType::from(colorer as &_),
+    };
     let mut changed = false;
     egui::ComboBox::new(colorer as *const _, "colorer")
         .selected_text(format!("{:?}", colorer_type))
@@ -118,48 +270,201 @@ fn show_colorer_settings(ui: &mut egui::Ui, colorer: &mut Colorer) -> bool {
             changed |= ui
                 .selectable_value(&mut colorer_type, ColorerType::ZGradient, "ZGradient")
                 .changed();
+            changed |= ui
+                .selectable_value(&mut colorer_type, ColorerType::Checker, "Checker")
+                .changed();
+            changed |= ui
+                .selectable_value(&mut colorer_type, ColorerType::Noise, "Noise")
+                .changed();
+            changed |= ui
+                .selectable_value(&mut colorer_type, ColorerType::Marble, "Marble")
+                .changed();
+            changed |= ui
+                .selectable_value(&mut colorer_type, ColorerType::Wood, "Wood")
+                .changed();
+            changed |= ui
+                .selectable_value(&mut colorer_type, ColorerType::Sky, "Sky")
+                .changed();
+            changed |= ui
+                .selectable_value(&mut colorer_type, ColorerType::Image, "Image")
+                .changed();
 
             if changed {
+                // images are only set once they are loaded
+                image_path = (colorer_type == ColorerType::Image).then(ImagePath::default);
+                changed = false;
                 let previous_color = match colorer {
                     Colorer::ZGradient { top, .. } => top.clone(),
                     Colorer::Solid(c) => c.clone(),
                     Colorer::Bubblegum => Color::random(),
+                    Colorer::Checker { even: c, .. }
+                    | Colorer::Noise { high: c, .. }
+                    | Colorer::Marble { base: c, .. }
+                    | Colorer::Wood { light: c, .. } => c.clone(),
+                    Colorer::Image { .. } | Colorer::Environment(_) | Colorer::Sky(_) => {
+                        Color::random()
+                    }
                 };
-                *colorer = colorer_type.to_colorer(previous_color);
+                if let Some(c) = colorer_type.to_colorer(previous_color) {
+                    *colorer = c;
+                    changed = true;
+                }
             }
         });
 
+    if let Some(mut pending) = image_path {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut pending.path);
+            if ui.button("Load").clicked() {
+                match Texture::load(&pending.path) {
+                    Ok(texture) => {
+                        *colorer = Colorer::Image {
+                            texture,
+                            wrap: Wrap::Repeat,
+                        };
+                        changed = true;
+                    }
+                    Err(e) => pending.error = Some(e.to_string()),
+                }
+            }
+        });
+        if let Some(error) = &pending.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+        ui.data_mut(|data| {
+            if changed {
+                data.remove::<ImagePath>(image_id);
+            } else {
+                data.insert_temp(image_id, pending);
+            }
+        });
+    } else {
+        ui.data_mut(|data| data.remove::<ImagePath>(image_id));
+    }
+
     match colorer {
         Colorer::ZGradient { bottom, top } => {
-            let mut top_rgb = [top.r, top.g, top.b];
-            changed |= egui::color_picker::color_edit_button_rgb(ui, &mut top_rgb).changed();
-            top.r = top_rgb[0];
-            top.g = top_rgb[1];
-            top.b = top_rgb[2];
-            let mut bottom_rgb = [bottom.r, bottom.g, bottom.b];
-            changed |= egui::color_picker::color_edit_button_rgb(ui, &mut bottom_rgb).changed();
-            bottom.r = bottom_rgb[0];
-            bottom.g = bottom_rgb[1];
-            bottom.b = bottom_rgb[2];
-        }
-        Colorer::Solid(ref mut color) => {
-            let mut rgb = [color.r, color.g, color.b];
-            changed |= egui::color_picker::color_edit_button_rgb(ui, &mut rgb).changed();
-            color.r = rgb[0];
-            color.g = rgb[1];
-            color.b = rgb[2];
+            changed |= show_color_settings(ui, top);
+            changed |= show_color_settings(ui, bottom);
         }
+        Colorer::Solid(ref mut color) => changed |= show_color_settings(ui, color),
         Colorer::Bubblegum => {}
+        Colorer::Checker { even, odd, scale } => {
+            changed |= show_color_settings(ui, even);
+            changed |= show_color_settings(ui, odd);
+            changed |= ui
+                .add(egui::Slider::new(scale, (0.01)..=1.).text("scale"))
+                .changed();
+        }
+        Colorer::Noise {
+            low,
+            high,
+            scale,
+            octaves,
+        } => {
+            changed |= show_color_settings(ui, low);
+            changed |= show_color_settings(ui, high);
+            changed |= ui
+                .add(egui::Slider::new(scale, (0.01)..=1.).text("scale"))
+                .changed();
+            changed |= ui
+                .add(egui::Slider::new(octaves, 1..=8).text("octaves"))
+                .changed();
+        }
+        Colorer::Marble {
+            base: first,
+            vein: second,
+            scale,
+            turbulence,
+        }
+        | Colorer::Wood {
+            light: first,
+            dark: second,
+            scale,
+            turbulence,
+        } => {
+            changed |= show_color_settings(ui, first);
+            changed |= show_color_settings(ui, second);
+            changed |= ui
+                .add(egui::Slider::new(scale, (0.01)..=1.).text("scale"))
+                .changed();
+            changed |= ui
+                .add(egui::Slider::new(turbulence, (0.)..=2.).text("turbulence"))
+                .changed();
+        }
+        Colorer::Image { texture, wrap } => {
+            ui.label(texture.path());
+            egui::ComboBox::new(wrap as *const _, "wrap")
+                .selected_text(format!("{:?}", wrap))
+                .show_ui(ui, |ui| {
+                    for mode in [Wrap::Repeat, Wrap::Mirror, Wrap::Clamp] {
+                        let text = format!("{:?}", mode);
+                        changed |= ui.selectable_value(wrap, mode, text).changed();
+                    }
+                });
+        }
+        Colorer::Environment(environment) => {
+            ui.label(environment.texture().path());
+            changed |= ui
+                .add(egui::Slider::new(&mut environment.rotation, (0.)..=360.).text("rotation"))
+                .changed();
+        }
+        Colorer::Sky(sky) => {
+            changed |= ui
+                .add(egui::Slider::new(&mut sky.elevation, (0.)..=90.).text("sun elevation"))
+                .changed();
+            changed |= ui
+                .add(egui::Slider::new(&mut sky.azimuth, (0.)..=360.).text("sun azimuth"))
+                .changed();
+            changed |= ui
+                .add(egui::Slider::new(&mut sky.turbidity, (2.)..=10.).text("turbidity"))
+                .changed();
+        }
     }
 
     changed
 }
 
 fn show_background_settings(ui: &mut egui::Ui, background: &mut Background) -> bool {
-    let Material::Light(colorer) = &mut background.material else {
+    let Material::Light {
+        colorer, intensity, ..
+    } = &mut background.material
+    else {
         panic!("Expected background material to be a Light");
     };
-    show_colorer_settings(ui, colorer)
+    show_colorer_settings(ui, colorer) | show_intensity_settings(ui, intensity)
+}
+
+fn show_intensity_settings(ui: &mut egui::Ui, intensity: &mut f32) -> bool {
+    ui.add(
+        egui::Slider::new(intensity, (0.)..=100.)
+            .logarithmic(true)
+            .text("intensity"),
+    )
+    .changed()
+}
+
+fn show_fog_settings(ui: &mut egui::Ui, fog: &mut Option<Fog>) -> bool {
+    let mut enabled = fog.is_some();
+    let mut changed = ui.checkbox(&mut enabled, "enabled").changed();
+    if changed {
+        *fog = enabled.then_some(Fog {
+            density: 0.5,
+            extent: 10.,
+            material: Material::Isotropic(Colorer::Solid(Color::WHITE)),
+        });
+    }
+
+    if let Some(fog) = fog {
+        changed |= show_material_settings(ui, &mut fog.material);
+        changed |= ui
+            .add(egui::Slider::new(&mut fog.density, (0.)..=5.).text("density"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut fog.extent, (0.)..=100.).text("extent"))
+            .changed();
+    }
+    changed
 }
 
 fn show_point_settings(ui: &mut egui::Ui, point: &mut Point) -> bool {
@@ -201,7 +506,75 @@ fn show_normal_settings(ui: &mut egui::Ui, normal: &mut Normal) -> bool {
     changed
 }
 
+fn show_direction_settings(ui: &mut egui::Ui, direction: &mut Vec3) -> bool {
+    let previous = direction.clone();
+    let mut changed = false;
+    changed |= ui
+        .add(egui::Slider::new(&mut direction.x, (-1.)..=1.).text("x"))
+        .changed();
+    changed |= ui
+        .add(egui::Slider::new(&mut direction.y, (-1.)..=1.).text("y"))
+        .changed();
+    changed |= ui
+        .add(egui::Slider::new(&mut direction.z, (-1.)..=1.).text("z"))
+        .changed();
+    if direction.len() == 0. {
+        // directions can't be normalized without a length
+        *direction = previous;
+        changed = false;
+    }
+    changed
+}
+
+fn show_bump_settings(ui: &mut egui::Ui, bump: &mut Bump) -> bool {
+    let mut changed = false;
+    match bump {
+        Bump::NormalMap {
+            texture, strength, ..
+        } => {
+            ui.label(texture.path());
+            changed |= ui
+                .add(egui::Slider::new(strength, (0.)..=2.).text("strength"))
+                .changed();
+        }
+        Bump::Noise { scale, strength } => {
+            changed |= ui
+                .add(egui::Slider::new(scale, (0.01)..=1.).text("bump scale"))
+                .changed();
+            changed |= ui
+                .add(egui::Slider::new(strength, (0.)..=2.).text("bump strength"))
+                .changed();
+        }
+    }
+    changed
+}
+
+/// Material settings with the option of bump mapping the surface.
+fn show_surface_material_settings(ui: &mut egui::Ui, material: &mut Material) -> bool {
+    let mut bumped = matches!(material, Material::Bumped { .. });
+    let mut changed = ui.checkbox(&mut bumped, "bump").changed();
+    if changed {
+        let previous = std::mem::replace(material, Material::Diffuse(Colorer::Bubblegum));
+        *material = match previous {
+            Material::Bumped { material, .. } => *material,
+            m => Material::Bumped {
+                material: Box::new(m),
+                bump: Bump::Noise {
+                    scale: 0.05,
+                    strength: 0.5,
+                },
+            },
+        };
+    }
+    changed |= show_material_settings(ui, material);
+    changed
+}
+
 fn show_material_settings(ui: &mut egui::Ui, material: &mut Material) -> bool {
+    if let Material::Bumped { material, bump } = material {
+        return show_material_settings(ui, material) | show_bump_settings(ui, bump);
+    }
+
     let mut material_type = MaterialType::from(material as &_);
     let mut changed = false;
     egui::ComboBox::new(material as *const _, "material")
@@ -219,15 +592,60 @@ fn show_material_settings(ui: &mut egui::Ui, material: &mut Material) -> bool {
             changed |= ui
                 .selectable_value(&mut material_type, MaterialType::Dialectric, "Dialectric")
                 .changed();
+            changed |= ui
+                .selectable_value(&mut material_type, MaterialType::Isotropic, "Isotropic")
+                .changed();
+            changed |= ui
+                .selectable_value(
+                    &mut material_type,
+                    MaterialType::HenyeyGreenstein,
+                    "HenyeyGreenstein",
+                )
+                .changed();
+            changed |= ui
+                .selectable_value(&mut material_type, MaterialType::Conductor, "Conductor")
+                .changed();
+            changed |= ui
+                .selectable_value(
+                    &mut material_type,
+                    MaterialType::RoughDielectric,
+                    "RoughDielectric",
+                )
+                .changed();
+            changed |= ui
+                .selectable_value(&mut material_type, MaterialType::Principled, "Principled")
+                .changed();
             if changed {
                 *material = material_type.to_material(material.get_colorer());
             }
         });
 
     match material {
-        Material::Diffuse(ref mut colorer) | Material::Light(ref mut colorer) => {
+        Material::Diffuse(ref mut colorer) | Material::Isotropic(ref mut colorer) => {
             changed |= show_colorer_settings(ui, colorer)
         }
+        Material::Light {
+            ref mut colorer,
+            ref mut intensity,
+            ref mut two_sided,
+            ref mut temperature,
+        } => {
+            let mut blackbody = temperature.is_some();
+            if ui.checkbox(&mut blackbody, "blackbody").changed() {
+                *temperature = blackbody.then_some(6500.);
+                changed = true;
+            }
+            match temperature {
+                Some(temperature) => {
+                    changed |= ui
+                        .add(egui::Slider::new(temperature, (1000.)..=12000.).text("temperature"))
+                        .changed();
+                }
+                None => changed |= show_colorer_settings(ui, colorer),
+            }
+            changed |= show_intensity_settings(ui, intensity);
+            changed |= ui.checkbox(two_sided, "two-sided").changed();
+        }
         Material::Metal {
             ref mut colorer,
             ref mut fuzz,
@@ -240,16 +658,130 @@ fn show_material_settings(ui: &mut egui::Ui, material: &mut Material) -> bool {
         Material::Dielectric {
             ref mut refraction_index,
             ref mut colorer,
+            ref mut absorption,
         } => {
             changed |= show_colorer_settings(ui, colorer);
             changed |= ui
                 .add(egui::Slider::new(refraction_index, (0.)..=2.).text("refraction index"))
                 .changed();
+            ui.label("Absorption");
+            changed |= show_absorption_settings(ui, absorption);
+        }
+        Material::HenyeyGreenstein {
+            ref mut colorer,
+            ref mut asymmetry,
+        } => {
+            changed |= show_colorer_settings(ui, colorer);
+            changed |= ui
+                .add(egui::Slider::new(asymmetry, (-0.99)..=0.99).text("asymmetry"))
+                .changed();
+        }
+        Material::Conductor {
+            ref mut colorer,
+            ref mut roughness,
+            ref mut ior,
+        } => {
+            changed |= show_colorer_settings(ui, colorer);
+            changed |= ui
+                .add(egui::Slider::new(roughness, (0.)..=1.).text("roughness"))
+                .changed();
+            changed |= show_ior_settings(ui, ior);
         }
+        Material::RoughDielectric {
+            ref mut refraction_index,
+            ref mut roughness,
+            ref mut colorer,
+        } => {
+            changed |= show_colorer_settings(ui, colorer);
+            changed |= ui
+                .add(egui::Slider::new(refraction_index, (0.)..=2.).text("refraction index"))
+                .changed();
+            changed |= ui
+                .add(egui::Slider::new(roughness, (0.)..=1.).text("roughness"))
+                .changed();
+        }
+        Material::Principled(ref mut principled) => {
+            changed |= show_principled_settings(ui, principled)
+        }
+        // handled above
+        Material::Bumped { .. } => {}
     };
     changed
 }
 
+fn show_absorption_settings(ui: &mut egui::Ui, absorption: &mut Color) -> bool {
+    let mut changed = false;
+    changed |= ui
+        .add(egui::Slider::new(&mut absorption.r, (0.)..=20.).text("r"))
+        .changed();
+    changed |= ui
+        .add(egui::Slider::new(&mut absorption.g, (0.)..=20.).text("g"))
+        .changed();
+    changed |= ui
+        .add(egui::Slider::new(&mut absorption.b, (0.)..=20.).text("b"))
+        .changed();
+    changed
+}
+
+fn show_principled_settings(ui: &mut egui::Ui, principled: &mut Principled) -> bool {
+    let Principled {
+        base_color,
+        metallic,
+        roughness,
+        specular,
+        clearcoat,
+        sheen,
+        transmission,
+        refraction_index,
+    } = principled;
+
+    let mut changed = false;
+    let parameters = [
+        ("base color", base_color),
+        ("metallic", metallic),
+        ("roughness", roughness),
+        ("specular", specular),
+        ("clearcoat", clearcoat),
+        ("sheen", sheen),
+        ("transmission", transmission),
+    ];
+    for (name, colorer) in parameters {
+        egui::CollapsingHeader::new(name)
+            .id_source(colorer as *const _)
+            .show(ui, |ui| changed |= show_colorer_settings(ui, colorer));
+    }
+    changed |= ui
+        .add(egui::Slider::new(refraction_index, (0.)..=2.).text("refraction index"))
+        .changed();
+    changed
+}
+
+fn show_ior_settings(ui: &mut egui::Ui, ior: &mut ComplexIor) -> bool {
+    let presets = [
+        ("Gold", ComplexIor::GOLD),
+        ("Copper", ComplexIor::COPPER),
+        ("Aluminum", ComplexIor::ALUMINUM),
+        ("Silver", ComplexIor::SILVER),
+    ];
+    let selected = presets
+        .iter()
+        .find(|(_, preset)| preset == ior)
+        .map_or("Custom", |(name, _)| name);
+
+    let mut changed = false;
+    egui::ComboBox::new(ior as *const _, "metal")
+        .selected_text(selected)
+        .show_ui(ui, |ui| {
+            for (name, preset) in presets {
+                if ui.selectable_label(selected == name, name).clicked() {
+                    *ior = preset;
+                    changed = true;
+                }
+            }
+        });
+    changed
+}
+
 fn make_frame(ui: &egui::Ui, selected: bool) -> egui::Frame {
     let mut frame = egui::Frame::group(ui.style());
     if selected {
@@ -261,7 +793,7 @@ fn make_frame(ui: &egui::Ui, selected: bool) -> egui::Frame {
 fn show_plane_settings(ui: &mut egui::Ui, plane: &mut Plane, selected: bool) -> bool {
     let mut changed = false;
     make_frame(ui, selected).show(ui, |ui| {
-        changed |= show_material_settings(ui, &mut plane.material);
+        changed |= show_surface_material_settings(ui, &mut plane.material);
         ui.label("Point");
         changed |= show_point_settings(ui, &mut plane.point);
         ui.label("Normal");
@@ -273,7 +805,7 @@ fn show_plane_settings(ui: &mut egui::Ui, plane: &mut Plane, selected: bool) ->
 fn show_sphere_settings(ui: &mut egui::Ui, sphere: &mut Sphere, selected: bool) -> bool {
     let mut changed = false;
     make_frame(ui, selected).show(ui, |ui| {
-        changed |= show_material_settings(ui, &mut sphere.material);
+        changed |= show_surface_material_settings(ui, &mut sphere.material);
         changed |= show_point_settings(ui, &mut sphere.center);
         changed |= ui
             .add(egui::Slider::new(&mut sphere.radius, (0.01)..=0.3).text("radius"))
@@ -282,9 +814,332 @@ fn show_sphere_settings(ui: &mut egui::Ui, sphere: &mut Sphere, selected: bool)
     changed
 }
 
-struct PreviewState {
+fn show_light_settings(ui: &mut egui::Ui, light: &mut Light, selected: bool) -> bool {
+    let mut changed = false;
+    make_frame(ui, selected).show(ui, |ui| match light {
+        Light::Point {
+            position,
+            color,
+            intensity,
+        } => {
+            ui.label("Point light");
+            changed |= show_point_settings(ui, position);
+            changed |= show_color_settings(ui, color);
+            changed |= show_intensity_settings(ui, intensity);
+        }
+        Light::Spot {
+            position,
+            direction,
+            color,
+            intensity,
+            inner_angle,
+            outer_angle,
+        } => {
+            ui.label("Spot light");
+            changed |= show_point_settings(ui, position);
+            ui.label("Direction");
+            changed |= show_direction_settings(ui, direction);
+            changed |= show_color_settings(ui, color);
+            changed |= show_intensity_settings(ui, intensity);
+            changed |= ui
+                .add(egui::Slider::new(inner_angle, (0.)..=90.).text("inner angle"))
+                .changed();
+            changed |= ui
+                .add(egui::Slider::new(outer_angle, (0.)..=90.).text("outer angle"))
+                .changed();
+        }
+        Light::Directional {
+            direction,
+            color,
+            intensity,
+            angular_radius,
+        } => {
+            ui.label("Directional light");
+            ui.label("Direction");
+            changed |= show_direction_settings(ui, direction);
+            changed |= show_color_settings(ui, color);
+            changed |= show_intensity_settings(ui, intensity);
+            changed |= ui
+                .add(egui::Slider::new(angular_radius, (0.)..=10.).text("angular radius"))
+                .changed();
+        }
+    });
+    changed
+}
+
+fn show_integrator_settings(ui: &mut egui::Ui, integrator: &mut IntegratorKind) -> bool {
+    let mut changed = false;
+    egui::ComboBox::new(integrator as *const _, "integrator")
+        .selected_text(integrator.name())
+        .show_ui(ui, |ui| {
+            for default in IntegratorKind::ALL {
+                let selected =
+                    std::mem::discriminant(integrator) == std::mem::discriminant(&default);
+                if ui.selectable_label(selected, default.name()).clicked() && !selected {
+                    *integrator = default;
+                    changed = true;
+                }
+            }
+        });
+    match integrator {
+        IntegratorKind::AmbientOcclusion { distance } => {
+            changed |= ui
+                .add(egui::Slider::new(distance, (0.01)..=10.).text("occlusion distance"))
+                .changed();
+        }
+        IntegratorKind::PhotonMapping {
+            photons,
+            nearest,
+            radius,
+        } => {
+            changed |= ui
+                .add(
+                    egui::Slider::new(photons, 1000..=10_000_000)
+                        .logarithmic(true)
+                        .text("photons"),
+                )
+                .changed();
+            changed |= ui
+                .add(egui::Slider::new(nearest, 1..=500).text("nearest photons"))
+                .changed();
+            changed |= ui
+                .add(
+                    egui::Slider::new(radius, (0.001)..=1.)
+                        .logarithmic(true)
+                        .text("photon radius"),
+                )
+                .changed();
+        }
+        _ => {}
+    }
+    changed
+}
+
+fn show_sampler_settings(ui: &mut egui::Ui, sampler: &mut SamplerKind) -> bool {
+    let mut changed = false;
+    egui::ComboBox::new(sampler as *const _, "sampler")
+        .selected_text(format!("{:?}", sampler))
+        .show_ui(ui, |ui| {
+            for kind in [
+                SamplerKind::Independent,
+                SamplerKind::Stratified,
+                SamplerKind::Halton,
+                SamplerKind::Sobol,
+            ] {
+                let text = format!("{:?}", kind);
+                changed |= ui.selectable_value(sampler, kind, text).changed();
+            }
+        });
+    changed
+}
+
+fn show_filter_settings(ui: &mut egui::Ui, filter: &mut Filter) -> bool {
+    let name = |filter: &Filter| match filter {
+        Filter::Box { .. } => "Box",
+        Filter::Tent { .. } => "Tent",
+        Filter::Gaussian { .. } => "Gaussian",
+        Filter::Mitchell { .. } => "Mitchell",
+        Filter::Lanczos { .. } => "Lanczos",
+    };
+    let mut changed = false;
+    egui::ComboBox::new(filter as *const _, "filter")
+        .selected_text(name(filter))
+        .show_ui(ui, |ui| {
+            for default in [
+                Filter::default(),
+                Filter::Tent { radius: 1. },
+                Filter::Gaussian {
+                    radius: 1.5,
+                    sigma: 0.5,
+                },
+                Filter::Mitchell {
+                    radius: 2.,
+                    b: 1. / 3.,
+                    c: 1. / 3.,
+                },
+                Filter::Lanczos { radius: 2. },
+            ] {
+                let selected = std::mem::discriminant(filter) == std::mem::discriminant(&default);
+                if ui.selectable_label(selected, name(&default)).clicked() && !selected {
+                    *filter = default;
+                    changed = true;
+                }
+            }
+        });
+
+    let radius_slider = |radius| egui::Slider::new(radius, (0.5)..=4.).text("filter radius");
+    match filter {
+        Filter::Box { radius } | Filter::Tent { radius } | Filter::Lanczos { radius } => {
+            changed |= ui.add(radius_slider(radius)).changed();
+        }
+        Filter::Gaussian { radius, sigma } => {
+            changed |= ui.add(radius_slider(radius)).changed();
+            changed |= ui
+                .add(egui::Slider::new(sigma, (0.1)..=2.).text("sigma"))
+                .changed();
+        }
+        Filter::Mitchell { radius, b, c } => {
+            changed |= ui.add(radius_slider(radius)).changed();
+            changed |= ui.add(egui::Slider::new(b, (0.)..=1.).text("B")).changed();
+            changed |= ui.add(egui::Slider::new(c, (0.)..=1.).text("C")).changed();
+        }
+    }
+    changed
+}
+
+fn show_optional_slider(
+    ui: &mut egui::Ui,
+    value: &mut Option<f32>,
+    text: &str,
+    default: f32,
+    range: RangeInclusive<f32>,
+) -> bool {
+    let mut enabled = value.is_some();
+    let mut changed = ui.checkbox(&mut enabled, text).changed();
+    if changed {
+        *value = enabled.then_some(default);
+    }
+    if let Some(value) = value {
+        changed |= ui
+            .add(egui::Slider::new(value, range).logarithmic(true))
+            .changed();
+    }
+    changed
+}
+
+fn show_firefly_settings(
+    ui: &mut egui::Ui,
+    settings: &mut RenderSettings,
+    outlier_threshold: &mut Option<f32>,
+) -> bool {
+    let mut changed = show_optional_slider(
+        ui,
+        &mut settings.maximum_radiance,
+        "clamp samples",
+        10.,
+        (1.)..=100.,
+    );
+    changed |= show_optional_slider(
+        ui,
+        &mut settings.maximum_indirect_radiance,
+        "clamp indirect light",
+        3.,
+        (0.1)..=100.,
+    );
+    changed |= show_optional_slider(ui, outlier_threshold, "reject outliers", 4., (1.5)..=20.);
+    changed
+}
+
+fn show_denoiser_settings(ui: &mut egui::Ui, denoiser: &mut Option<Denoiser>) -> bool {
+    let mut enabled = denoiser.is_some();
+    let mut changed = ui.checkbox(&mut enabled, "denoise").changed();
+    if changed {
+        *denoiser = enabled.then(Denoiser::default);
+    }
+    if let Some(denoiser) = denoiser {
+        changed |= ui
+            .add(egui::Slider::new(&mut denoiser.radius, 1..=15).text("denoising radius"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut denoiser.spatial_sigma, (0.5)..=10.).text("spatial sigma"))
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut denoiser.color_sigma, (0.05)..=10.)
+                    .logarithmic(true)
+                    .text("color sigma"),
+            )
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut denoiser.albedo_sigma, (0.01)..=1.)
+                    .logarithmic(true)
+                    .text("albedo sigma"),
+            )
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut denoiser.normal_sigma, (0.01)..=1.)
+                    .logarithmic(true)
+                    .text("normal sigma"),
+            )
+            .changed();
+    }
+    changed
+}
+
+fn show_adaptive_settings(
+    ui: &mut egui::Ui,
+    adaptive: &mut Option<AdaptiveSettings>,
     samples_per_pixel: usize,
-    maximum_bounces: usize,
+) -> bool {
+    let mut enabled = adaptive.is_some();
+    let mut changed = ui.checkbox(&mut enabled, "adaptive sampling").changed();
+    if changed {
+        *adaptive = enabled.then_some(AdaptiveSettings {
+            noise_threshold: 0.02,
+            maximum_samples_per_pixel: 4 * samples_per_pixel,
+        });
+    }
+    if let Some(adaptive) = adaptive {
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut adaptive.noise_threshold, (0.001)..=0.2)
+                    .logarithmic(true)
+                    .text("noise threshold"),
+            )
+            .changed();
+        ui.horizontal(|ui| {
+            changed |= ui
+                .add(egui::DragValue::new(
+                    &mut adaptive.maximum_samples_per_pixel,
+                ))
+                .changed();
+            ui.label("maximum samples per pixel");
+        });
+    }
+    changed
+}
+
+/// Object seen through the pixel of the preview under `pos`, from the object id buffer.
+fn get_hit_object(preview: &PreviewState, rect: egui::Rect, pos: egui::Pos2) -> Option<Object> {
+    let offset = pos - rect.min;
+    if offset.x < 0. || offset.y < 0. || offset.x >= preview.canvas.width as f32 {
+        return None;
+    }
+    let index = offset.y as usize * preview.canvas.width + offset.x as usize;
+    match preview.object_ids.get(index).copied().flatten() {
+        Some(ObjectId::Sphere(i)) => Some(Object::Sphere(i)),
+        Some(ObjectId::Plane(i)) => Some(Object::Plane(i)),
+        _ => None,
+    }
+}
+
+fn show_aov_settings(ui: &mut egui::Ui, aovs: &mut Vec<Aov>) {
+    ui.horizontal_wrapped(|ui| {
+        for aov in Aov::ALL {
+            let mut enabled = aovs.contains(&aov);
+            if ui.checkbox(&mut enabled, aov.name()).changed() {
+                if enabled {
+                    aovs.push(aov);
+                } else {
+                    aovs.retain(|a| *a != aov);
+                }
+            }
+        }
+    });
+}
+
+struct PreviewState {
+    settings: RenderSettings,
+    outlier_threshold: Option<f32>,
+    denoiser: Option<Denoiser>,
+    show_sample_counts: bool,
+    /// Object seen through each pixel, for picking.
+    object_ids: Vec<Option<ObjectId>>,
+    /// What was preprocessed for the last preview, along with the scene and integrator it was
+    /// for, so that e.g. photons are not shot again while only the camera moves.
+    preprocessed: Option<(String, Preprocessed)>,
     canvas: keyell::render::Canvas,
     buffer: Vec<u8>,
     texture_handle: Option<egui::TextureHandle>,
@@ -295,8 +1150,17 @@ impl PreviewState {
         let width = 640;
         let height = 360;
         Self {
-            samples_per_pixel: 10,
-            maximum_bounces: 10,
+            settings: RenderSettings {
+                samples_per_pixel: 10,
+                maximum_bounces: 10,
+                sampler: SamplerKind::Sobol,
+                ..Default::default()
+            },
+            outlier_threshold: None,
+            denoiser: None,
+            show_sample_counts: false,
+            object_ids: Vec::new(),
+            preprocessed: None,
             canvas: keyell::render::Canvas { width, height },
             buffer: vec![0u8; 3 * width * height],
             texture_handle: None,
@@ -304,11 +1168,32 @@ impl PreviewState {
     }
 }
 
+/// Preprocesses the scene for the preview, unless it was already for the same scene and
+/// integrator.
+fn preprocess_preview<'a>(
+    cache: &'a mut Option<(String, Preprocessed)>,
+    scene: &Scene,
+    settings: &RenderSettings,
+) -> &'a Preprocessed {
+    let key = format!(
+        "{:?} {} {}",
+        settings.integrator,
+        settings.maximum_bounces,
+        serde_json::to_string(scene).unwrap()
+    );
+    if !matches!(cache, Some((cached, _)) if *cached == key) {
+        *cache = Some((key, settings.integrator.preprocess(scene, settings)));
+    }
+    &cache.as_ref().unwrap().1
+}
+
 struct ExportParams {
     remotes: Vec<Remote>,
-    samples_per_pixel: usize,
-    maximum_bounces: usize,
+    settings: RenderSettings,
     canvas: keyell::render::Canvas,
+    outlier_threshold: Option<f32>,
+    denoiser: Option<Denoiser>,
+    sample_heatmap: bool,
     file_name: String,
     overwrite: bool,
 }
@@ -317,12 +1202,19 @@ impl ExportParams {
     fn new() -> Self {
         Self {
             remotes: Vec::new(),
-            samples_per_pixel: 100,
-            maximum_bounces: 30,
+            settings: RenderSettings {
+                samples_per_pixel: 100,
+                maximum_bounces: 30,
+                sampler: SamplerKind::Sobol,
+                ..Default::default()
+            },
             canvas: keyell::render::Canvas {
                 width: 1920,
                 height: 1080,
             },
+            outlier_threshold: None,
+            denoiser: None,
+            sample_heatmap: false,
             file_name: String::from("out"),
             overwrite: false,
         }
@@ -392,6 +1284,14 @@ fn save_scene(file_name: &str, scene: &Scene, status: &mut Status, overwrite: bo
     status.text = format!("Saved scene to {file_name}");
 }
 
+fn write_ppm(file: File, canvas: &keyell::render::Canvas, pixels: &[keyell::render::Color]) {
+    let mut writer = keyell::ppm::PpmWriter::new(BufWriter::new(file), canvas);
+    writer.write_header().unwrap();
+    for pixel in pixels {
+        writer.write_pixel(pixel).unwrap();
+    }
+}
+
 fn export_file(
     file_name: &str,
     scene: &Scene,
@@ -403,6 +1303,14 @@ fn export_file(
         Some(f) => f,
         None => return,
     };
+    let heatmap_file = if params.sample_heatmap {
+        match create_file(&format!("{file_name}_samples.ppm"), overwrite, status) {
+            Some(f) => Some(f),
+            None => return,
+        }
+    } else {
+        None
+    };
 
     let camera = keyell::render::Camera::from_canvas(
         &params.canvas,
@@ -410,20 +1318,42 @@ fn export_file(
         keyell::render::Degrees::new(90.),
     );
     let mut pixels = vec![keyell::render::Color::BLACK; params.canvas.height * params.canvas.width];
-    keyell::net::render_scene_distributed(
+    let settings = RenderSettings {
+        feature_buffers: params.denoiser.is_some(),
+        ..params.settings.clone()
+    };
+    let output = keyell::net::render_scene_distributed(
         &params.remotes,
         &mut pixels,
         scene,
         &params.canvas,
         &camera,
-        params.samples_per_pixel,
-        params.maximum_bounces,
+        &settings,
     );
 
-    let mut writer = keyell::ppm::PpmWriter::new(BufWriter::new(file), &params.canvas);
-    writer.write_header().unwrap();
-    for pixel in pixels {
-        writer.write_pixel(&pixel).unwrap();
+    if let Some(threshold) = params.outlier_threshold {
+        keyell::postprocess::reject_outliers(&mut pixels, &params.canvas, threshold);
+    }
+    if let Some(denoiser) = &params.denoiser {
+        denoiser.denoise(&mut pixels, &output, &params.canvas);
+    }
+    write_ppm(file, &params.canvas, &pixels);
+    if let Some(file) = heatmap_file {
+        write_ppm(
+            file,
+            &params.canvas,
+            &keyell::sample_heatmap(&output.sample_counts),
+        );
+    }
+    for aov in &params.settings.aovs {
+        let aov_file_name = format!("{file_name}_{}.ppm", aov.name());
+        let file = match create_file(&aov_file_name, overwrite, status) {
+            Some(f) => f,
+            None => return,
+        };
+        if let Some(image) = aov.image(&output) {
+            write_ppm(file, &params.canvas, &image);
+        }
     }
 
     status.color = egui::Color32::GREEN;
@@ -436,14 +1366,13 @@ fn main() -> Result<(), eframe::Error> {
 
     let mut selected_object = Option::<Object>::None;
     let mut scene = Scene {
-        spheres: Vec::new(),
-        planes: Vec::new(),
         background: Background {
-            material: Material::Light(Colorer::ZGradient {
+            material: Material::light(Colorer::ZGradient {
                 bottom: Color::WHITE,
                 top: Color::new(0.4, 0.3, 0.8),
             }),
         },
+        ..Default::default()
     };
 
     let mut status = Status {
@@ -464,33 +1393,36 @@ fn main() -> Result<(), eframe::Error> {
                 };
 
                 let point = match selected {
-                    Object::Sphere(i) => &mut scene.spheres[*i].center,
-                    Object::Plane(i) => &mut scene.planes[*i].point,
+                    Object::Sphere(i) => Some(&mut scene.spheres[*i].center),
+                    Object::Plane(i) => Some(&mut scene.planes[*i].point),
+                    Object::Light(i) => light_position_mut(&mut scene.lights[*i]),
                 };
 
-                if i.consume_key(egui::Modifiers::NONE, egui::Key::W) {
-                    point.z += 0.01;
-                    render_preview = true;
-                }
-                if i.consume_key(egui::Modifiers::NONE, egui::Key::S) {
-                    point.z -= 0.01;
-                    render_preview = true;
-                }
-                if i.consume_key(egui::Modifiers::NONE, egui::Key::A) {
-                    point.x -= 0.01;
-                    render_preview = true;
-                }
-                if i.consume_key(egui::Modifiers::NONE, egui::Key::D) {
-                    point.x += 0.01;
-                    render_preview = true;
-                }
-                if i.consume_key(egui::Modifiers::NONE, egui::Key::Q) {
-                    point.y -= 0.01;
-                    render_preview = true;
-                }
-                if i.consume_key(egui::Modifiers::NONE, egui::Key::E) {
-                    point.y += 0.01;
-                    render_preview = true;
+                if let Some(point) = point {
+                    if i.consume_key(egui::Modifiers::NONE, egui::Key::W) {
+                        point.z += 0.01;
+                        render_preview = true;
+                    }
+                    if i.consume_key(egui::Modifiers::NONE, egui::Key::S) {
+                        point.z -= 0.01;
+                        render_preview = true;
+                    }
+                    if i.consume_key(egui::Modifiers::NONE, egui::Key::A) {
+                        point.x -= 0.01;
+                        render_preview = true;
+                    }
+                    if i.consume_key(egui::Modifiers::NONE, egui::Key::D) {
+                        point.x += 0.01;
+                        render_preview = true;
+                    }
+                    if i.consume_key(egui::Modifiers::NONE, egui::Key::Q) {
+                        point.y -= 0.01;
+                        render_preview = true;
+                    }
+                    if i.consume_key(egui::Modifiers::NONE, egui::Key::E) {
+                        point.y += 0.01;
+                        render_preview = true;
+                    }
                 }
                 if i.consume_key(egui::Modifiers::NONE, egui::Key::X) {
                     if let Some(o) = &selected_object {
@@ -505,6 +1437,11 @@ fn main() -> Result<(), eframe::Error> {
                                 let i = (*i).min(scene.planes.len() - 1);
                                 selected_object = scene.planes.get(i).map(|_| Object::Plane(i));
                             }
+                            Object::Light(i) => {
+                                scene.lights.remove(*i);
+                                let i = (*i).min(scene.lights.len() - 1);
+                                selected_object = scene.lights.get(i).map(|_| Object::Light(i));
+                            }
                         }
                         render_preview = true;
                     }
@@ -518,16 +1455,42 @@ fn main() -> Result<(), eframe::Error> {
                         .show_unindented(ui, |ui| {
                             render_preview |= ui
                                 .add(
-                                    egui::Slider::new(&mut preview.samples_per_pixel, 1..=10)
-                                        .text("samples per pixel"),
+                                    egui::Slider::new(
+                                        &mut preview.settings.samples_per_pixel,
+                                        1..=10,
+                                    )
+                                    .text("samples per pixel"),
                                 )
                                 .changed();
                             render_preview |= ui
                                 .add(
-                                    egui::Slider::new(&mut preview.maximum_bounces, 1..=100)
-                                        .text("maximum bounces"),
+                                    egui::Slider::new(
+                                        &mut preview.settings.maximum_bounces,
+                                        1..=100,
+                                    )
+                                    .text("maximum bounces"),
                                 )
                                 .changed();
+                            render_preview |=
+                                show_integrator_settings(ui, &mut preview.settings.integrator);
+                            render_preview |=
+                                show_sampler_settings(ui, &mut preview.settings.sampler);
+                            render_preview |=
+                                show_filter_settings(ui, &mut preview.settings.filter);
+                            render_preview |= show_firefly_settings(
+                                ui,
+                                &mut preview.settings,
+                                &mut preview.outlier_threshold,
+                            );
+                            render_preview |= show_denoiser_settings(ui, &mut preview.denoiser);
+                            render_preview |= show_adaptive_settings(
+                                ui,
+                                &mut preview.settings.adaptive,
+                                preview.settings.samples_per_pixel,
+                            );
+                            render_preview |= ui
+                                .checkbox(&mut preview.show_sample_counts, "show sample counts")
+                                .changed();
                             ui.horizontal(|ui| {
                                 render_preview |= ui
                                     .add(egui::DragValue::new(&mut preview.canvas.width))
@@ -544,13 +1507,31 @@ fn main() -> Result<(), eframe::Error> {
                         .default_open(true)
                         .show_unindented(ui, |ui| {
                             ui.horizontal(|ui| {
-                                ui.add(egui::DragValue::new(&mut export.samples_per_pixel));
+                                ui.add(egui::DragValue::new(
+                                    &mut export.settings.samples_per_pixel,
+                                ));
                                 ui.label("samples per pixel");
                             });
                             ui.horizontal(|ui| {
-                                ui.add(egui::DragValue::new(&mut export.maximum_bounces));
+                                ui.add(egui::DragValue::new(&mut export.settings.maximum_bounces));
                                 ui.label("maximum bounces");
                             });
+                            show_integrator_settings(ui, &mut export.settings.integrator);
+                            show_sampler_settings(ui, &mut export.settings.sampler);
+                            show_filter_settings(ui, &mut export.settings.filter);
+                            show_firefly_settings(
+                                ui,
+                                &mut export.settings,
+                                &mut export.outlier_threshold,
+                            );
+                            show_denoiser_settings(ui, &mut export.denoiser);
+                            show_adaptive_settings(
+                                ui,
+                                &mut export.settings.adaptive,
+                                export.settings.samples_per_pixel,
+                            );
+                            ui.checkbox(&mut export.sample_heatmap, "export sample heatmap");
+                            show_aov_settings(ui, &mut export.settings.aovs);
 
                             ui.horizontal(|ui| {
                                 ui.add(egui::DragValue::new(&mut export.canvas.width));
@@ -613,6 +1594,13 @@ fn main() -> Result<(), eframe::Error> {
                             ui.separator();
                         });
 
+                    egui::CollapsingHeader::new("Fog")
+                        .default_open(false)
+                        .show_unindented(ui, |ui| {
+                            render_preview |= show_fog_settings(ui, &mut scene.fog);
+                            ui.separator();
+                        });
+
                     egui::CollapsingHeader::new("Spheres")
                         .default_open(true)
                         .show_unindented(ui, |ui| {
@@ -652,11 +1640,54 @@ fn main() -> Result<(), eframe::Error> {
                                 render_preview |= show_plane_settings(ui, plane, selected);
                             }
                         });
+                    ui.separator();
+
+                    egui::CollapsingHeader::new("Lights")
+                        .default_open(true)
+                        .show_unindented(ui, |ui| {
+                            let mut added = None;
+                            ui.horizontal(|ui| {
+                                if ui.button("Add point light").clicked() {
+                                    added = Some(Light::Point {
+                                        position: Point::new(0., 0.5, 0.3),
+                                        color: Color::WHITE,
+                                        intensity: 0.1,
+                                    });
+                                }
+                                if ui.button("Add spot light").clicked() {
+                                    added = Some(Light::Spot {
+                                        position: Point::new(0., 0.5, 0.3),
+                                        direction: Vec3::new(0., 0., -1.),
+                                        color: Color::WHITE,
+                                        intensity: 0.1,
+                                        inner_angle: 20.,
+                                        outer_angle: 30.,
+                                    });
+                                }
+                                if ui.button("Add directional light").clicked() {
+                                    added = Some(Light::Directional {
+                                        direction: Vec3::new(0.3, 0.5, -1.),
+                                        color: Color::WHITE,
+                                        intensity: 1.,
+                                        angular_radius: 0.5,
+                                    });
+                                }
+                            });
+                            if let Some(light) = added {
+                                scene.lights.push(light);
+                                selected_object = Some(Object::Light(scene.lights.len() - 1));
+                                render_preview = true;
+                            }
+                            for (i, light) in scene.lights.iter_mut().enumerate() {
+                                let selected = selected_object == Some(Object::Light(i));
+                                render_preview |= show_light_settings(ui, light, selected);
+                            }
+                        });
                 });
             });
 
             egui::CentralPanel::default().show(ctx, |ui| {
-                let camera = keyell::render::Camera::from_canvas(
+                let camera = Camera::from_canvas(
                     &preview.canvas,
                     keyell::types::Point::new(0., 0., 0.05),
                     keyell::render::Degrees::new(90.),
@@ -668,15 +1699,33 @@ fn main() -> Result<(), eframe::Error> {
                         keyell::render::Color::BLACK;
                         preview.canvas.height * preview.canvas.width
                     ];
-                    keyell::render_scene(
+                    preview.settings.aovs = vec![Aov::ObjectId];
+                    preview.settings.feature_buffers = preview.denoiser.is_some();
+                    let preprocessed =
+                        preprocess_preview(&mut preview.preprocessed, &scene, &preview.settings);
+                    let output = keyell::render_scene_with(
                         &mut pixels,
                         &scene,
+                        preprocessed,
                         &preview.canvas,
                         &camera,
-                        preview.samples_per_pixel,
-                        preview.maximum_bounces,
+                        &preview.settings,
                         0..preview.canvas.height,
                     );
+                    preview.object_ids = output.object_ids.clone();
+                    if let Some(threshold) = preview.outlier_threshold {
+                        keyell::postprocess::reject_outliers(
+                            &mut pixels,
+                            &preview.canvas,
+                            threshold,
+                        );
+                    }
+                    if let Some(denoiser) = &preview.denoiser {
+                        denoiser.denoise(&mut pixels, &output, &preview.canvas);
+                    }
+                    if preview.show_sample_counts {
+                        pixels = keyell::sample_heatmap(&output.sample_counts);
+                    }
                     preview
                         .buffer
                         .resize(3 * preview.canvas.height * preview.canvas.width, 0);
@@ -698,20 +1747,50 @@ fn main() -> Result<(), eframe::Error> {
 
                 let response = ui
                     .add(egui::Image::new(preview.texture_handle.as_ref().unwrap()))
-                    .interact(egui::Sense::click());
-                if let Some(pos) = response.interact_pointer_pos() {
-                    let rect = response.rect;
+                    .interact(egui::Sense::click_and_drag());
+                let rect = response.rect;
+                let to_uv = |pos: egui::Pos2| {
                     let x = pos.x - rect.min.x;
                     let y = pos.y - rect.min.y;
+                    (
+                        x / preview.canvas.width as f32,
+                        (preview.canvas.height as f32 - y) / preview.canvas.height as f32,
+                    )
+                };
 
-                    selected_object = get_hit_object(
-                        &scene,
-                        &camera.get_ray(
-                            x / preview.canvas.width as f32,
-                            (preview.canvas.height as f32 - y) / preview.canvas.height as f32,
-                        ),
-                    );
+                if response.clicked() || response.drag_started() {
+                    if let Some(pos) = response.interact_pointer_pos() {
+                        selected_object =
+                            get_light_gizmo(&scene, &camera, &preview.canvas, rect, pos)
+                                .or_else(|| get_hit_object(&preview, rect, pos));
+                    }
+                } else if response.dragged() {
+                    // drag lights parallel to the image plane, keeping their depth
+                    let dragged_light = match &selected_object {
+                        Some(Object::Light(i)) => light_position_mut(&mut scene.lights[*i]),
+                        _ => None,
+                    };
+                    if let (Some(position), Some(pos)) =
+                        (dragged_light, response.interact_pointer_pos())
+                    {
+                        if let Some((_, travel)) =
+                            project_to_preview(&camera, &preview.canvas, rect, position)
+                        {
+                            let (u, v) = to_uv(pos);
+                            *position = camera.get_ray(u, v).at(travel);
+                            render_preview = true;
+                        }
+                    }
                 }
+
+                draw_light_gizmos(
+                    &ui.painter_at(rect),
+                    &scene,
+                    &camera,
+                    &preview.canvas,
+                    rect,
+                    &selected_object,
+                );
             });
         },
     )
@@ -1,11 +1,12 @@
+use keyell::integrator::IntegratorKind;
 use keyell::render::{
-    Background, Camera, Canvas, Color, Colorer, Degrees, Material, Plane, Sphere,
+    Background, Camera, Canvas, Color, Colorer, Degrees, Material, Plane, SamplerKind, Sphere,
 };
 use keyell::types::{Normal, Point, Vec3};
-use keyell::Scene;
+use keyell::{RenderSettings, Scene};
 
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufWriter, Error, ErrorKind};
 
 fn make_scene() -> Scene {
     let spheres = vec![
@@ -25,6 +26,7 @@ fn make_scene() -> Scene {
             material: Material::Dielectric {
                 refraction_index: 1.3,
                 colorer: Colorer::Solid(Color::WHITE),
+                absorption: Color::BLACK,
             },
         },
         Sphere {
@@ -33,12 +35,13 @@ fn make_scene() -> Scene {
             material: Material::Dielectric {
                 refraction_index: 0.4,
                 colorer: Colorer::Solid(Color::new(0.6, 0.3, 0.9)),
+                absorption: Color::BLACK,
             },
         },
         Sphere {
             center: Point::new(0., -0.5, 0.),
             radius: 0.3,
-            material: Material::Light(Colorer::Bubblegum),
+            material: Material::light(Colorer::Bubblegum),
         },
         Sphere {
             center: Point::new(0.1, 0.3, 0.1),
@@ -57,7 +60,7 @@ fn make_scene() -> Scene {
     }];
 
     const BACKGROUND: Background = Background {
-        material: Material::Light(Colorer::ZGradient {
+        material: Material::light(Colorer::ZGradient {
             top: Color::new(0.5, 0.7, 1.0),
             bottom: Color::BLACK,
         }),
@@ -67,16 +70,31 @@ fn make_scene() -> Scene {
         spheres,
         planes,
         background: BACKGROUND,
+        ..Default::default()
     }
 }
 
+/// Renders the example scene to `out.ppm`, with the integrator named by the first argument if any.
 fn main() -> Result<(), std::io::Error> {
     const CANVAS: Canvas = Canvas {
         width: 1920,
         height: 1080,
     };
-    let samples_per_pixel = 10;
-    let maximum_bounces = 10;
+    let integrator = match std::env::args().nth(1) {
+        Some(name) => IntegratorKind::from_name(&name).ok_or_else(|| {
+            let names: Vec<_> = IntegratorKind::ALL.iter().map(|kind| kind.name()).collect();
+            let message = format!("unknown integrator {name}, expected one of {names:?}");
+            Error::new(ErrorKind::InvalidInput, message)
+        })?,
+        None => IntegratorKind::default(),
+    };
+    let settings = RenderSettings {
+        samples_per_pixel: 10,
+        maximum_bounces: 10,
+        sampler: SamplerKind::Sobol,
+        integrator,
+        ..Default::default()
+    };
 
     let mut writer = keyell::ppm::PpmWriter::new(BufWriter::new(File::create("out.ppm")?), &CANVAS);
     writer.write_header()?;
@@ -91,8 +109,7 @@ fn main() -> Result<(), std::io::Error> {
         &scene,
         &CANVAS,
         &camera,
-        samples_per_pixel,
-        maximum_bounces,
+        &settings,
         0..CANVAS.height,
     );
     let duration = begin.elapsed();
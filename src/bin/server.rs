@@ -22,13 +22,13 @@ fn main() -> std::io::Result<()> {
         let request: Request = serde_json::from_reader(buffer.as_slice()).unwrap();
         pixels.resize(request.canvas.width * request.range.len(), Color::BLACK);
         println!("rendering...");
-        keyell::render_scene(
+        let output = keyell::render_scene_with(
             &mut pixels,
             &request.scene,
+            &request.preprocessed,
             &request.canvas,
             &request.camera,
-            request.samples_per_pixel,
-            request.maximum_bounces,
+            &request.settings,
             request.range,
         );
         println!("rendered");
@@ -37,7 +37,9 @@ fn main() -> std::io::Result<()> {
         let bytes_len = std::mem::size_of::<Color>() * pixels.len();
         let bytes = unsafe { std::slice::from_raw_parts(bytes_ptr, bytes_len) };
         stream.write_all(bytes)?;
-        println!("wrote {} bytes to the client", bytes.len());
+        let encoded = keyell::net::encode_output(&output);
+        stream.write_all(&encoded)?;
+        println!("wrote {} bytes to the client", bytes.len() + encoded.len());
     }
     Ok(())
 }
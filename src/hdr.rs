@@ -0,0 +1,155 @@
+use crate::render::Color;
+
+use std::io::{BufRead, Error, ErrorKind};
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, Error> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "unexpected end of HDR header",
+        ));
+    }
+    String::from_utf8(line)
+        .map(|line| String::from(line.trim_end()))
+        .map_err(|_| invalid_data("invalid HDR header"))
+}
+
+/// Reads the resolution line, only the usual top to bottom, left to right orientation is
+/// supported.
+fn read_resolution<R: BufRead>(reader: &mut R) -> Result<(usize, usize), Error> {
+    let line = read_line(reader)?;
+    let tokens: Vec<_> = line.split_whitespace().collect();
+    match tokens[..] {
+        ["-Y", height, "+X", width] => {
+            let parse = |n: &str| {
+                n.parse()
+                    .map_err(|_| invalid_data("invalid HDR resolution"))
+            };
+            Ok((parse(width)?, parse(height)?))
+        }
+        _ => Err(invalid_data("unsupported HDR orientation")),
+    }
+}
+
+fn rgbe_to_color(rgbe: &[u8]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::BLACK;
+    }
+    let scale = 2f32.powi(rgbe[3] as i32 - (128 + 8));
+    let channel = |mantissa: u8| (mantissa as f32 + 0.5) * scale;
+    Color::new(channel(rgbe[0]), channel(rgbe[1]), channel(rgbe[2]))
+}
+
+/// Reads a run-length encoded scanline, whose four components are stored one after the other.
+fn read_rle_scanline<R: BufRead>(reader: &mut R, scanline: &mut [u8]) -> Result<(), Error> {
+    let width = scanline.len() / 4;
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            reader.read_exact(&mut count)?;
+            let (count, run) = match count[0] {
+                0 => return Err(invalid_data("invalid HDR run length")),
+                count if count > 128 => (count as usize - 128, true),
+                count => (count as usize, false),
+            };
+            if x + count > width {
+                return Err(invalid_data("HDR run overflows its scanline"));
+            }
+            let mut values = vec![0u8; if run { 1 } else { count }];
+            reader.read_exact(&mut values)?;
+            for i in 0..count {
+                scanline[4 * (x + i) + component] = if run { values[0] } else { values[i] };
+            }
+            x += count;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a Radiance RGBE image, returning its width, height and pixels from top to bottom.
+///
+/// Scanlines can either be flat or use the run-length encoding of newer files.
+pub fn read_hdr<R: BufRead>(mut reader: R) -> Result<(usize, usize, Vec<Color>), Error> {
+    if !read_line(&mut reader)?.starts_with("#?") {
+        return Err(invalid_data("missing HDR magic number"));
+    }
+    loop {
+        let line = read_line(&mut reader)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid_data("unsupported HDR format"));
+            }
+        }
+    }
+    let (width, height) = read_resolution(&mut reader)?;
+    if width == 0 {
+        return Ok((0, height, Vec::new()));
+    }
+
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut scanline = vec![0u8; 4 * width];
+    for _ in 0..height {
+        reader.read_exact(&mut scanline[..4])?;
+        let encoded_width = ((scanline[2] as usize) << 8) | scanline[3] as usize;
+        let rle = (8..0x8000).contains(&width)
+            && scanline[0] == 2
+            && scanline[1] == 2
+            && scanline[2] & 0x80 == 0;
+        if rle {
+            if encoded_width != width {
+                return Err(invalid_data("HDR scanline width mismatch"));
+            }
+            read_rle_scanline(&mut reader, &mut scanline)?;
+        } else {
+            reader.read_exact(&mut scanline[4..])?;
+        }
+        pixels.extend(scanline.chunks_exact(4).map(rgbe_to_color));
+    }
+
+    Ok((width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_and_rle_scanlines() {
+        let mut file = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+        // flat scanline
+        for x in 0..8 {
+            file.extend([x * 16, 64, 128, 129]);
+        }
+        // run-length encoded scanline: a run of 8 reds, then literal greens, then runs
+        file.extend([2, 2, 0, 8]);
+        file.extend([128 + 8, 128]);
+        file.extend([8, 0, 1, 2, 3, 4, 5, 6, 7]);
+        file.extend([128 + 4, 0, 128 + 4, 255]);
+        file.extend([128 + 8, 130]);
+
+        let (width, height, pixels) = read_hdr(&file[..]).unwrap();
+        assert_eq!((width, height), (8, 2));
+        assert_eq!(pixels.len(), 16);
+
+        // exponent 129 doubles mantissas over 256
+        let flat = &pixels[3];
+        assert_eq!(
+            (flat.r, flat.g, flat.b),
+            (48.5 / 128., 64.5 / 128., 128.5 / 128.)
+        );
+        // exponent 130 quadruples them
+        let rle = &pixels[8 + 5];
+        assert_eq!((rle.r, rle.g, rle.b), (128.5 / 64., 5.5 / 64., 255.5 / 64.));
+        let rle = &pixels[8 + 1];
+        assert_eq!(rle.b, 0.5 / 64.);
+    }
+}
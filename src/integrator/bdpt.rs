@@ -0,0 +1,683 @@
+use std::f32::consts::PI;
+
+use crate::math::{dot, Frame};
+use crate::render::{Color, Hit, Light, Material, Ray, Sampler, Sphere};
+use crate::sampling::{cosine_hemisphere, power_heuristic, uniform_sphere, UNIFORM_SPHERE_PDF};
+use crate::types::{Point, Vec3};
+use crate::{RenderSettings, Scene};
+
+use super::{background_light, is_medium, light_contribution, Integrator};
+
+/// Bidirectional path tracer, connecting every vertex of a path traced from the camera to every
+/// vertex of a path traced from a light, and weighting all the ways of building a path with
+/// multiple importance sampling.
+///
+/// Light paths start from emissive spheres and point and spot lights, which is what makes small
+/// lights, or lights seen through small openings, converge much faster than with path tracing.
+/// The background and directional lights cannot start light paths, and are only reached from the
+/// camera paths, as with path tracing. Light paths are not connected straight to the camera, so
+/// caustics on diffuse surfaces remain as noisy as with path tracing.
+pub struct Bidirectional<'a> {
+    emitters: Vec<Emitter<'a>>,
+}
+
+impl<'a> Bidirectional<'a> {
+    /// Bidirectional path tracer of the scene, whose emitters are gathered once for all paths.
+    pub fn new(scene: &'a Scene) -> Self {
+        Self {
+            emitters: emitters(scene),
+        }
+    }
+}
+
+/// Light or surface starting light paths.
+enum Emitter<'a> {
+    Sphere(&'a Sphere),
+    Light(&'a Light),
+}
+
+fn emitters(scene: &Scene) -> Vec<Emitter<'_>> {
+    let spheres = (scene.spheres.iter())
+        .filter(|sphere| matches!(sphere.material, Material::Light { .. }))
+        .map(Emitter::Sphere);
+    let lights = (scene.lights.iter())
+        .filter(|light| light.position().is_some())
+        .map(Emitter::Light);
+    spheres.chain(lights).collect()
+}
+
+enum VertexKind<'a> {
+    Camera,
+    /// Point or spot light, starting a light path.
+    Light(&'a Light),
+    /// Point of an emissive sphere, starting a light path.
+    Emitter(&'a Sphere),
+    /// Surface hit or scattering event in a medium.
+    Scatter(Hit<'a>),
+}
+
+struct Vertex<'a> {
+    kind: VertexKind<'a>,
+    point: Point,
+    /// Geometric normal, zero for points which are not on surfaces.
+    normal: Vec3,
+    /// Unit direction towards the previous vertex of the path.
+    wo: Vec3,
+    /// Light carried from the start of the path to the vertex, divided by the density of
+    /// sampling it.
+    throughput: Color,
+    /// Whether the path was continued from the vertex in a specular direction, which no other
+    /// vertex can be connected to.
+    delta: bool,
+    /// Density of the vertex with respect to area, when sampled from the previous vertex of its
+    /// path.
+    pdf_forward: f32,
+    /// Density of the vertex with respect to area, if it were sampled from the next vertex of
+    /// its path by a path going the other way.
+    pdf_reverse: f32,
+}
+
+/// Density of emitting light from a point of an emissive surface along the unit direction,
+/// with respect to solid angles.
+fn emission_pdf(material: &Material, normal: &Vec3, direction: &Vec3) -> f32 {
+    let cos = dot(normal, direction);
+    match material {
+        Material::Light {
+            two_sided: true, ..
+        } => cos.abs() / (2. * PI),
+        _ => cos.max(0.) / PI,
+    }
+}
+
+impl<'a> Vertex<'a> {
+    fn scatter(hit: Hit<'a>, wo: Vec3, throughput: Color) -> Self {
+        let normal = if is_medium(hit.material) {
+            Vec3::default()
+        } else {
+            hit.normal.outward().get().clone()
+        };
+        Self {
+            point: hit.point.clone(),
+            kind: VertexKind::Scatter(hit),
+            normal,
+            wo,
+            throughput,
+            delta: false,
+            pdf_forward: 0.,
+            pdf_reverse: 0.,
+        }
+    }
+
+    /// Converts the density of sampling the direction towards `next` from this vertex to a
+    /// density of sampling `next` with respect to area.
+    fn to_area(&self, pdf: f32, next: &Vertex) -> f32 {
+        let to_next = &next.point - &self.point;
+        let squared_distance = dot(&to_next, &to_next);
+        if squared_distance == 0. {
+            return 0.;
+        }
+        let cos = if next.normal == Vec3::default() {
+            1.
+        } else {
+            dot(&next.normal, &to_next).abs() / squared_distance.sqrt()
+        };
+        pdf * cos / squared_distance
+    }
+
+    /// Density of sampling `next` from this vertex, with respect to area, `previous` being the
+    /// vertex before this one or `None` if this vertex starts a light path.
+    fn pdf(&self, previous: Option<&Vertex>, next: &Vertex) -> f32 {
+        let direction = (&next.point - &self.point).unit().get().clone();
+        let pdf = match (&self.kind, previous) {
+            (VertexKind::Camera, _) => 0.,
+            (VertexKind::Light(_), _) => UNIFORM_SPHERE_PDF,
+            (VertexKind::Emitter(sphere), _) => {
+                emission_pdf(&sphere.material, &self.normal, &direction)
+            }
+            (VertexKind::Scatter(hit), None) => {
+                emission_pdf(hit.material, &self.normal, &direction)
+            }
+            (VertexKind::Scatter(hit), Some(previous)) => {
+                let wo = (&previous.point - &self.point).unit().get().clone();
+                hit.material.pdf(hit, &direction, &wo)
+            }
+        };
+        self.to_area(pdf, next)
+    }
+}
+
+/// Ratio of two densities, zero ones standing for the densities of specular vertices, which are
+/// equal in both directions.
+fn density_ratio(numerator: f32, denominator: f32) -> f32 {
+    let remap = |pdf: f32| if pdf == 0. { 1. } else { pdf };
+    remap(numerator) / remap(denominator)
+}
+
+/// Ray leaving the background, along with the path that reached it.
+struct Escape<'a> {
+    hit: Hit<'a>,
+    direction: Vec3,
+    throughput: Color,
+    /// Density of the direction of the ray if it was sampled from a non-specular BSDF.
+    bsdf_pdf: Option<f32>,
+}
+
+/// Extends a path by tracing rays and sampling BSDFs, until it has `maximum_vertices` vertices.
+/// `pdf` is the density of the direction of the ray, with respect to solid angles.
+#[allow(clippy::too_many_arguments)]
+fn random_walk<'a>(
+    scene: &'a Scene,
+    mut ray: Ray,
+    mut throughput: Color,
+    mut pdf: f32,
+    maximum_vertices: usize,
+    first_bounce: usize,
+    vertices: &mut Vec<Vertex<'a>>,
+    sampler: &mut dyn Sampler,
+) -> Option<Escape<'a>> {
+    let mut bsdf_pdf = None;
+    while vertices.len() < maximum_vertices {
+        sampler.start_bounce(first_bounce + vertices.len() - 1);
+        let hit = scene.trace(&ray, 0.001, f32::INFINITY, sampler)?;
+        if std::ptr::eq(hit.material, &scene.background.material) {
+            return Some(Escape {
+                hit,
+                direction: ray.direction.unit().get().clone(),
+                throughput,
+                bsdf_pdf,
+            });
+        }
+        let wo = -ray.direction.unit().get();
+        let mut vertex = Vertex::scatter(hit, wo, throughput.clone());
+        let previous = vertices.last_mut().unwrap();
+        vertex.pdf_forward = previous.to_area(pdf, &vertex);
+
+        let hit = match &vertex.kind {
+            VertexKind::Scatter(hit) => hit,
+            _ => unreachable!(),
+        };
+        let sample = hit.material.sample(hit, &vertex.wo, sampler);
+        let sample = match sample {
+            Some(sample) if sample.weight != Color::BLACK => sample,
+            _ => {
+                vertices.push(vertex);
+                break;
+            }
+        };
+        throughput = throughput * sample.weight;
+        let reverse_pdf = if sample.specular {
+            pdf = 0.;
+            0.
+        } else {
+            pdf = sample.pdf;
+            hit.material.pdf(hit, &vertex.wo, &sample.wi)
+        };
+        vertex.delta = sample.specular;
+        bsdf_pdf = (!sample.specular).then_some(sample.pdf);
+        previous.pdf_reverse = vertex.to_area(reverse_pdf, previous);
+        ray = Ray {
+            origin: vertex.point.clone(),
+            direction: sample.wi,
+        };
+        vertices.push(vertex);
+    }
+    None
+}
+
+/// Starts a light path from one of the emitters, picked uniformly.
+fn light_path<'a>(
+    scene: &'a Scene,
+    emitters: &[Emitter<'a>],
+    maximum_vertices: usize,
+    first_bounce: usize,
+    sampler: &mut dyn Sampler,
+) -> Vec<Vertex<'a>> {
+    let mut vertices = Vec::new();
+    if emitters.is_empty() || maximum_vertices == 0 {
+        return vertices;
+    }
+    sampler.start_bounce(first_bounce);
+    let selection_pdf = 1. / emitters.len() as f32;
+    let index = ((sampler.get_1d() * emitters.len() as f32) as usize).min(emitters.len() - 1);
+    let (start, radiance, direction, direction_pdf) = match emitters[index] {
+        Emitter::Sphere(sphere) => {
+            let normal = uniform_sphere(sampler);
+            let point = &sphere.center + sphere.radius * &normal;
+            let area_pdf = 1. / (4. * PI * sphere.radius * sphere.radius);
+            let side = match sphere.material {
+                Material::Light {
+                    two_sided: true, ..
+                } if sampler.get_1d() < 0.5 => -1.,
+                _ => 1.,
+            };
+            let direction = Frame::new(&(side * &normal)).to_world(&cosine_hemisphere(sampler));
+            let direction_pdf = emission_pdf(&sphere.material, &normal, &direction);
+            let radiance = sphere
+                .material
+                .emitted(&sphere.hit_at(point.clone(), &-&direction, 0.));
+            let start = Vertex {
+                kind: VertexKind::Emitter(sphere),
+                point,
+                normal: normal.clone(),
+                wo: Vec3::default(),
+                throughput: Color::grey(1. / (selection_pdf * area_pdf)),
+                delta: false,
+                pdf_forward: selection_pdf * area_pdf,
+                pdf_reverse: 0.,
+            };
+            let cos = dot(&normal, &direction).abs();
+            (start, cos * radiance, direction, direction_pdf)
+        }
+        Emitter::Light(light) => {
+            let position = light.position().unwrap().clone();
+            let direction = uniform_sphere(sampler);
+            // the light reaching a point at a distance of 1 is the intensity along the direction
+            let radiance = (light.sample(&(&position + &direction), sampler))
+                .map_or(Color::BLACK, |sample| sample.radiance);
+            let start = Vertex {
+                kind: VertexKind::Light(light),
+                point: position,
+                normal: Vec3::default(),
+                wo: Vec3::default(),
+                throughput: Color::grey(1. / selection_pdf),
+                delta: false,
+                pdf_forward: selection_pdf,
+                pdf_reverse: 0.,
+            };
+            (start, radiance, direction, UNIFORM_SPHERE_PDF)
+        }
+    };
+
+    let throughput = (1. / direction_pdf) * (start.throughput.clone() * radiance);
+    let ray = Ray {
+        origin: start.point.clone(),
+        direction,
+    };
+    vertices.push(start);
+    if direction_pdf > 0. && throughput != Color::BLACK {
+        random_walk(
+            scene,
+            ray,
+            throughput,
+            direction_pdf,
+            maximum_vertices,
+            first_bounce + 1,
+            &mut vertices,
+            sampler,
+        );
+    }
+    vertices
+}
+
+fn hit_of<'v, 'a>(vertex: &'v Vertex<'a>) -> Option<&'v Hit<'a>> {
+    match &vertex.kind {
+        VertexKind::Scatter(hit) => Some(hit),
+        _ => None,
+    }
+}
+
+/// Light carried by the path made of the first `s` vertices of the light path and the first `t`
+/// vertices of the camera path, before weighting it.
+fn connect(
+    scene: &Scene,
+    light: &[Vertex],
+    camera: &[Vertex],
+    s: usize,
+    t: usize,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    let last = &camera[t - 1];
+    let hit = match hit_of(last) {
+        Some(hit) => hit,
+        None => return Color::BLACK,
+    };
+    if s == 0 {
+        return last.throughput.clone() * hit.material.emitted(hit);
+    }
+    let first = &light[s - 1];
+    if last.delta || first.delta {
+        return Color::BLACK;
+    }
+
+    if let VertexKind::Light(point_light) = first.kind {
+        // shadow rays towards point lights are handled like direct lighting
+        let color = light_contribution(scene, hit, &last.wo, point_light, sampler);
+        return last.throughput.clone() * color * first.throughput.clone();
+    }
+
+    let to_light = &first.point - &last.point;
+    let distance = to_light.len();
+    if distance == 0. {
+        return Color::BLACK;
+    }
+    let wi = to_light / distance;
+    let bsdf = hit.material.eval(hit, &wi, &last.wo);
+    if bsdf == Color::BLACK {
+        return Color::BLACK;
+    }
+    let light_bsdf = match &first.kind {
+        VertexKind::Emitter(sphere) => {
+            let emitting_hit = sphere.hit_at(first.point.clone(), &wi, 0.);
+            dot(&first.normal, &wi).abs() * sphere.material.emitted(&emitting_hit)
+        }
+        VertexKind::Scatter(light_hit) => light_hit.material.eval(light_hit, &-&wi, &first.wo),
+        _ => Color::BLACK,
+    };
+    if light_bsdf == Color::BLACK {
+        return Color::BLACK;
+    }
+    let shadow_ray = Ray {
+        origin: last.point.clone(),
+        direction: wi,
+    };
+    let transmittance = scene.transmittance(&shadow_ray, 0.001, distance - 0.001, sampler);
+    (transmittance / (distance * distance))
+        * (last.throughput.clone() * bsdf * light_bsdf * first.throughput.clone())
+}
+
+/// Weight of the path made of the first `s` vertices of the light path and the first `t`
+/// vertices of the camera path, by the power heuristic over all the ways of building it.
+fn mis_weight(
+    emitters: &[Emitter],
+    light: &[Vertex],
+    camera: &[Vertex],
+    s: usize,
+    t: usize,
+) -> f32 {
+    let last = &camera[t - 1];
+    let before_last = &camera[t - 2];
+    let first = s.checked_sub(1).map(|i| &light[i]);
+    let before_first = s.checked_sub(2).map(|i| &light[i]);
+
+    // densities of the vertices around the connection, when sampled from the other side of it
+    let (last_reverse, before_last_reverse) = match first {
+        Some(first) => (
+            first.pdf(before_first, last),
+            last.pdf(Some(first), before_last),
+        ),
+        None => {
+            let hit = hit_of(last).unwrap();
+            let sphere = emitters.iter().find_map(|emitter| match emitter {
+                Emitter::Sphere(sphere) if std::ptr::eq(&sphere.material, hit.material) => {
+                    Some(sphere)
+                }
+                _ => None,
+            });
+            match sphere {
+                Some(sphere) => (
+                    1. / (emitters.len() as f32 * 4. * PI * sphere.radius * sphere.radius),
+                    last.pdf(None, before_last),
+                ),
+                // the camera path is the only way of reaching emitters that do not start paths
+                None => return 1.,
+            }
+        }
+    };
+    let first_reverse = first.map_or(0., |first| last.pdf(Some(before_last), first));
+    let before_first_reverse = match (first, before_first) {
+        (Some(first), Some(before_first)) => first.pdf(Some(last), before_first),
+        _ => 0.,
+    };
+
+    // ratios of the densities of building the path with fewer camera vertices, then with fewer
+    // light vertices, to the density of building it this way; connections to the camera itself
+    // are not attempted
+    let mut sum = 0.;
+    let mut ratio = 1.;
+    for i in (2..t).rev() {
+        let reverse = match t - 1 - i {
+            0 => last_reverse,
+            1 => before_last_reverse,
+            _ => camera[i].pdf_reverse,
+        };
+        ratio *= density_ratio(reverse, camera[i].pdf_forward).powi(2);
+        let delta = i != t - 1 && camera[i].delta;
+        if !delta && !camera[i - 1].delta {
+            sum += ratio;
+        }
+    }
+    let mut ratio = 1.;
+    for i in (0..s).rev() {
+        let reverse = match s - 1 - i {
+            0 => first_reverse,
+            1 => before_first_reverse,
+            _ => light[i].pdf_reverse,
+        };
+        ratio *= density_ratio(reverse, light[i].pdf_forward).powi(2);
+        let delta = i != s - 1 && light[i].delta;
+        let delta_before = match i {
+            0 => matches!(light[0].kind, VertexKind::Light(_)),
+            _ => light[i - 1].delta,
+        };
+        if !delta && !delta_before {
+            sum += ratio;
+        }
+    }
+    1. / (1. + sum)
+}
+
+impl Integrator for Bidirectional<'_> {
+    fn radiance(
+        &self,
+        ray: Ray,
+        scene: &Scene,
+        settings: &RenderSettings,
+        sampler: &mut dyn Sampler,
+    ) -> (Color, Color) {
+        let maximum_bounces = settings.maximum_bounces;
+        let emitters = &self.emitters;
+        let mut direct_color = Color::BLACK;
+        let mut indirect_color = Color::BLACK;
+        // as with path tracing, paths of at most two segments only bounce off the first hit, and
+        // carry direct light
+        let mut add = |color: Color, segments: usize| {
+            if segments <= 2 {
+                direct_color = direct_color.clone() + color;
+            } else {
+                indirect_color = indirect_color.clone()
+                    + match settings.maximum_indirect_radiance {
+                        Some(maximum) => color.limited(maximum),
+                        None => color,
+                    };
+            }
+        };
+
+        // as with path tracing, camera rays are traced `maximum_bounces` times, and paths end on
+        // emitters after as many segments, or on lights without surfaces after one more
+        let mut camera = vec![Vertex {
+            kind: VertexKind::Camera,
+            point: ray.origin.clone(),
+            normal: Vec3::default(),
+            wo: Vec3::default(),
+            throughput: Color::WHITE,
+            delta: false,
+            pdf_forward: 1.,
+            pdf_reverse: 0.,
+        }];
+        let escape = random_walk(
+            scene,
+            ray,
+            Color::WHITE,
+            1.,
+            maximum_bounces + 1,
+            0,
+            &mut camera,
+            sampler,
+        );
+        let light = light_path(
+            scene,
+            emitters,
+            maximum_bounces,
+            maximum_bounces + 1,
+            sampler,
+        );
+
+        // the background and directional lights are only reached from the camera path
+        if let Some(escape) = escape {
+            let mut emitted = escape.hit.material.emitted(&escape.hit);
+            if let Some(bsdf_pdf) = escape.bsdf_pdf {
+                let light_pdf = scene.background.pdf(&escape.direction);
+                emitted = power_heuristic(bsdf_pdf, light_pdf) * emitted;
+            }
+            add(escape.throughput * emitted, camera.len());
+        }
+        let sun = scene.background.sun();
+        for (i, vertex) in camera.iter().enumerate().skip(1) {
+            let hit = hit_of(vertex).unwrap();
+            if vertex.delta {
+                continue;
+            }
+            let mut color = background_light(scene, hit, &vertex.wo, true, sampler);
+            for light in scene.lights.iter().chain(&sun) {
+                if light.position().is_none() {
+                    color = color + light_contribution(scene, hit, &vertex.wo, light, sampler);
+                }
+            }
+            add(vertex.throughput.clone() * color, i + 1);
+        }
+
+        let point_light = matches!(
+            light.first(),
+            Some(Vertex {
+                kind: VertexKind::Light(_),
+                ..
+            })
+        );
+        for t in 2..=camera.len() {
+            for s in 0..=light.len() {
+                let segments = if s == 0 { t - 1 } else { s + t - 1 };
+                let maximum_segments = if s > 0 && point_light {
+                    maximum_bounces + 1
+                } else {
+                    maximum_bounces
+                };
+                if segments > maximum_segments {
+                    break;
+                }
+                let color = connect(scene, &light, &camera, s, t, sampler);
+                if color == Color::BLACK {
+                    continue;
+                }
+                let weight = mis_weight(emitters, &light, &camera, s, t);
+                add(weight * color, segments);
+            }
+        }
+
+        (direct_color, indirect_color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::Aov;
+    use crate::render::{Camera, Canvas, Colorer, Degrees, Plane, SamplerKind};
+    use crate::render_scene;
+    use crate::types::Normal;
+
+    use super::super::IntegratorKind;
+
+    fn cornell_box() -> Scene {
+        let wall = |point: Point, normal: Vec3, color: Color| Plane {
+            point,
+            normal: Normal::Outward(normal.unit()),
+            material: Material::Diffuse(Colorer::Solid(color)),
+        };
+        let white = Color::grey(0.73);
+        Scene {
+            spheres: vec![
+                Sphere {
+                    center: Point::new(0., 2., 1.8),
+                    radius: 0.2,
+                    material: Material::Light {
+                        colorer: Colorer::Solid(Color::grey(10.)),
+                        intensity: 1.,
+                        two_sided: false,
+                        temperature: None,
+                    },
+                },
+                Sphere {
+                    center: Point::new(-0.4, 2.2, 0.4),
+                    radius: 0.4,
+                    material: Material::Diffuse(Colorer::Solid(white.clone())),
+                },
+                Sphere {
+                    center: Point::new(0.45, 1.6, 0.3),
+                    radius: 0.3,
+                    material: Material::Dielectric {
+                        refraction_index: 0.67,
+                        colorer: Colorer::Solid(Color::WHITE),
+                        absorption: Color::BLACK,
+                    },
+                },
+            ],
+            planes: vec![
+                wall(Point::new(0., 0., 0.), Vec3::new(0., 0., 1.), white.clone()),
+                wall(
+                    Point::new(0., 0., 2.),
+                    Vec3::new(0., 0., -1.),
+                    white.clone(),
+                ),
+                wall(Point::new(0., 3., 0.), Vec3::new(0., -1., 0.), white),
+                wall(
+                    Point::new(-1., 0., 0.),
+                    Vec3::new(1., 0., 0.),
+                    Color::new(0.65, 0.05, 0.05),
+                ),
+                wall(
+                    Point::new(1., 0., 0.),
+                    Vec3::new(-1., 0., 0.),
+                    Color::new(0.12, 0.45, 0.15),
+                ),
+            ],
+            lights: vec![Light::Point {
+                position: Point::new(0.5, 2.5, 1.2),
+                color: Color::new(1., 0.8, 0.6),
+                intensity: 0.2,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn matches_path_tracing() {
+        let scene = cornell_box();
+        let canvas = Canvas {
+            width: 12,
+            height: 12,
+        };
+        let camera = Camera::from_canvas(&canvas, Point::new(0., -0.9, 1.), Degrees::new(60.));
+        // mean luminance of blocks of 3x3 pixels, which are less noisy than single pixels, of
+        // the image and then of its direct light
+        let regions = |integrator, samples_per_pixel| {
+            let settings = RenderSettings {
+                samples_per_pixel,
+                maximum_bounces: 6,
+                sampler: SamplerKind::Sobol,
+                integrator,
+                aovs: vec![Aov::Direct],
+                ..Default::default()
+            };
+            let mut pixels = vec![Color::BLACK; canvas.width * canvas.height];
+            let output = render_scene(&mut pixels, &scene, &canvas, &camera, &settings, 0..12);
+            let mut sums = vec![0.; 32];
+            for (i, pixel) in pixels.iter().chain(&output.direct).enumerate() {
+                let (x, y) = (i % canvas.width, i / canvas.width);
+                sums[y / 3 * 4 + x / 3] += pixel.luminance() / 9.;
+            }
+            sums
+        };
+
+        let path_tracing = regions(IntegratorKind::PathTracer, 4096);
+        let bidirectional = regions(IntegratorKind::Bidirectional, 256);
+        // dark regions are relatively noisier, hence the absolute part of the tolerance
+        for (path_tracing, bidirectional) in path_tracing.iter().zip(&bidirectional) {
+            assert!(
+                (path_tracing - bidirectional).abs() < 0.05 * path_tracing + 0.015,
+                "{} {}",
+                path_tracing,
+                bidirectional
+            );
+        }
+    }
+}
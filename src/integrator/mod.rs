@@ -0,0 +1,616 @@
+use serde::{Deserialize, Serialize};
+
+use crate::math::{dot, Frame};
+use crate::render::{Color, Hit, Hittable, Light, Material, Ray, Sampler};
+use crate::sampling::{cosine_hemisphere, power_heuristic};
+use crate::types::Vec3;
+use crate::{RenderSettings, Scene};
+
+mod bdpt;
+pub use bdpt::Bidirectional;
+mod photon;
+pub use photon::{PhotonMap, PhotonMapping};
+
+/// What integrators build from the whole scene before rendering it, once for a render and shared
+/// by all of its parts, including the rows rendered by servers.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Preprocessed {
+    /// Photon map of photon mapping integrators.
+    pub caustics: Option<PhotonMap>,
+}
+
+/// Estimates the light carried along camera rays.
+pub trait Integrator: Sync {
+    /// Light reaching the camera along the ray, split into the light emitted by the first hit or
+    /// reaching it straight from an emitter, and the light bouncing more than once.
+    ///
+    /// Integrators which do not simulate light, like the debug visualizers, return their whole
+    /// color as direct light.
+    fn radiance(
+        &self,
+        ray: Ray,
+        scene: &Scene,
+        settings: &RenderSettings,
+        sampler: &mut dyn Sampler,
+    ) -> (Color, Color);
+}
+
+/// Integrator to render with, as picked in the render settings.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum IntegratorKind {
+    #[default]
+    PathTracer,
+    Bidirectional,
+    /// Path tracing, with caustics estimated from a photon map built before rendering.
+    ///
+    /// Photons are only aimed at glass and perfect mirror spheres, so specular planes and SDFs
+    /// cast no caustics.
+    PhotonMapping {
+        photons: usize,
+        /// Number of photons whose density estimates the caustics at a hit.
+        nearest: usize,
+        /// Largest distance of the photons to the hit.
+        radius: f32,
+    },
+    Whitted,
+    AmbientOcclusion {
+        distance: f32,
+    },
+    Normals,
+    Uvs,
+    BounceCount,
+}
+
+impl IntegratorKind {
+    /// Every kind of integrator, with default parameters.
+    pub const ALL: [IntegratorKind; 8] = [
+        IntegratorKind::PathTracer,
+        IntegratorKind::Bidirectional,
+        IntegratorKind::PhotonMapping {
+            photons: 100_000,
+            nearest: 50,
+            radius: 0.05,
+        },
+        IntegratorKind::Whitted,
+        IntegratorKind::AmbientOcclusion { distance: 1. },
+        IntegratorKind::Normals,
+        IntegratorKind::Uvs,
+        IntegratorKind::BounceCount,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IntegratorKind::PathTracer => "path_tracer",
+            IntegratorKind::Bidirectional => "bidirectional",
+            IntegratorKind::PhotonMapping { .. } => "photon_mapping",
+            IntegratorKind::Whitted => "whitted",
+            IntegratorKind::AmbientOcclusion { .. } => "ambient_occlusion",
+            IntegratorKind::Normals => "normals",
+            IntegratorKind::Uvs => "uvs",
+            IntegratorKind::BounceCount => "bounce_count",
+        }
+    }
+
+    /// Kind of integrator with the given name, with default parameters.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+
+    /// Builds what the integrator needs from the whole scene, e.g. the photon map of photon
+    /// mapping.
+    pub fn preprocess(self, scene: &Scene, settings: &RenderSettings) -> Preprocessed {
+        let caustics = match self {
+            IntegratorKind::PhotonMapping {
+                photons,
+                nearest,
+                radius,
+            } => Some(PhotonMap::new(
+                scene,
+                photons,
+                nearest,
+                radius,
+                settings.maximum_bounces,
+            )),
+            _ => None,
+        };
+        Preprocessed { caustics }
+    }
+
+    /// Integrator rendering the scene, from what `preprocess` built for it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if photon mapping is not given a photon map.
+    pub fn integrator<'a>(
+        self,
+        scene: &'a Scene,
+        preprocessed: &'a Preprocessed,
+    ) -> Box<dyn Integrator + 'a> {
+        match self {
+            IntegratorKind::PathTracer => Box::new(PathTracer),
+            IntegratorKind::Bidirectional => Box::new(Bidirectional::new(scene)),
+            IntegratorKind::PhotonMapping { .. } => Box::new(PhotonMapping {
+                caustics: (preprocessed.caustics.as_ref())
+                    .expect("photon mapping needs its photon map"),
+            }),
+            IntegratorKind::Whitted => Box::new(Whitted),
+            IntegratorKind::AmbientOcclusion { distance } => {
+                Box::new(AmbientOcclusion { distance })
+            }
+            IntegratorKind::Normals => Box::new(Normals),
+            IntegratorKind::Uvs => Box::new(Uvs),
+            IntegratorKind::BounceCount => Box::new(BounceCount),
+        }
+    }
+}
+
+/// Light reaching the hit straight from the lights of the scene.
+///
+/// If the background is also reached by sampling the BSDF of the hit, both estimates of its light
+/// are weighted by multiple importance sampling.
+fn direct_light(
+    scene: &Scene,
+    hit: &Hit,
+    wo: &Vec3,
+    bsdf_sampled: bool,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    let sun = scene.background.sun();
+    let lights = (scene.lights.iter().chain(&sun))
+        .map(|light| light_contribution(scene, hit, wo, light, sampler))
+        .fold(Color::BLACK, |sum, color| sum + color);
+    lights + background_light(scene, hit, wo, bsdf_sampled, sampler)
+}
+
+/// Light reaching the hit straight from a light, if it is not blocked.
+fn light_contribution(
+    scene: &Scene,
+    hit: &Hit,
+    wo: &Vec3,
+    light: &Light,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    let sample = match light.sample(&hit.point, sampler) {
+        Some(sample) => sample,
+        None => return Color::BLACK,
+    };
+    let bsdf = hit.material.eval(hit, &sample.wi, wo);
+    if bsdf == Color::BLACK {
+        return Color::BLACK;
+    }
+    let shadow_ray = Ray {
+        origin: hit.point.clone(),
+        direction: sample.wi,
+    };
+    let transmittance = scene.transmittance(&shadow_ray, 0.001, sample.distance, sampler);
+    transmittance * (bsdf * sample.radiance)
+}
+
+/// Light reaching the hit straight from the background, if it can be importance sampled.
+fn background_light(
+    scene: &Scene,
+    hit: &Hit,
+    wo: &Vec3,
+    bsdf_sampled: bool,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    let (wi, light_pdf) = match scene.background.sample(sampler) {
+        Some(sample) => sample,
+        None => return Color::BLACK,
+    };
+    let bsdf = hit.material.eval(hit, &wi, wo);
+    if bsdf == Color::BLACK {
+        return Color::BLACK;
+    }
+    let ray = Ray {
+        origin: hit.point.clone(),
+        direction: wi,
+    };
+    let transmittance = scene.transmittance(&ray, 0.001, f32::INFINITY, sampler);
+    match scene.background.hit(&ray, 0.001, f32::INFINITY) {
+        Some(background) => {
+            let weight = if bsdf_sampled {
+                power_heuristic(light_pdf, hit.material.pdf(hit, &ray.direction, wo))
+            } else {
+                1.
+            };
+            let radiance = background.material.emitted(&background);
+            (transmittance * weight / light_pdf) * (bsdf * radiance)
+        }
+        None => Color::BLACK,
+    }
+}
+
+/// Whether the material is the phase function of a medium, whose hits are not on surfaces.
+fn is_medium(material: &Material) -> bool {
+    matches!(
+        material,
+        Material::Isotropic(_) | Material::HenyeyGreenstein { .. }
+    )
+}
+
+/// Number of bounces after which paths can be terminated by Russian roulette.
+const ROULETTE_DEPTH: usize = 3;
+
+/// Unidirectional path tracer, sampling lights at every bounce.
+pub struct PathTracer;
+
+impl PathTracer {
+    /// Traces a path from the camera, also returning the number of surfaces or media it hit.
+    /// Caustics from the photon map are added at every hit, as indirect light, and paths can be
+    /// terminated by Russian roulette from `roulette_depth` bounces.
+    fn trace(
+        &self,
+        mut ray: Ray,
+        scene: &Scene,
+        settings: &RenderSettings,
+        caustics: Option<&PhotonMap>,
+        roulette_depth: usize,
+        sampler: &mut dyn Sampler,
+    ) -> (Color, Color, usize) {
+        let mut direct_color = Color::BLACK;
+        let mut indirect_color = Color::BLACK;
+        // fraction of the light reaching the current hit which makes it to the camera
+        let mut throughput = Color::WHITE;
+        // density of the direction of the ray if it was sampled from a non-specular BSDF
+        let mut bsdf_pdf = None;
+        let mut hits = 0;
+
+        for bounce in 0..settings.maximum_bounces {
+            sampler.start_bounce(bounce);
+            let hit = match scene.trace(&ray, 0.001, f32::INFINITY, sampler) {
+                Some(hit) => hit,
+                None => break,
+            };
+            let wo = -ray.direction.unit().get();
+            let is_background = std::ptr::eq(hit.material, &scene.background.material);
+            if !is_background {
+                hits += 1;
+            }
+
+            let mut emitted = hit.material.emitted(&hit);
+            if let Some(bsdf_pdf) = bsdf_pdf {
+                if is_background {
+                    // the background was also sampled directly at the previous bounce
+                    let light_pdf = scene.background.pdf(ray.direction.unit().get());
+                    emitted = power_heuristic(bsdf_pdf, light_pdf) * emitted;
+                }
+            }
+            let emitted = throughput.clone() * emitted;
+            let direct = throughput.clone() * direct_light(scene, &hit, &wo, true, sampler);
+            // photons are only stored on surfaces, not in media or the background
+            let mut indirect = match caustics {
+                Some(caustics) if !is_background && !is_medium(hit.material) => {
+                    throughput.clone() * caustics.estimate(&hit, &wo)
+                }
+                _ => Color::BLACK,
+            };
+            // light coming straight from an emitter to the first hit is direct, whether it was
+            // found by sampling the emitter or the BSDF
+            if bounce <= 1 {
+                direct_color = direct_color + emitted;
+            } else {
+                indirect = indirect + emitted;
+            }
+            if bounce == 0 {
+                direct_color = direct_color + direct;
+            } else {
+                indirect = indirect + direct;
+            }
+            indirect_color = indirect_color
+                + match settings.maximum_indirect_radiance {
+                    Some(maximum) => indirect.limited(maximum),
+                    None => indirect,
+                };
+
+            let sample = match hit.material.sample(&hit, &wo, sampler) {
+                Some(sample) => sample,
+                None => break,
+            };
+            throughput = throughput * sample.weight;
+
+            if bounce + 1 >= roulette_depth {
+                // keep paths carrying more light more often, and always give them a chance to stop
+                let survival = throughput.r.max(throughput.g).max(throughput.b).min(0.95);
+                if sampler.get_1d() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+
+            bsdf_pdf = (!sample.specular).then_some(sample.pdf);
+            ray = Ray {
+                origin: hit.point.clone(),
+                direction: sample.wi,
+            };
+        }
+
+        (direct_color, indirect_color, hits)
+    }
+}
+
+impl Integrator for PathTracer {
+    fn radiance(
+        &self,
+        ray: Ray,
+        scene: &Scene,
+        settings: &RenderSettings,
+        sampler: &mut dyn Sampler,
+    ) -> (Color, Color) {
+        let (direct, indirect, _) = self.trace(ray, scene, settings, None, ROULETTE_DEPTH, sampler);
+        (direct, indirect)
+    }
+}
+
+/// Only follows perfect reflections and refractions, lighting other surfaces straight from the
+/// lights.
+///
+/// Much faster to converge than path tracing, but misses all indirect diffuse and glossy light.
+pub struct Whitted;
+
+impl Integrator for Whitted {
+    fn radiance(
+        &self,
+        mut ray: Ray,
+        scene: &Scene,
+        settings: &RenderSettings,
+        sampler: &mut dyn Sampler,
+    ) -> (Color, Color) {
+        let mut direct_color = Color::BLACK;
+        let mut indirect_color = Color::BLACK;
+        let mut throughput = Color::WHITE;
+
+        for bounce in 0..settings.maximum_bounces {
+            sampler.start_bounce(bounce);
+            let hit = match scene.trace(&ray, 0.001, f32::INFINITY, sampler) {
+                Some(hit) => hit,
+                None => break,
+            };
+            let wo = -ray.direction.unit().get();
+
+            let emitted = throughput.clone() * hit.material.emitted(&hit);
+            let direct = throughput.clone() * direct_light(scene, &hit, &wo, false, sampler);
+            // as with path tracing, emitters seen after one bounce light the first hit directly
+            if bounce <= 1 {
+                direct_color = direct_color + emitted;
+            } else {
+                indirect_color = indirect_color + emitted;
+            }
+            if bounce == 0 {
+                direct_color = direct_color + direct;
+            } else {
+                indirect_color = indirect_color + direct;
+            }
+
+            let sample = match hit.material.sample(&hit, &wo, sampler) {
+                Some(sample) if sample.specular => sample,
+                _ => break,
+            };
+            throughput = throughput * sample.weight;
+            ray = Ray {
+                origin: hit.point.clone(),
+                direction: sample.wi,
+            };
+        }
+
+        (direct_color, indirect_color)
+    }
+}
+
+/// Fraction of the hemisphere around the first hit which is not blocked by surfaces within
+/// `distance`, weighted by the cosine with the normal. Media and the lighting are ignored.
+pub struct AmbientOcclusion {
+    pub distance: f32,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(
+        &self,
+        ray: Ray,
+        scene: &Scene,
+        _settings: &RenderSettings,
+        sampler: &mut dyn Sampler,
+    ) -> (Color, Color) {
+        sampler.start_bounce(0);
+        let hit = match scene.hit_objects(&ray, 0.001, f32::INFINITY) {
+            Some(hit) => hit,
+            None => return (Color::WHITE, Color::BLACK),
+        };
+        let mut normal = hit.normal.outward().get().clone();
+        if dot(&normal, &ray.direction) > 0. {
+            normal = -normal;
+        }
+        let direction = Frame::new(&normal).to_world(&cosine_hemisphere(sampler));
+        let occlusion_ray = Ray {
+            origin: hit.point.clone(),
+            direction,
+        };
+        let visible = (scene.hit_objects(&occlusion_ray, 0.001, self.distance)).is_none();
+        (Color::grey(if visible { 1. } else { 0. }), Color::BLACK)
+    }
+}
+
+/// Outward normal at the first hit, mapped from [-1, 1] to [0, 1]. The background is black.
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn radiance(
+        &self,
+        ray: Ray,
+        scene: &Scene,
+        _settings: &RenderSettings,
+        sampler: &mut dyn Sampler,
+    ) -> (Color, Color) {
+        sampler.start_bounce(0);
+        let color = match scene.trace(&ray, 0.001, f32::INFINITY, sampler) {
+            Some(hit) if !std::ptr::eq(hit.material, &scene.background.material) => {
+                let n = hit.normal.outward().get().clone();
+                Color::new(0.5 + 0.5 * n.x, 0.5 + 0.5 * n.y, 0.5 + 0.5 * n.z)
+            }
+            _ => Color::BLACK,
+        };
+        (color, Color::BLACK)
+    }
+}
+
+/// Surface coordinates at the first hit, wrapped to [0, 1], as red and green.
+pub struct Uvs;
+
+impl Integrator for Uvs {
+    fn radiance(
+        &self,
+        ray: Ray,
+        scene: &Scene,
+        _settings: &RenderSettings,
+        sampler: &mut dyn Sampler,
+    ) -> (Color, Color) {
+        sampler.start_bounce(0);
+        let color = match scene.trace(&ray, 0.001, f32::INFINITY, sampler) {
+            Some(hit) => Color::new(hit.u.rem_euclid(1.), hit.v.rem_euclid(1.), 0.),
+            None => Color::BLACK,
+        };
+        (color, Color::BLACK)
+    }
+}
+
+/// Number of surfaces or media that path tracing hits before the path ends, relative to the
+/// maximum number of bounces.
+pub struct BounceCount;
+
+impl Integrator for BounceCount {
+    fn radiance(
+        &self,
+        ray: Ray,
+        scene: &Scene,
+        settings: &RenderSettings,
+        sampler: &mut dyn Sampler,
+    ) -> (Color, Color) {
+        let (_, _, hits) = PathTracer.trace(ray, scene, settings, None, ROULETTE_DEPTH, sampler);
+        let maximum = settings.maximum_bounces.max(1) as f32;
+        (Color::heatmap(hits as f32 / maximum), Color::BLACK)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{Background, Colorer, Light, Material, Plane, SamplerKind, Sphere};
+    use crate::types::{Normal, Point};
+
+    #[test]
+    fn ambient_occlusion_darkens_contacts() {
+        let scene = Scene {
+            spheres: vec![Sphere {
+                center: Point::new(0., 0., 1.),
+                radius: 1.,
+                material: Material::Diffuse(Colorer::Solid(Color::grey(0.5))),
+            }],
+            planes: vec![Plane {
+                point: Point::new(0., 0., 0.),
+                normal: Normal::Outward(Vec3::new(0., 0., 1.).unit()),
+                material: Material::Diffuse(Colorer::Solid(Color::grey(0.5))),
+            }],
+            background: Background {
+                material: Material::light(Colorer::Solid(Color::WHITE)),
+            },
+            ..Default::default()
+        };
+        let settings = RenderSettings {
+            samples_per_pixel: 1,
+            maximum_bounces: 4,
+            sampler: SamplerKind::Independent,
+            integrator: IntegratorKind::AmbientOcclusion { distance: 2. },
+            ..Default::default()
+        };
+        let preprocessed = settings.integrator.preprocess(&scene, &settings);
+        let integrator = settings.integrator.integrator(&scene, &preprocessed);
+        let mut sampler = settings.sampler.sampler(1, 0);
+        let mut occlusion = |x: f32| -> f32 {
+            let samples = 1000;
+            let sum: f32 = (0..samples)
+                .map(|i| {
+                    let ray = Ray {
+                        origin: Point::new(x, 0., 5.),
+                        direction: Vec3::new(0., 0., -1.),
+                    };
+                    sampler.start_pixel_sample((0, 0), i);
+                    let (color, _) = integrator.radiance(ray, &scene, &settings, &mut *sampler);
+                    color.r
+                })
+                .sum();
+            sum / samples as f32
+        };
+
+        let far = occlusion(10.);
+        let near = occlusion(1.1);
+        assert!(far > 0.99, "{}", far);
+        assert!(near < 0.8, "{} {}", far, near);
+    }
+
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        // a closed white box lit from inside, where paths keep bouncing until the maximum
+        let wall = |x: f32, y: f32, z: f32| Plane {
+            point: Point::new(-x, -y, -z),
+            normal: Normal::Outward(Vec3::new(x, y, z).unit()),
+            material: Material::Diffuse(Colorer::Solid(Color::grey(0.9))),
+        };
+        let scene = Scene {
+            planes: vec![
+                wall(1., 0., 0.),
+                wall(-1., 0., 0.),
+                wall(0., 1., 0.),
+                wall(0., -1., 0.),
+                wall(0., 0., 1.),
+                wall(0., 0., -1.),
+            ],
+            lights: vec![Light::Point {
+                position: Point::new(0.3, 0.2, 0.5),
+                color: Color::WHITE,
+                intensity: 1.,
+            }],
+            ..Default::default()
+        };
+        let settings = RenderSettings {
+            samples_per_pixel: 1,
+            maximum_bounces: 20,
+            sampler: SamplerKind::Independent,
+            ..Default::default()
+        };
+        let mut sampler = settings.sampler.sampler(1, 0);
+        let mut average = |roulette_depth: usize| -> (f32, f32) {
+            let samples = 20000;
+            let (radiance, hits) = (0..samples)
+                .map(|i| {
+                    let ray = Ray {
+                        origin: Point::new(0., 0., 0.),
+                        direction: Vec3::new(0.2, 1., -0.3),
+                    };
+                    sampler.start_pixel_sample((0, 0), i);
+                    let (direct, indirect, hits) = PathTracer.trace(
+                        ray,
+                        &scene,
+                        &settings,
+                        None,
+                        roulette_depth,
+                        &mut *sampler,
+                    );
+                    ((direct + indirect).r, hits as f32)
+                })
+                .fold((0., 0.), |(r, h), (radiance, hits)| {
+                    (r + radiance, h + hits)
+                });
+            (radiance / samples as f32, hits / samples as f32)
+        };
+
+        let (with_roulette, shorter) = average(ROULETTE_DEPTH);
+        let (without_roulette, hits) = average(usize::MAX);
+        assert!(hits > 19.9 && shorter < 12., "{} {}", hits, shorter);
+        assert!(
+            (with_roulette - without_roulette).abs() < 0.02 * without_roulette,
+            "{} {}",
+            with_roulette,
+            without_roulette
+        );
+    }
+}
@@ -0,0 +1,446 @@
+use std::f32::consts::PI;
+use std::ops::Range;
+
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::math::{dot, Frame};
+use crate::render::{Color, Hit, Light, Material, Ray, Sampler, Sphere};
+use crate::types::{Point, Vec3};
+use crate::{RenderSettings, Scene};
+
+use super::{is_medium, Integrator, PathTracer, ROULETTE_DEPTH};
+
+/// Number of photons traced by each parallel task, which seeds its own random number generator
+/// so that the map is the same across renders.
+const PHOTONS_PER_TASK: usize = 4096;
+
+/// Light which reached a diffuse or glossy surface through specular surfaces only.
+#[derive(Clone, Serialize, Deserialize)]
+struct Photon {
+    point: Point,
+    /// Unit direction the light came from.
+    wi: Vec3,
+    power: Color,
+}
+
+/// Sphere which photons are aimed at, with the distance from its center beyond which there are
+/// no objects.
+struct Target<'a> {
+    sphere: &'a Sphere,
+    extent: f32,
+}
+
+/// Caustics cast by glass and mirror spheres, estimated from photons traced from the lights.
+///
+/// Only lights without a surface shoot photons: paths traced from the camera can never reach them
+/// through specular surfaces, while they do reach emissive spheres and the background. Photons
+/// are aimed at dielectric and perfectly smooth metal spheres, so other objects, like specular
+/// planes and SDFs, do not cast caustics.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PhotonMap {
+    /// Photons ordered as a balanced kd-tree, each node being in the middle of its subtree.
+    photons: Vec<Photon>,
+    /// Axis splitting the subtree of the photon with the same index.
+    axes: Vec<u8>,
+    nearest: usize,
+    radius: f32,
+}
+
+impl PhotonMap {
+    /// Shoots `count` photons, which bounce at most `maximum_bounces` times. Caustics are
+    /// estimated from the `nearest` photons within `radius` of hits.
+    pub fn new(
+        scene: &Scene,
+        count: usize,
+        nearest: usize,
+        radius: f32,
+        maximum_bounces: usize,
+    ) -> Self {
+        let sun = scene.background.sun();
+        let lights: Vec<&Light> = scene.lights.iter().chain(&sun).collect();
+        let targets: Vec<Target> = (scene.spheres.iter())
+            .filter(|sphere| is_specular(&sphere.material))
+            .map(|sphere| Target {
+                sphere,
+                extent: (scene.spheres.iter())
+                    .map(|other| (&other.center - &sphere.center).len() + other.radius)
+                    .fold(0., f32::max)
+                    + 1.,
+            })
+            .collect();
+
+        let mut photons: Vec<Photon> = if lights.is_empty() || targets.is_empty() {
+            Vec::new()
+        } else {
+            (0..count.div_ceil(PHOTONS_PER_TASK))
+                .into_par_iter()
+                .flat_map_iter(|task| {
+                    let mut rng = SmallRng::seed_from_u64(task as u64);
+                    let start = task * PHOTONS_PER_TASK;
+                    (start..(start + PHOTONS_PER_TASK).min(count))
+                        .filter_map(|_| {
+                            shoot(scene, &lights, &targets, count, maximum_bounces, &mut rng)
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        };
+
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        Self {
+            photons,
+            axes,
+            nearest: nearest.max(1),
+            radius,
+        }
+    }
+
+    /// Light reaching the camera along `wo` from caustics at the hit.
+    pub fn estimate(&self, hit: &Hit, wo: &Vec3) -> Color {
+        let mut nearest = Vec::with_capacity(self.nearest);
+        let mut radius_squared = self.radius * self.radius;
+        self.gather(
+            0..self.photons.len(),
+            &hit.point,
+            &mut nearest,
+            &mut radius_squared,
+        );
+        if nearest.is_empty() {
+            return Color::BLACK;
+        }
+
+        let normal = hit.normal.outward().get().clone();
+        let flux = (nearest.iter())
+            .map(|&(_, index)| {
+                let photon = &self.photons[index];
+                // the BSDF includes the cosine with the direction of the photon, which its power
+                // already accounts for
+                let cos = dot(&photon.wi, &normal).abs().max(1e-3);
+                (1. / cos) * hit.material.eval(hit, &photon.wi, wo) * photon.power.clone()
+            })
+            .fold(Color::BLACK, |sum, color| sum + color);
+        flux / (PI * radius_squared)
+    }
+
+    /// Keeps the photons of the subtree closest to the point in `nearest`, as their squared
+    /// distance and index, and shrinks the squared search radius once there are enough of them.
+    fn gather(
+        &self,
+        range: Range<usize>,
+        point: &Point,
+        nearest: &mut Vec<(f32, usize)>,
+        radius_squared: &mut f32,
+    ) {
+        if range.is_empty() {
+            return;
+        }
+        let middle = range.start + range.len() / 2;
+        let photon = &self.photons[middle];
+        let axis = self.axes[middle];
+        let offset = coordinate(point, axis) - coordinate(&photon.point, axis);
+        let (near, far) = if offset < 0. {
+            (range.start..middle, middle + 1..range.end)
+        } else {
+            (middle + 1..range.end, range.start..middle)
+        };
+
+        self.gather(near, point, nearest, radius_squared);
+        let to_photon = point - &photon.point;
+        let distance_squared = dot(&to_photon, &to_photon);
+        if distance_squared < *radius_squared {
+            if nearest.len() == self.nearest {
+                let farthest = farthest(nearest);
+                nearest[farthest] = (distance_squared, middle);
+            } else {
+                nearest.push((distance_squared, middle));
+            }
+            if nearest.len() == self.nearest {
+                *radius_squared = nearest[farthest(nearest)].0;
+            }
+        }
+        if offset * offset < *radius_squared {
+            self.gather(far, point, nearest, radius_squared);
+        }
+    }
+}
+
+/// Path tracer adding the caustics of a photon map, which it cannot sample from point-like lights.
+pub struct PhotonMapping<'a> {
+    pub caustics: &'a PhotonMap,
+}
+
+impl Integrator for PhotonMapping<'_> {
+    fn radiance(
+        &self,
+        ray: Ray,
+        scene: &Scene,
+        settings: &RenderSettings,
+        sampler: &mut dyn Sampler,
+    ) -> (Color, Color) {
+        let (direct, indirect, _) = PathTracer.trace(
+            ray,
+            scene,
+            settings,
+            Some(self.caustics),
+            ROULETTE_DEPTH,
+            sampler,
+        );
+        (direct, indirect)
+    }
+}
+
+fn farthest(nearest: &[(f32, usize)]) -> usize {
+    (0..nearest.len())
+        .max_by(|&a, &b| nearest[a].0.total_cmp(&nearest[b].0))
+        .unwrap_or(0)
+}
+
+fn coordinate(point: &Point, axis: u8) -> f32 {
+    match axis {
+        0 => point.x,
+        1 => point.y,
+        _ => point.z,
+    }
+}
+
+/// Orders the photons as a kd-tree, splitting each subtree at its median along the axis in which
+/// it is the most spread out.
+fn build(photons: &mut [Photon], axes: &mut [u8]) {
+    if photons.is_empty() {
+        return;
+    }
+    let axis = (0..3)
+        .max_by(|&a, &b| {
+            let extent = |axis| {
+                let (min, max) = (photons.iter())
+                    .map(|photon| coordinate(&photon.point, axis))
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), x| {
+                        (min.min(x), max.max(x))
+                    });
+                max - min
+            };
+            extent(a).total_cmp(&extent(b))
+        })
+        .unwrap_or(0);
+    let middle = photons.len() / 2;
+    photons.select_nth_unstable_by(middle, |a, b| {
+        coordinate(&a.point, axis).total_cmp(&coordinate(&b.point, axis))
+    });
+    axes[middle] = axis;
+
+    let (left, right) = photons.split_at_mut(middle);
+    let (left_axes, right_axes) = axes.split_at_mut(middle);
+    build(left, left_axes);
+    build(&mut right[1..], &mut right_axes[1..]);
+}
+
+/// Whether the material only reflects or refracts light in single directions.
+fn is_specular(material: &Material) -> bool {
+    match material {
+        Material::Dielectric { .. } => true,
+        Material::Metal { fuzz, .. } => *fuzz <= 0.,
+        _ => false,
+    }
+}
+
+/// Cone of directions from `origin` towards the sphere, as its unit axis and the cosine of its
+/// half angle. Returns `None` if the origin is inside the sphere.
+fn cone(origin: &Point, sphere: &Sphere) -> Option<(Vec3, f32)> {
+    let to_center = &sphere.center - origin;
+    let distance_squared = dot(&to_center, &to_center);
+    let radius_squared = sphere.radius * sphere.radius;
+    if distance_squared <= radius_squared {
+        return None;
+    }
+    let cos_max = (1. - radius_squared / distance_squared).max(0.).sqrt();
+    Some((to_center / distance_squared.sqrt(), cos_max))
+}
+
+/// Traces a photon from a random light towards a random target, returning where it lands if it
+/// went through at least one specular surface.
+fn shoot(
+    scene: &Scene,
+    lights: &[&Light],
+    targets: &[Target],
+    count: usize,
+    maximum_bounces: usize,
+    sampler: &mut dyn Sampler,
+) -> Option<Photon> {
+    let pick = |len: usize, u: f32| ((u * len as f32) as usize).min(len - 1);
+    let light = lights[pick(lights.len(), sampler.get_1d())];
+    let target = &targets[pick(targets.len(), sampler.get_1d())];
+    // every photon carries its share of the light, divided by the density of its direction or
+    // position, which mixes those of all targets
+    let share = 1. / (lights.len() * count) as f32;
+
+    let (mut ray, mut power) = match light.position() {
+        Some(position) => {
+            let (axis, cos_max) = cone(position, target.sphere)?;
+            let (u, v) = sampler.get_2d();
+            let cos_theta = 1. - u * (1. - cos_max);
+            let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+            let phi = 2. * PI * v;
+            let local = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+            let direction = Frame::new(&axis).to_world(&local);
+
+            let density = (targets.iter())
+                .filter_map(|other| cone(position, other.sphere))
+                .filter(|(axis, cos_max)| dot(&direction, axis) >= *cos_max)
+                .map(|(_, cos_max)| 1. / (2. * PI * (1. - cos_max)))
+                .sum::<f32>()
+                / targets.len() as f32;
+            // the light reaching a unit distance away is its intensity along the direction
+            let intensity = light.sample(&(position + &direction), sampler)?.radiance;
+            let ray = Ray {
+                origin: position.clone(),
+                direction,
+            };
+            (ray, (share / density) * intensity)
+        }
+        None => {
+            // uniformly pick a line crossing the target, along the light's direction
+            let sample = light.sample(&target.sphere.center, sampler)?;
+            let direction = -sample.wi;
+            let (u, v) = sampler.get_2d();
+            let r = target.sphere.radius * u.sqrt();
+            let phi = 2. * PI * v;
+            let offset =
+                Frame::new(&direction).to_world(&Vec3::new(r * phi.cos(), r * phi.sin(), 0.));
+            let through = &target.sphere.center + offset;
+
+            let density = (targets.iter())
+                .filter(|other| {
+                    let to_center = &other.sphere.center - &through;
+                    let along = dot(&to_center, &direction);
+                    dot(&to_center, &to_center) - along * along
+                        <= other.sphere.radius * other.sphere.radius
+                })
+                .map(|other| 1. / (PI * other.sphere.radius * other.sphere.radius))
+                .sum::<f32>()
+                / targets.len() as f32;
+            let ray = Ray {
+                origin: &through + (-target.extent * &direction),
+                direction,
+            };
+            (ray, (share / density) * sample.radiance)
+        }
+    };
+
+    for bounce in 0..maximum_bounces {
+        let hit = scene.trace(&ray, 0.001, f32::INFINITY, sampler)?;
+        if std::ptr::eq(hit.material, &scene.background.material) || is_medium(hit.material) {
+            return None;
+        }
+        let wi = -ray.direction.unit().get();
+        let sample = hit.material.sample(&hit, &wi, sampler)?;
+        if !sample.specular {
+            return (bounce > 0).then(|| Photon {
+                point: hit.point.clone(),
+                wi,
+                power,
+            });
+        }
+        power = power * sample.weight;
+        ray = Ray {
+            origin: hit.point.clone(),
+            direction: sample.wi,
+        };
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{Colorer, Plane, SamplerKind};
+    use crate::types::Normal;
+
+    use super::super::IntegratorKind;
+
+    /// Glass ball above the ground, focusing a point light right below it.
+    fn lens() -> Scene {
+        Scene {
+            spheres: vec![Sphere {
+                center: Point::new(0., 0., 2.5),
+                radius: 1.,
+                material: Material::Dielectric {
+                    refraction_index: 0.67,
+                    colorer: Colorer::Solid(Color::WHITE),
+                    absorption: Color::BLACK,
+                },
+            }],
+            planes: vec![Plane {
+                point: Point::new(0., 0., 0.),
+                normal: Normal::Outward(Vec3::new(0., 0., 1.).unit()),
+                material: Material::Diffuse(Colorer::Solid(Color::grey(0.5))),
+            }],
+            lights: vec![Light::Point {
+                position: Point::new(0., 0., 8.),
+                color: Color::WHITE,
+                intensity: 40.,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn photons_carry_the_light_hitting_targets() {
+        let scene = lens();
+        let map = PhotonMap::new(&scene, 20_000, 50, 0.05, 8);
+        let power: f64 = (map.photons.iter()).map(|p| p.power.g as f64).sum();
+        // intensity times the solid angle of the ball seen from the light
+        let cos_max = (1. - 1. / 5.5_f64.powi(2)).sqrt();
+        let expected = 40. * 2. * std::f64::consts::PI * (1. - cos_max);
+        assert!(
+            (power - expected).abs() < 0.01 * expected,
+            "{} {}",
+            power,
+            expected
+        );
+    }
+
+    #[test]
+    fn focuses_caustics() {
+        let scene = lens();
+        let radiance = |integrator: IntegratorKind, x: f32| {
+            let settings = RenderSettings {
+                samples_per_pixel: 1,
+                maximum_bounces: 8,
+                sampler: SamplerKind::Independent,
+                integrator,
+                ..Default::default()
+            };
+            let preprocessed = integrator.preprocess(&scene, &settings);
+            let integrator = integrator.integrator(&scene, &preprocessed);
+            let mut sampler = settings.sampler.sampler(1, 0);
+            let samples = 100;
+            let sum: f32 = (0..samples)
+                .map(|_| {
+                    // looks at the ground from below the ball
+                    let ray = Ray {
+                        origin: Point::new(x, -5., 1.),
+                        direction: Vec3::new(0., 5., -1.),
+                    };
+                    let (direct, indirect) =
+                        integrator.radiance(ray, &scene, &settings, &mut *sampler);
+                    (direct + indirect).luminance()
+                })
+                .sum();
+            sum / samples as f32
+        };
+
+        let photon_mapping = IntegratorKind::PhotonMapping {
+            photons: 100_000,
+            nearest: 50,
+            radius: 0.05,
+        };
+        let caustic = radiance(photon_mapping, 0.);
+        let lit = radiance(photon_mapping, 4.);
+        assert!(caustic > 10. * lit, "{} {}", caustic, lit);
+        assert_eq!(radiance(IntegratorKind::PathTracer, 0.), 0.);
+    }
+}
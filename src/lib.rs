@@ -1,33 +1,115 @@
+pub mod aov;
+pub mod hdr;
+pub mod integrator;
 mod math;
 pub mod net;
 mod physics;
+pub mod postprocess;
 pub mod ppm;
 pub mod render;
+mod sampling;
 pub mod types;
 
 use render::{
-    Background, Bounce, Camera, Canvas, Color, Hit, Hittable, Interaction, Plane, Ray, Source,
-    Sphere,
+    Background, Camera, Canvas, Color, ConstantMedium, Filter, Fog, Hit, Hittable, Light, Material,
+    Medium, Plane, Ray, Sampler, SamplerKind, Sdf, Sphere, VoxelMedium,
 };
 
-use rand::rngs::SmallRng;
-use rand::{Rng, SeedableRng};
+use aov::{Aov, ObjectId};
+use integrator::{IntegratorKind, Preprocessed};
 use rayon::prelude::{ParallelBridge, ParallelIterator};
 use serde::{Deserialize, Serialize};
-use std::f32::INFINITY;
 use std::ops::Range;
+use std::sync::Mutex;
+use types::Vec3;
 
-#[derive(Clone, Serialize, Deserialize)]
+/// Scenes default to having nothing in front of a black background.
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Scene {
     pub spheres: Vec<Sphere>,
     pub planes: Vec<Plane>,
+    #[serde(default)]
+    pub sdfs: Vec<Sdf>,
+    #[serde(default)]
+    pub media: Vec<ConstantMedium>,
+    #[serde(default)]
+    pub fog: Option<Fog>,
+    #[serde(default)]
+    pub voxel_media: Vec<VoxelMedium>,
+    #[serde(default)]
+    pub lights: Vec<Light>,
     pub background: Background,
 }
 
-impl Hittable for Scene {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+impl Scene {
+    /// Object whose surface or medium the hit is on, found by its material.
+    pub fn object_id(&self, hit: &Hit) -> Option<ObjectId> {
+        let is_hit = |material: &Material| std::ptr::eq(hit.material, material);
+        if let Some(i) = self.spheres.iter().position(|s| is_hit(&s.material)) {
+            Some(ObjectId::Sphere(i))
+        } else if let Some(i) = self.planes.iter().position(|p| is_hit(&p.material)) {
+            Some(ObjectId::Plane(i))
+        } else if let Some(i) = self.sdfs.iter().position(|s| is_hit(&s.material)) {
+            Some(ObjectId::Sdf(i))
+        } else if let Some(i) = self.media.iter().position(|m| is_hit(&m.material)) {
+            Some(ObjectId::Medium(i))
+        } else if let Some(i) = self.voxel_media.iter().position(|m| is_hit(&m.material)) {
+            Some(ObjectId::VoxelMedium(i))
+        } else if self.fog.as_ref().is_some_and(|fog| is_hit(&fog.material)) {
+            Some(ObjectId::Fog)
+        } else {
+            None
+        }
+    }
+
+    fn media(&self) -> impl Iterator<Item = &dyn Medium> {
+        (self.media.iter().map(|m| m as &dyn Medium))
+            .chain(self.voxel_media.iter().map(|m| m as &dyn Medium))
+            .chain(self.fog.iter().map(|f| f as &dyn Medium))
+    }
+
+    /// Finds the closest surface hit, or a scattering event in participating media before it.
+    pub fn trace(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Hit<'_>> {
+        let mut closest_hit = self.hit(ray, t_min, t_max);
+        let mut closest_travel = closest_hit.as_ref().map_or(t_max, |hit| hit.travel);
+
+        for medium in self.media() {
+            if let Some(hit) = medium.sample(ray, t_min, closest_travel, sampler) {
+                closest_travel = hit.travel;
+                closest_hit = Some(hit);
+            }
+        }
+
+        closest_hit
+    }
+
+    /// Estimates the fraction of light going through the scene between `t_min` and `t_max`,
+    /// which surfaces block entirely.
+    pub fn transmittance(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> f32 {
+        if self.hit_objects(ray, t_min, t_max).is_some() {
+            return 0.;
+        }
+        self.media()
+            .map(|medium| medium.transmittance(ray, t_min, t_max, sampler))
+            .product()
+    }
+
+    /// Finds the closest hit, ignoring the background.
+    fn hit_objects(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
         let mut closest_travel = t_max;
-        let mut closest_hit = self.background.hit(ray, t_min, closest_travel);
+        let mut closest_hit = None;
 
         for sphere in &self.spheres {
             if let Some(hit) = sphere.hit(ray, t_min, closest_travel) {
@@ -43,66 +125,738 @@ impl Hittable for Scene {
             }
         }
 
+        for sdf in &self.sdfs {
+            if let Some(hit) = sdf.hit(ray, t_min, closest_travel) {
+                closest_travel = hit.travel;
+                closest_hit = Some(hit);
+            }
+        }
+
         closest_hit
     }
 }
 
-fn color_hit(
-    scene: &Scene,
+impl Hittable for Scene {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        self.hit_objects(ray, t_min, t_max)
+            .or_else(|| self.background.hit(ray, t_min, t_max))
+    }
+}
+
+/// Scales down colors whose largest component is above `maximum_radiance`, keeping their split.
+fn clamp_radiance(settings: &RenderSettings, direct: Color, indirect: Color) -> (Color, Color) {
+    if let Some(maximum) = settings.maximum_radiance {
+        let total = direct.clone() + indirect.clone();
+        let largest = total.r.max(total.g).max(total.b);
+        if largest > maximum {
+            let scale = maximum / largest;
+            return (scale * direct, scale * indirect);
+        }
+    }
+    (direct, indirect)
+}
+
+/// Parameters controlling the quality of renders.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RenderSettings {
+    /// Samples taken for every pixel, or in every pass of adaptive renders.
+    pub samples_per_pixel: usize,
+    pub maximum_bounces: usize,
+    #[serde(default)]
+    pub sampler: SamplerKind,
+    #[serde(default)]
+    pub integrator: IntegratorKind,
+    #[serde(default)]
+    pub adaptive: Option<AdaptiveSettings>,
+    #[serde(default)]
+    pub filter: Filter,
+    /// Largest component of the color of a sample, as rare bright paths would otherwise make
+    /// isolated bright pixels. Clamping loses energy, darkening highlights.
+    #[serde(default)]
+    pub maximum_radiance: Option<f32>,
+    /// Same as `maximum_radiance` for the light reaching the camera after a bounce, leaving
+    /// directly visible and directly lit surfaces alone.
+    #[serde(default)]
+    pub maximum_indirect_radiance: Option<f32>,
+    /// Whether to fill the albedo and normal buffers of the output, e.g. to guide denoising.
+    #[serde(default)]
+    pub feature_buffers: bool,
+    /// Buffers to fill besides the colors.
+    #[serde(default)]
+    pub aovs: Vec<Aov>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            samples_per_pixel: 10,
+            maximum_bounces: 10,
+            sampler: SamplerKind::default(),
+            integrator: IntegratorKind::default(),
+            adaptive: None,
+            filter: Filter::default(),
+            maximum_radiance: None,
+            maximum_indirect_radiance: None,
+            feature_buffers: false,
+            aovs: Vec::new(),
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Whether the buffer of the AOV is filled, the albedo and normals also being filled for
+    /// `feature_buffers`.
+    pub fn wants(&self, aov: Aov) -> bool {
+        self.aovs.contains(&aov) || (self.feature_buffers && aov.is_feature())
+    }
+}
+
+/// Renders in passes, only refining the pixels that are still noisy.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AdaptiveSettings {
+    /// Standard error of the mean luminance of a pixel, relative to that luminance, under which
+    /// the pixel stops being refined.
+    pub noise_threshold: f32,
+    pub maximum_samples_per_pixel: usize,
+}
+
+/// Pixels darker than this are held to the noise threshold of this luminance, so that nearly
+/// black ones do not take all the samples.
+const DARK_LUMINANCE: f32 = 0.05;
+/// Samples a pixel needs before its variance is trusted, as a few similar samples can hide a
+/// noisy pixel.
+const MINIMUM_ADAPTIVE_SAMPLES: usize = 16;
+
+/// Running estimate of the noise of a pixel, along with its AOVs, from its own samples.
+#[derive(Clone, Default)]
+struct PixelEstimate {
+    luminance_sum: f32,
+    luminance_squares: f32,
+    count: usize,
+    depth_sum: f32,
+    hits: usize,
+    normal_sum: Vec3,
+    albedo_sum: Color,
+    material_id: Option<u32>,
+    object_id: Option<ObjectId>,
+    direct_sum: Color,
+    indirect_sum: Color,
+}
+
+impl PixelEstimate {
+    fn add(&mut self, color: &Color) {
+        let luminance = color.luminance();
+        self.luminance_sum += luminance;
+        self.luminance_squares += luminance * luminance;
+        self.count += 1;
+    }
+
+    /// Adds the first hit of the next sample, before the sample itself.
+    fn add_first_hit(&mut self, hit: Option<FirstHit>) {
+        let hit = match hit {
+            Some(hit) => hit,
+            None => return,
+        };
+        if hit.distance.is_finite() {
+            self.depth_sum += hit.distance;
+            self.hits += 1;
+        }
+        if self.count == 0 {
+            self.material_id = Some(hit.material_id);
+            self.object_id = hit.object_id;
+        }
+    }
+
+    fn add_features(&mut self, albedo: Color, normal: Vec3) {
+        self.albedo_sum = std::mem::take(&mut self.albedo_sum) + albedo;
+        self.normal_sum = &self.normal_sum + normal;
+    }
+
+    fn add_split(&mut self, direct: Color, indirect: Color) {
+        self.direct_sum = std::mem::take(&mut self.direct_sum) + direct;
+        self.indirect_sum = std::mem::take(&mut self.indirect_sum) + indirect;
+    }
+
+    fn is_done(&self, settings: &RenderSettings, maximum_samples: usize) -> bool {
+        if self.count >= maximum_samples {
+            return true;
+        }
+        let adaptive = match &settings.adaptive {
+            Some(adaptive) if self.count >= MINIMUM_ADAPTIVE_SAMPLES => adaptive,
+            _ => return false,
+        };
+        let n = self.count as f32;
+        let mean = self.luminance_sum / n;
+        let variance = ((self.luminance_squares - mean * self.luminance_sum) / (n - 1.)).max(0.);
+        (variance / n).sqrt() <= adaptive.noise_threshold * mean.max(DARK_LUMINANCE)
+    }
+}
+
+/// Samples splatted onto a pixel, weighted by the reconstruction filter.
+#[derive(Clone, Default)]
+struct FilmPixel {
+    sum: Color,
+    weight: f32,
+}
+
+impl FilmPixel {
+    fn splat(&mut self, color: &Color, weight: f32) {
+        self.sum = std::mem::take(&mut self.sum) + weight * color;
+        self.weight += weight;
+    }
+
+    fn merge(&mut self, other: &FilmPixel) {
+        self.sum = std::mem::take(&mut self.sum) + other.sum.clone();
+        self.weight += other.weight;
+    }
+
+    /// Weighted average of the samples, clamped as filters with negative lobes can overshoot.
+    fn color(&self) -> Color {
+        if self.weight <= 0. {
+            return Color::BLACK;
+        }
+        let color = self.sum.clone() / self.weight;
+        Color::new(color.r.max(0.), color.g.max(0.), color.b.max(0.))
+    }
+}
+
+/// Splats a sample at `(x, y)` on the film, in pixels from its top left corner, onto the rows
+/// of `splats`, starting at `first_row`, that the filter reaches.
+fn splat(
+    splats: &mut [FilmPixel],
+    width: usize,
+    first_row: isize,
+    filter: &Filter,
+    (x, y): (f32, f32),
+    color: &Color,
+) {
+    let radius = filter.radius();
+    let rows = splats.len() / width;
+    for row in (y - 0.5 - radius).ceil() as isize..=(y - 0.5 + radius).floor() as isize {
+        let offset = row - first_row;
+        if offset < 0 || offset >= rows as isize {
+            continue;
+        }
+        for col in (x - 0.5 - radius).ceil() as isize..=(x - 0.5 + radius).floor() as isize {
+            if col < 0 || col >= width as isize {
+                continue;
+            }
+            let weight = filter.evaluate(x - col as f32 - 0.5, y - row as f32 - 0.5);
+            if weight != 0. {
+                splats[offset as usize * width + col as usize].splat(color, weight);
+            }
+        }
+    }
+}
+
+/// Maximum number of specular bounces followed to find the surface whose features are recorded.
+const FEATURE_BOUNCES: usize = 4;
+
+/// Albedo and normal of the first non-specular surface seen along the ray, from its first hit,
+/// the albedo being tinted by the specular surfaces on the way. The normal of the background is
+/// zero.
+fn features(
+    first: Option<Hit>,
     ray: &Ray,
-    hit: &Hit,
-    remaining_bounces: usize,
-    rng: &mut SmallRng,
-) -> Color {
-    if remaining_bounces == 0 {
-        return Color::BLACK;
+    scene: &Scene,
+    sampler: &mut dyn Sampler,
+) -> (Color, Vec3) {
+    let mut throughput = Color::WHITE;
+    let (mut next, mut direction) = (first, ray.direction.clone());
+    for bounce in 0..FEATURE_BOUNCES {
+        let hit = match next {
+            Some(hit) => hit,
+            None => break,
+        };
+        let wo = -direction.unit().get();
+        match hit.material.sample(&hit, &wo, sampler) {
+            Some(sample) if sample.specular && bounce + 1 < FEATURE_BOUNCES => {
+                throughput = throughput * sample.weight;
+                let ray = Ray {
+                    origin: hit.point.clone(),
+                    direction: sample.wi,
+                };
+                next = scene.trace(&ray, 0.001, f32::INFINITY, sampler);
+                direction = ray.direction;
+            }
+            _ => {
+                let albedo = throughput * hit.material.albedo(&hit);
+                if std::ptr::eq(hit.material, &scene.background.material) {
+                    return (albedo, Vec3::default());
+                }
+                return (albedo, hit.normal.outward().get().clone());
+            }
+        }
     }
-    match hit.material.scatter(ray, hit, rng) {
-        Interaction::Bounce(Bounce {
-            scattered,
-            attenuation,
-        }) => attenuation * ray_color(&scattered, scene, remaining_bounces - 1, rng),
-        Interaction::Source(Source { color }) => color,
-        Interaction::Nothing => Color::BLACK,
+    (Color::BLACK, Vec3::default())
+}
+
+/// Properties of the first hit of a camera ray.
+struct FirstHit {
+    distance: f32,
+    material_id: u32,
+    object_id: Option<ObjectId>,
+}
+
+impl FirstHit {
+    fn new(hit: &Hit, scene: &Scene) -> Self {
+        FirstHit {
+            distance: hit.distance,
+            material_id: hit.material.kind_id(),
+            object_id: scene.object_id(hit),
+        }
     }
 }
 
-fn ray_color(ray: &Ray, scene: &Scene, remaining_bounces: usize, rng: &mut SmallRng) -> Color {
-    match scene.hit(ray, 0.001, INFINITY) {
-        Some(hit) => color_hit(scene, ray, &hit, remaining_bounces, rng),
-        None => Color::BLACK,
+/// Per-pixel data gathered while rendering, besides the colors.
+///
+/// Buffers of AOVs which were not requested in the render settings are empty. Ids come from the
+/// first sample of each pixel, and the other buffers are averaged over all of them.
+///
+/// The albedo and normals are the feature buffers guiding denoising, which see through
+/// specular surfaces.
+#[derive(Default)]
+pub struct RenderOutput {
+    pub sample_counts: Vec<usize>,
+    pub depth: Vec<f32>,
+    pub normals: Vec<Vec3>,
+    pub albedo: Vec<Color>,
+    pub material_ids: Vec<Option<u32>>,
+    pub object_ids: Vec<Option<ObjectId>>,
+    pub direct: Vec<Color>,
+    pub indirect: Vec<Color>,
+}
+
+impl RenderOutput {
+    /// Appends the output of the rows following these ones.
+    pub fn append(&mut self, mut other: RenderOutput) {
+        self.sample_counts.append(&mut other.sample_counts);
+        self.depth.append(&mut other.depth);
+        self.normals.append(&mut other.normals);
+        self.albedo.append(&mut other.albedo);
+        self.material_ids.append(&mut other.material_ids);
+        self.object_ids.append(&mut other.object_ids);
+        self.direct.append(&mut other.direct);
+        self.indirect.append(&mut other.indirect);
     }
 }
 
+/// Renders the given rows of the scene.
+///
+/// The scene is preprocessed for the integrator on every call, so renders split across several
+/// calls should preprocess it once and use `render_scene_with` instead.
 pub fn render_scene(
     pixels: &mut [Color],
     scene: &Scene,
     canvas: &Canvas,
     camera: &Camera,
-    samples_per_pixel: usize,
-    maximum_bounces: usize,
+    settings: &RenderSettings,
     range: Range<usize>,
-) {
-    let mut rngs: Vec<SmallRng> = range
+) -> RenderOutput {
+    let preprocessed = settings.integrator.preprocess(scene, settings);
+    render_scene_with(
+        pixels,
+        scene,
+        &preprocessed,
+        canvas,
+        camera,
+        settings,
+        range,
+    )
+}
+
+/// Renders the given rows of the scene, with what was preprocessed for the integrator of the
+/// settings.
+///
+/// Rows just outside of the range are sampled as well, since their samples reach into it.
+pub fn render_scene_with(
+    pixels: &mut [Color],
+    scene: &Scene,
+    preprocessed: &Preprocessed,
+    canvas: &Canvas,
+    camera: &Camera,
+    settings: &RenderSettings,
+    range: Range<usize>,
+) -> RenderOutput {
+    let maximum_samples = settings
+        .adaptive
+        .as_ref()
+        .map_or(settings.samples_per_pixel, |a| {
+            a.maximum_samples_per_pixel.max(settings.samples_per_pixel)
+        });
+    let integrator = settings.integrator.integrator(scene, preprocessed);
+    let width = canvas.width;
+    let margin = settings.filter.radius().ceil() as usize;
+    let sampled_rows = range.start.saturating_sub(margin)..(range.end + margin).min(canvas.height);
+    let mut samplers: Vec<_> = sampled_rows
+        .clone()
+        .map(|row| settings.sampler.sampler(maximum_samples, row as u64))
+        .collect();
+    // AOVs draw from their own samplers, so that requesting them leaves the colors unchanged
+    let mut aov_samplers: Vec<_> = sampled_rows
         .clone()
-        .map(|i| SmallRng::seed_from_u64(i as u64))
+        .map(|row| settings.sampler.sampler(maximum_samples, !(row as u64)))
+        .collect();
+    let mut estimates = vec![PixelEstimate::default(); sampled_rows.len() * width];
+    let wanted =
+        |is_kind: fn(Aov) -> bool| Aov::ALL.iter().any(|&a| is_kind(a) && settings.wants(a));
+    let first_hit_aovs = wanted(Aov::is_first_hit);
+    let feature_buffers = wanted(Aov::is_feature);
+    let split_aovs = wanted(Aov::is_split);
+    let film: Vec<_> = range
+        .clone()
+        .map(|_| Mutex::new(vec![FilmPixel::default(); width]))
         .collect();
 
-    pixels
-        .chunks_mut(canvas.width)
-        .enumerate()
-        .zip(&mut rngs)
-        .par_bridge()
-        .for_each(|((row, pixel_row), rng)| {
-            let row = canvas.height - range.start - row - 1;
-            for (col, pixel) in pixel_row.iter_mut().enumerate() {
-                let mut color = Color::BLACK;
-                for _ in 0..samples_per_pixel {
-                    let u = (rng.gen_range(0. ..1.) + col as f32) / canvas.width as f32;
-                    let v = (rng.gen_range(0. ..1.) + row as f32) / canvas.height as f32;
-                    color = color + ray_color(&camera.get_ray(u, v), scene, maximum_bounces, rng);
+    loop {
+        let refined: usize = estimates
+            .chunks_mut(width)
+            .zip(&mut samplers)
+            .zip(&mut aov_samplers)
+            .zip(sampled_rows.clone())
+            .par_bridge()
+            .map(|(((estimates, sampler), aov_sampler), film_row)| {
+                let row = canvas.height - film_row - 1;
+                let first_row = film_row as isize - margin as isize;
+                let mut splats = vec![FilmPixel::default(); (2 * margin + 1) * width];
+                let mut refined = 0;
+                for (col, estimate) in estimates.iter_mut().enumerate() {
+                    if estimate.is_done(settings, maximum_samples) {
+                        continue;
+                    }
+                    refined += 1;
+                    let samples = settings
+                        .samples_per_pixel
+                        .clamp(1, maximum_samples - estimate.count);
+                    for _ in 0..samples {
+                        sampler.start_pixel_sample((col, row), estimate.count);
+                        let (du, dv) = sampler.get_2d();
+                        let u = (du + col as f32) / canvas.width as f32;
+                        let v = (dv + row as f32) / canvas.height as f32;
+                        let ray = camera.get_ray(u, v);
+                        if first_hit_aovs || feature_buffers {
+                            let aov_sampler = &mut **aov_sampler;
+                            aov_sampler.start_pixel_sample((col, row), estimate.count);
+                            let hit = scene.trace(&ray, 0.001, f32::INFINITY, aov_sampler);
+                            if first_hit_aovs {
+                                let first = hit.as_ref().map(|hit| FirstHit::new(hit, scene));
+                                estimate.add_first_hit(first);
+                            }
+                            if feature_buffers {
+                                let (albedo, normal) = features(hit, &ray, scene, aov_sampler);
+                                estimate.add_features(albedo, normal);
+                            }
+                        }
+                        let (direct, indirect) =
+                            integrator.radiance(ray, scene, settings, &mut **sampler);
+                        let (direct, indirect) = clamp_radiance(settings, direct, indirect);
+                        let color = direct.clone() + indirect.clone();
+                        if split_aovs {
+                            estimate.add_split(direct, indirect);
+                        }
+                        // film rows go down, unlike `v`
+                        let position = (col as f32 + du, (film_row + 1) as f32 - dv);
+                        splat(
+                            &mut splats,
+                            width,
+                            first_row,
+                            &settings.filter,
+                            position,
+                            &color,
+                        );
+                        estimate.add(&color);
+                    }
+                }
+                if refined > 0 {
+                    for (offset, splat_row) in splats.chunks(width).enumerate() {
+                        let film_row = first_row + offset as isize;
+                        if film_row < 0 || !range.contains(&(film_row as usize)) {
+                            continue;
+                        }
+                        let mut film_row = film[film_row as usize - range.start].lock().unwrap();
+                        for (pixel, splat) in film_row.iter_mut().zip(splat_row) {
+                            pixel.merge(splat);
+                        }
+                    }
                 }
-                *pixel = color / samples_per_pixel as f32;
+                refined
+            })
+            .sum();
+        if refined == 0 {
+            break;
+        }
+    }
+
+    for (pixel_row, film_row) in pixels.chunks_mut(width).zip(film) {
+        for (pixel, film_pixel) in pixel_row.iter_mut().zip(film_row.into_inner().unwrap()) {
+            *pixel = film_pixel.color();
+        }
+    }
+    let skipped = (range.start - sampled_rows.start) * width;
+    let estimates = &estimates[skipped..skipped + range.len() * width];
+    let mut output = RenderOutput {
+        sample_counts: estimates.iter().map(|estimate| estimate.count).collect(),
+        ..RenderOutput::default()
+    };
+    for aov in Aov::ALL.iter().copied().filter(|&aov| settings.wants(aov)) {
+        let average =
+            |sum: &Color, estimate: &PixelEstimate| sum.clone() / estimate.count.max(1) as f32;
+        match aov {
+            Aov::Depth => {
+                output.depth = (estimates.iter())
+                    .map(|e| match e.hits {
+                        0 => f32::INFINITY,
+                        hits => e.depth_sum / hits as f32,
+                    })
+                    .collect()
             }
-        });
+            Aov::Normal => {
+                output.normals = (estimates.iter())
+                    .map(|e| &e.normal_sum / e.count.max(1) as f32)
+                    .collect()
+            }
+            Aov::Albedo => {
+                output.albedo = (estimates.iter())
+                    .map(|e| average(&e.albedo_sum, e))
+                    .collect()
+            }
+            Aov::MaterialId => {
+                output.material_ids = estimates.iter().map(|e| e.material_id).collect()
+            }
+            Aov::ObjectId => output.object_ids = estimates.iter().map(|e| e.object_id).collect(),
+            Aov::Direct => {
+                output.direct = (estimates.iter())
+                    .map(|e| average(&e.direct_sum, e))
+                    .collect()
+            }
+            Aov::Indirect => {
+                output.indirect = (estimates.iter())
+                    .map(|e| average(&e.indirect_sum, e))
+                    .collect()
+            }
+        }
+    }
+    output
+}
+
+/// Visualizes sample counts, from black for pixels with no samples to white for the most sampled
+/// ones.
+pub fn sample_heatmap(counts: &[usize]) -> Vec<Color> {
+    let maximum = counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+    counts
+        .iter()
+        .map(|&count| Color::heatmap(count as f32 / maximum))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use render::{Colorer, Degrees, SamplerKind};
+    use types::{Normal, Point};
+
+    #[test]
+    fn adaptive_sampling_refines_noisy_pixels() {
+        // the black sky converges right away, while the ground is only lit by randomly hitting
+        // a light overhead, out of view
+        let scene = Scene {
+            spheres: vec![Sphere {
+                center: Point::new(0., 2., 20.),
+                radius: 14.,
+                material: Material::light(Colorer::Solid(Color::WHITE)),
+            }],
+            planes: vec![Plane {
+                point: Point::new(0., 0., 0.),
+                normal: Normal::Outward(Vec3::new(0., 0., 1.).unit()),
+                material: Material::Diffuse(Colorer::Solid(Color::grey(0.5))),
+            }],
+            ..Default::default()
+        };
+        let canvas = Canvas {
+            width: 8,
+            height: 8,
+        };
+        let camera = Camera::from_canvas(&canvas, Point::new(0., 0., 1.), Degrees::new(60.));
+        // passes stop at the maximum even when it isn't a multiple of their samples
+        for (samples_per_pixel, maximum, converged) in [(4, 64, 16), (5, 62, 20)] {
+            let settings = RenderSettings {
+                samples_per_pixel,
+                maximum_bounces: 4,
+                sampler: SamplerKind::Independent,
+                integrator: IntegratorKind::PathTracer,
+                adaptive: Some(AdaptiveSettings {
+                    noise_threshold: 0.01,
+                    maximum_samples_per_pixel: maximum,
+                }),
+                ..Default::default()
+            };
+            let mut pixels = vec![Color::BLACK; canvas.width * canvas.height];
+            let output = render_scene(&mut pixels, &scene, &canvas, &camera, &settings, 0..8);
+
+            let counts = output.sample_counts;
+            let (sky, ground) = counts.split_at(counts.len() / 2);
+            assert!(sky.iter().all(|&c| c == converged), "{:?}", sky);
+            assert!(ground.iter().all(|&c| c == maximum), "{:?}", ground);
+        }
+    }
+
+    #[test]
+    fn one_sided_plane_lights_emit_towards_their_normal() {
+        // the camera looks along +y at a wall of light
+        let render = |normal: Normal, two_sided: bool| {
+            let scene = Scene {
+                planes: vec![Plane {
+                    point: Point::new(0., 2., 0.),
+                    normal,
+                    material: Material::Light {
+                        colorer: Colorer::Solid(Color::WHITE),
+                        intensity: 1.,
+                        two_sided,
+                        temperature: None,
+                    },
+                }],
+                ..Default::default()
+            };
+            let canvas = Canvas {
+                width: 2,
+                height: 2,
+            };
+            let camera = Camera::from_canvas(&canvas, Point::new(0., 0., 0.), Degrees::new(60.));
+            let settings = RenderSettings {
+                samples_per_pixel: 1,
+                maximum_bounces: 1,
+                sampler: SamplerKind::Independent,
+                integrator: IntegratorKind::PathTracer,
+                ..Default::default()
+            };
+            let mut pixels = vec![Color::BLACK; 4];
+            render_scene(&mut pixels, &scene, &canvas, &camera, &settings, 0..2);
+            pixels
+        };
+        let towards = Vec3::new(0., -1., 0.).unit();
+        let away = Vec3::new(0., 1., 0.).unit();
+
+        let lit = vec![Color::WHITE; 4];
+        let unlit = vec![Color::BLACK; 4];
+        assert_eq!(render(Normal::Outward(towards.clone()), false), lit);
+        assert_eq!(render(Normal::Outward(away.clone()), false), unlit);
+        // inward normals point the other way
+        assert_eq!(render(Normal::Inward(away.clone()), false), lit);
+        assert_eq!(render(Normal::Inward(towards), false), unlit);
+        assert_eq!(render(Normal::Outward(away), true), lit);
+    }
+
+    #[test]
+    fn clamping_limits_radiance_and_keeps_its_split() {
+        // a small bright light behind glass, and a point light next to it lighting the floor and
+        // the wall behind them
+        let scene = Scene {
+            spheres: vec![
+                Sphere {
+                    center: Point::new(0., 3., 0.),
+                    radius: 1.,
+                    material: Material::Dielectric {
+                        refraction_index: 0.67,
+                        colorer: Colorer::Solid(Color::WHITE),
+                        absorption: Color::BLACK,
+                    },
+                },
+                Sphere {
+                    center: Point::new(0., 6., 0.),
+                    radius: 0.2,
+                    material: Material::Light {
+                        colorer: Colorer::Solid(Color::WHITE),
+                        intensity: 100.,
+                        two_sided: false,
+                        temperature: None,
+                    },
+                },
+            ],
+            planes: vec![
+                Plane {
+                    point: Point::new(0., 0., -1.),
+                    normal: Normal::Outward(Vec3::new(0., 0., 1.).unit()),
+                    material: Material::Diffuse(Colorer::Solid(Color::grey(0.5))),
+                },
+                Plane {
+                    point: Point::new(0., 6.5, 0.),
+                    normal: Normal::Outward(Vec3::new(0., -1., 0.).unit()),
+                    material: Material::Diffuse(Colorer::Solid(Color::grey(0.8))),
+                },
+            ],
+            lights: vec![Light::Point {
+                position: Point::new(0., 5.5, 0.),
+                color: Color::WHITE,
+                intensity: 200.,
+            }],
+            ..Default::default()
+        };
+        let canvas = Canvas {
+            width: 8,
+            height: 8,
+        };
+        let camera = Camera::from_canvas(&canvas, Point::new(0., 0., 0.), Degrees::new(60.));
+        // single samples from the same sampler, so that clamped pixels are scaled unclamped ones
+        let render = |maximum_radiance, maximum_indirect_radiance| {
+            let settings = RenderSettings {
+                samples_per_pixel: 1,
+                maximum_bounces: 6,
+                sampler: SamplerKind::Independent,
+                integrator: IntegratorKind::PathTracer,
+                maximum_radiance,
+                maximum_indirect_radiance,
+                aovs: vec![Aov::Direct, Aov::Indirect],
+                ..Default::default()
+            };
+            let mut pixels = vec![Color::BLACK; canvas.width * canvas.height];
+            let output = render_scene(&mut pixels, &scene, &canvas, &camera, &settings, 0..8);
+            (pixels, output)
+        };
+        let largest = |c: &Color| c.r.max(c.g).max(c.b);
+
+        let (pixels, unclamped) = render(None, None);
+        assert!(pixels.iter().any(|p| largest(p) > 2.), "{:?}", pixels);
+        assert!(unclamped.indirect.iter().any(|c| largest(c) > 2.));
+        // some pixels need clamping with both parts lit
+        assert!(
+            (unclamped.direct.iter().zip(&unclamped.indirect)).any(|(d, i)| largest(d) > 0.1
+                && largest(i) > 0.1
+                && largest(&(d.clone() + i.clone())) > 1.)
+        );
+
+        let (pixels, clamped) = render(Some(1.), None);
+        for (pixel, i) in pixels.iter().zip(0..) {
+            assert!(largest(pixel) <= 1.0001, "{:?}", pixel);
+            let (direct, indirect) = (&clamped.direct[i], &clamped.indirect[i]);
+            assert!(largest(&(direct.clone() + indirect.clone())) <= 1.0001);
+            // both parts are scaled alike
+            let share = |direct: &Color, indirect: &Color| {
+                let total = largest(&(direct.clone() + indirect.clone()));
+                (
+                    largest(direct) / total.max(1e-6),
+                    largest(indirect) / total.max(1e-6),
+                )
+            };
+            let (direct_share, indirect_share) = share(direct, indirect);
+            let (unclamped_direct, unclamped_indirect) =
+                share(&unclamped.direct[i], &unclamped.indirect[i]);
+            assert!(
+                (direct_share - unclamped_direct).abs() < 1e-3
+                    && (indirect_share - unclamped_indirect).abs() < 1e-3,
+                "{:?} {:?} {:?} {:?}",
+                direct,
+                indirect,
+                unclamped.direct[i],
+                unclamped.indirect[i]
+            );
+        }
+
+        // limiting the light after bounces leaves the direct light as it is
+        let (_, limited) = render(None, Some(1.));
+        assert_eq!(limited.direct, unclamped.direct);
+        for (indirect, unclamped) in limited.indirect.iter().zip(&unclamped.indirect) {
+            assert!(largest(indirect) <= largest(unclamped) + 1e-4);
+        }
+        assert!(limited.indirect.iter().all(|c| largest(c) <= 6.));
+    }
 }
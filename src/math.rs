@@ -13,3 +13,54 @@ pub fn deg_to_radians(d: f32) -> f32 {
 pub fn same_orientation(v1: &Vec3, v2: &Vec3) -> bool {
     dot(v1, v2) > 0.
 }
+
+pub fn cross(v1: &Vec3, v2: &Vec3) -> Vec3 {
+    Vec3::new(
+        v1.y * v2.z - v1.z * v2.y,
+        v1.z * v2.x - v1.x * v2.z,
+        v1.x * v2.y - v1.y * v2.x,
+    )
+}
+
+/// Builds two unit vectors that form an orthonormal basis with the unit vector `n`.
+pub fn orthonormal_basis(n: &Vec3) -> (Vec3, Vec3) {
+    // Duff et al., "Building an Orthonormal Basis, Revisited"
+    let sign = 1_f32.copysign(n.z);
+    let a = -1. / (sign + n.z);
+    let b = n.x * n.y * a;
+    (
+        Vec3::new(1. + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        Vec3::new(b, sign + n.y * n.y * a, -n.y),
+    )
+}
+
+/// Orthonormal basis around a normal, to express directions in a local shading space where the
+/// normal is the Z axis.
+pub struct Frame {
+    tangent: Vec3,
+    bitangent: Vec3,
+    normal: Vec3,
+}
+
+impl Frame {
+    pub fn new(normal: &Vec3) -> Self {
+        let (tangent, bitangent) = orthonormal_basis(normal);
+        Self {
+            tangent,
+            bitangent,
+            normal: normal.clone(),
+        }
+    }
+
+    pub fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            dot(v, &self.tangent),
+            dot(v, &self.bitangent),
+            dot(v, &self.normal),
+        )
+    }
+
+    pub fn to_world(&self, v: &Vec3) -> Vec3 {
+        v.x * &self.tangent + v.y * &self.bitangent + v.z * &self.normal
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
-    io::{Read, Write},
+    convert::TryInto,
+    io::{self, Read, Write},
     net::TcpStream,
     ops::Range,
 };
@@ -8,8 +9,12 @@ use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelI
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    aov::ObjectId,
+    integrator::Preprocessed,
     render::{Camera, Canvas, Color},
-    render_scene, Scene,
+    render_scene_with,
+    types::Vec3,
+    RenderOutput, RenderSettings, Scene,
 };
 
 #[derive(Serialize, Deserialize)]
@@ -17,29 +22,132 @@ pub struct Request {
     pub scene: Scene,
     pub canvas: Canvas,
     pub camera: Camera,
-    pub samples_per_pixel: usize,
-    pub maximum_bounces: usize,
+    pub settings: RenderSettings,
+    /// What the client preprocessed for the integrator, so that servers do not redo it.
+    pub preprocessed: Preprocessed,
     pub range: Range<usize>,
 }
 
+/// Appends the buffer prefixed by its number of values.
+fn encode_buffer<T>(bytes: &mut Vec<u8>, buffer: &[T], encode: impl Fn(&T, &mut Vec<u8>)) {
+    bytes.extend_from_slice(&(buffer.len() as u64).to_le_bytes());
+    for value in buffer {
+        encode(value, bytes);
+    }
+}
+
+fn encode_floats(bytes: &mut Vec<u8>, floats: &[f32]) {
+    for float in floats {
+        bytes.extend_from_slice(&float.to_le_bytes());
+    }
+}
+
+/// Encodes the output of a render as it follows the pixels in responses.
+///
+/// Sample counts come first, one little-endian `u64` per pixel. Every other buffer follows in
+/// the order of the fields of the output, prefixed by its length as a `u64` and empty if it was
+/// not rendered. Floats are little-endian `f32`s, and missing ids are all ones.
+pub fn encode_output(output: &RenderOutput) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for &count in &output.sample_counts {
+        bytes.extend_from_slice(&(count as u64).to_le_bytes());
+    }
+    encode_buffer(&mut bytes, &output.depth, |&d, bytes| {
+        encode_floats(bytes, &[d])
+    });
+    encode_buffer(&mut bytes, &output.normals, |n, bytes| {
+        encode_floats(bytes, &[n.x, n.y, n.z])
+    });
+    let color = |c: &Color, bytes: &mut Vec<u8>| encode_floats(bytes, &[c.r, c.g, c.b]);
+    encode_buffer(&mut bytes, &output.albedo, color);
+    encode_buffer(&mut bytes, &output.material_ids, |id, bytes| {
+        bytes.extend_from_slice(&id.unwrap_or(u32::MAX).to_le_bytes())
+    });
+    encode_buffer(&mut bytes, &output.object_ids, |id, bytes| {
+        let bits = id.map_or(u64::MAX, ObjectId::to_bits);
+        bytes.extend_from_slice(&bits.to_le_bytes())
+    });
+    encode_buffer(&mut bytes, &output.direct, color);
+    encode_buffer(&mut bytes, &output.indirect, color);
+    bytes
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Reads a buffer written by `encode_buffer`, which is either empty or has a value per pixel.
+fn read_buffer<T>(
+    reader: &mut impl Read,
+    pixels: usize,
+    size: usize,
+    decode: impl Fn(&[u8]) -> T,
+) -> io::Result<Vec<T>> {
+    let len = read_u64(reader)?;
+    if len != 0 && len != pixels as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "buffer length does not match the pixels",
+        ));
+    }
+    let mut bytes = vec![0; len as usize * size];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes.chunks_exact(size).map(decode).collect())
+}
+
+fn floats<const N: usize>(bytes: &[u8]) -> [f32; N] {
+    std::array::from_fn(|i| f32::from_le_bytes(bytes[4 * i..4 * i + 4].try_into().unwrap()))
+}
+
+/// Reads the output of a render of `pixels` pixels, as encoded by `encode_output`.
+pub fn read_output(reader: &mut impl Read, pixels: usize) -> io::Result<RenderOutput> {
+    let sample_counts = (0..pixels)
+        .map(|_| read_u64(reader).map(|count| count as usize))
+        .collect::<io::Result<_>>()?;
+    let color = |bytes: &[u8]| {
+        let [r, g, b] = floats(bytes);
+        Color::new(r, g, b)
+    };
+    Ok(RenderOutput {
+        sample_counts,
+        depth: read_buffer(reader, pixels, 4, |bytes| floats::<1>(bytes)[0])?,
+        normals: read_buffer(reader, pixels, 12, |bytes| {
+            let [x, y, z] = floats(bytes);
+            Vec3::new(x, y, z)
+        })?,
+        albedo: read_buffer(reader, pixels, 12, color)?,
+        material_ids: read_buffer(reader, pixels, 4, |bytes| {
+            Some(u32::from_le_bytes(bytes.try_into().unwrap())).filter(|&id| id != u32::MAX)
+        })?,
+        object_ids: read_buffer(reader, pixels, 8, |bytes| {
+            ObjectId::from_bits(u64::from_le_bytes(bytes.try_into().unwrap()))
+        })?,
+        direct: read_buffer(reader, pixels, 12, color)?,
+        indirect: read_buffer(reader, pixels, 12, color)?,
+    })
+}
+
 pub struct Remote {
     pub ip: String,
     pub rows: usize,
 }
 
+/// Renders the scene across the local machine and the remotes, preprocessing it only once.
 pub fn render_scene_distributed(
     remotes: &[Remote],
     pixels: &mut [Color],
     scene: &Scene,
     canvas: &Canvas,
     camera: &Camera,
-    samples_per_pixel: usize,
-    maximum_bounces: usize,
-) {
+    settings: &RenderSettings,
+) -> RenderOutput {
     for remote in remotes {
         debug_assert!((0..(canvas.height)).contains(&remote.rows));
     }
 
+    let preprocessed = settings.integrator.preprocess(scene, settings);
     let local_rows = canvas.height - remotes.iter().map(|r| r.rows).sum::<usize>();
     let (local_pixels, mut pixels) = pixels.split_at_mut(local_rows * canvas.width);
 
@@ -47,6 +155,7 @@ pub fn render_scene_distributed(
         ip: &'a str,
         range: Range<usize>,
         pixels: &'a mut [Color],
+        output: RenderOutput,
     }
 
     let mut params = Vec::new();
@@ -58,23 +167,25 @@ pub fn render_scene_distributed(
             ip: &remote.ip,
             range: start..(start + remote.rows),
             pixels: current_pixels,
+            output: RenderOutput::default(),
         });
         start += remote.rows;
     }
 
-    std::thread::scope(|s| {
-        s.spawn(|| {
+    let mut output = std::thread::scope(|s| {
+        let local = s.spawn(|| {
             println!("rendering locally...");
-            render_scene(
+            let output = render_scene_with(
                 local_pixels,
                 scene,
+                &preprocessed,
                 canvas,
                 camera,
-                samples_per_pixel,
-                maximum_bounces,
+                settings,
                 0..local_rows,
             );
             println!("done rendering locally");
+            output
         });
 
         params.par_iter_mut().enumerate().for_each(|(i, params)| {
@@ -85,8 +196,8 @@ pub fn render_scene_distributed(
                 scene: scene.clone(),
                 canvas: canvas.clone(),
                 camera: camera.clone(),
-                samples_per_pixel,
-                maximum_bounces,
+                settings: settings.clone(),
+                preprocessed: preprocessed.clone(),
                 range: params.range.clone(),
             };
 
@@ -100,7 +211,48 @@ pub fn render_scene_distributed(
             let bytes_len = std::mem::size_of_val(params.pixels);
             let bytes = unsafe { std::slice::from_raw_parts_mut(bytes_ptr, bytes_len) };
             stream.read_exact(bytes).unwrap();
+            // the rest of the output follows the pixels
+            params.output = read_output(&mut stream, params.pixels.len()).unwrap();
             println!("got response {i}");
         });
+
+        local.join().unwrap()
     });
+
+    for params in params {
+        output.append(params.output);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outputs_survive_encoding() {
+        let output = RenderOutput {
+            sample_counts: vec![3, usize::MAX >> 1],
+            depth: vec![1.5, f32::INFINITY],
+            normals: vec![Vec3::new(0., -1., 0.), Vec3::default()],
+            albedo: Vec::new(),
+            material_ids: vec![Some(2), None],
+            object_ids: vec![Some(ObjectId::Sdf(7)), Some(ObjectId::Fog)],
+            direct: vec![Color::new(0.25, 0.5, 1.), Color::BLACK],
+            indirect: Vec::new(),
+        };
+        let bytes = encode_output(&output);
+        // counts, lengths, then 4 + 12 + 4 + 8 + 12 bytes for each pixel
+        assert_eq!(bytes.len(), 2 * 8 + 7 * 8 + 2 * 40);
+        let decoded = read_output(&mut bytes.as_slice(), 2).unwrap();
+        assert_eq!(decoded.sample_counts, output.sample_counts);
+        assert_eq!(decoded.depth, output.depth);
+        assert_eq!(decoded.normals, output.normals);
+        assert!(decoded.albedo.is_empty() && decoded.indirect.is_empty());
+        assert_eq!(decoded.material_ids, output.material_ids);
+        assert_eq!(decoded.object_ids, output.object_ids);
+        assert_eq!(decoded.direct, output.direct);
+
+        assert!(read_output(&mut bytes.as_slice(), 3).is_err());
+    }
 }
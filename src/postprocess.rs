@@ -0,0 +1,238 @@
+use rayon::prelude::{IndexedParallelIterator, ParallelIterator, ParallelSliceMut};
+use serde::{Deserialize, Serialize};
+
+use crate::math::dot;
+use crate::render::{Canvas, Color};
+use crate::types::Vec3;
+use crate::RenderOutput;
+
+/// Luminance under which pixels are compared to this luminance instead, so that noise in dark
+/// regions is not taken for outliers.
+const DARK_LUMINANCE: f32 = 0.05;
+
+/// Replaces pixels more than `threshold` times as bright as the median of their neighbors by
+/// that median neighbor, removing isolated bright pixels that clamping missed.
+///
+/// Features a single pixel wide, like small highlights, can be removed as well.
+pub fn reject_outliers(pixels: &mut [Color], canvas: &Canvas, threshold: f32) {
+    let (width, height) = (canvas.width, canvas.height);
+    let original = pixels.to_vec();
+    let mut neighbors = Vec::with_capacity(8);
+    for y in 0..height {
+        for x in 0..width {
+            neighbors.clear();
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    if (nx, ny) != (x, y) {
+                        neighbors.push(&original[ny * width + nx]);
+                    }
+                }
+            }
+            if neighbors.is_empty() {
+                continue;
+            }
+            neighbors.sort_by(|a, b| a.luminance().total_cmp(&b.luminance()));
+            let median = neighbors[neighbors.len() / 2];
+            let luminance = original[y * width + x].luminance();
+            if luminance > threshold * median.luminance().max(DARK_LUMINANCE) {
+                pixels[y * width + x] = median.clone();
+            }
+        }
+    }
+}
+
+/// Joint bilateral filter guided by the albedo and normal buffers of a render, which stay sharp
+/// at low sample counts unlike the colors.
+///
+/// The lighting, i.e. the colors divided by the albedo, is filtered rather than the colors so
+/// that textures are kept.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Denoiser {
+    /// Radius of the filter, in pixels.
+    pub radius: usize,
+    /// Standard deviation of the weights along the distance to the pixel, in pixels.
+    pub spatial_sigma: f32,
+    /// Standard deviation of the weights along differences of lighting, relative to the
+    /// lighting of the pixel. The lighting is blurred beforehand so that noise weighs less.
+    pub color_sigma: f32,
+    /// Standard deviation of the weights along differences of albedo.
+    pub albedo_sigma: f32,
+    /// Standard deviation of the weights along differences of normals.
+    pub normal_sigma: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            radius: 6,
+            spatial_sigma: 3.,
+            color_sigma: 1.,
+            albedo_sigma: 0.1,
+            normal_sigma: 0.3,
+        }
+    }
+}
+
+/// Albedo under which colors are not divided by it, as they carry too little information.
+const MINIMUM_ALBEDO: f32 = 0.01;
+
+fn demodulate(color: f32, albedo: f32) -> f32 {
+    if albedo > MINIMUM_ALBEDO {
+        color / albedo
+    } else {
+        color
+    }
+}
+
+fn remodulate(lighting: f32, albedo: f32) -> f32 {
+    if albedo > MINIMUM_ALBEDO {
+        lighting * albedo
+    } else {
+        lighting
+    }
+}
+
+fn squared_distance(a: &Color, b: &Color) -> f32 {
+    (a.r - b.r).powi(2) + (a.g - b.g).powi(2) + (a.b - b.b).powi(2)
+}
+
+impl Denoiser {
+    /// Denoises the pixels of a whole canvas, using the feature buffers of the output if it has
+    /// them.
+    pub fn denoise(&self, pixels: &mut [Color], output: &RenderOutput, canvas: &Canvas) {
+        let (width, height) = (canvas.width, canvas.height);
+        let white = Color::WHITE;
+        let albedo = |i: usize| output.albedo.get(i).unwrap_or(&white);
+        let zero = Vec3::default();
+        let normal = |i: usize| output.normals.get(i).unwrap_or(&zero);
+
+        let lighting: Vec<_> = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, color)| {
+                let albedo = albedo(i);
+                Color::new(
+                    demodulate(color.r, albedo.r),
+                    demodulate(color.g, albedo.g),
+                    demodulate(color.b, albedo.b),
+                )
+            })
+            .collect();
+        let mut guide = vec![Color::BLACK; lighting.len()];
+        for y in 0..height {
+            for x in 0..width {
+                let (mut sum, mut count) = (Color::BLACK, 0.);
+                for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                        sum = sum + lighting[ny * width + nx].clone();
+                        count += 1.;
+                    }
+                }
+                guide[y * width + x] = sum / count;
+            }
+        }
+
+        let spatial = -0.5 / (self.spatial_sigma * self.spatial_sigma);
+        let albedo_weight = -0.5 / (self.albedo_sigma * self.albedo_sigma);
+        let normal_weight = -0.5 / (self.normal_sigma * self.normal_sigma);
+        let radius = self.radius;
+        pixels
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    let i = y * width + x;
+                    let scale = guide[i].luminance().max(0.) + 0.1;
+                    let color_weight = -0.5 / (self.color_sigma * self.color_sigma * scale * scale);
+                    let (mut sum, mut total) = (Color::BLACK, 0.);
+                    for ny in y.saturating_sub(radius)..(y + radius + 1).min(height) {
+                        for nx in x.saturating_sub(radius)..(x + radius + 1).min(width) {
+                            let j = ny * width + nx;
+                            let (dx, dy) = (nx as f32 - x as f32, ny as f32 - y as f32);
+                            let normal_difference = normal(i) - normal(j);
+                            let exponent = spatial * (dx * dx + dy * dy)
+                                + albedo_weight * squared_distance(albedo(i), albedo(j))
+                                + normal_weight * dot(&normal_difference, &normal_difference)
+                                + color_weight * squared_distance(&guide[i], &guide[j]);
+                            let weight = exponent.exp();
+                            sum = sum + weight * &lighting[j];
+                            total += weight;
+                        }
+                    }
+                    let filtered = sum / total;
+                    let albedo = albedo(i);
+                    *pixel = Color::new(
+                        remodulate(filtered.r, albedo.r),
+                        remodulate(filtered.g, albedo.g),
+                        remodulate(filtered.b, albedo.b),
+                    );
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn isolated_pixels_are_rejected() {
+        let canvas = Canvas {
+            width: 6,
+            height: 4,
+        };
+        // the left half is dim, the right half bright
+        let mut pixels: Vec<_> = (0..canvas.width * canvas.height)
+            .map(|i| Color::grey(if i % canvas.width < 3 { 0.2 } else { 2. }))
+            .collect();
+        pixels[canvas.width + 1] = Color::new(50., 40., 30.);
+        let expected = {
+            let mut expected = pixels.clone();
+            expected[canvas.width + 1] = Color::grey(0.2);
+            expected
+        };
+
+        reject_outliers(&mut pixels, &canvas, 4.);
+        assert_eq!(pixels, expected);
+    }
+
+    #[test]
+    fn denoising_keeps_albedo_edges() {
+        let canvas = Canvas {
+            width: 32,
+            height: 16,
+        };
+        let count = canvas.width * canvas.height;
+        let albedo = |i: usize| {
+            if i % canvas.width < canvas.width / 2 {
+                Color::new(0.8, 0.2, 0.2)
+            } else {
+                Color::new(0.2, 0.2, 0.8)
+            }
+        };
+        let output = RenderOutput {
+            sample_counts: vec![1; count],
+            albedo: (0..count).map(albedo).collect(),
+            normals: vec![Vec3::new(0., 0., 1.); count],
+            ..RenderOutput::default()
+        };
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut pixels: Vec<_> = (0..count)
+            .map(|i| (2. * rng.gen::<f32>()) * albedo(i))
+            .collect();
+
+        let error = |pixels: &[Color]| -> f32 {
+            (0..count)
+                .map(|i| squared_distance(&pixels[i], &albedo(i)))
+                .sum::<f32>()
+                / count as f32
+        };
+        let noisy = error(&pixels);
+        Denoiser::default().denoise(&mut pixels, &output, &canvas);
+        let denoised = error(&pixels);
+        assert!(denoised < 0.1 * noisy, "{} {}", noisy, denoised);
+    }
+}
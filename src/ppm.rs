@@ -1,6 +1,6 @@
 use crate::render::{Canvas, Color};
 
-use std::io::Write;
+use std::io::{BufRead, Error, ErrorKind, Read, Write};
 
 pub struct PpmWriter<W: Write> {
     writer: W,
@@ -34,3 +34,107 @@ impl<W: Write> PpmWriter<W> {
         )
     }
 }
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Reads the next whitespace-separated header token, skipping comments.
+fn read_token<R: BufRead>(reader: &mut R) -> Result<String, Error> {
+    let mut token = String::new();
+    let mut byte = [0u8; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        match byte[0] {
+            b'#' => {
+                let mut comment = Vec::new();
+                reader.read_until(b'\n', &mut comment)?;
+            }
+            b if b.is_ascii_whitespace() => {
+                if !token.is_empty() {
+                    return Ok(token);
+                }
+            }
+            b => token.push(b as char),
+        }
+    }
+}
+
+fn read_number<R: BufRead>(reader: &mut R) -> Result<usize, Error> {
+    read_token(reader)?
+        .parse()
+        .map_err(|_| invalid_data("invalid number in PPM file"))
+}
+
+/// Reads a plain (P3) or binary (P6) PPM image, returning its width, height and pixels from
+/// top to bottom.
+pub fn read_ppm<R: BufRead>(mut reader: R) -> Result<(usize, usize, Vec<Color>), Error> {
+    let magic = read_token(&mut reader)?;
+    let width = read_number(&mut reader)?;
+    let height = read_number(&mut reader)?;
+    let max_value = read_number(&mut reader)?;
+    if max_value == 0 || max_value > 65535 {
+        return Err(invalid_data("invalid PPM maximum value"));
+    }
+
+    let bytes_per_value = if max_value < 256 { 1 } else { 2 };
+    let count = (width.checked_mul(height))
+        .and_then(|pixels| pixels.checked_mul(3 * bytes_per_value))
+        .ok_or_else(|| invalid_data("PPM image too large"))?
+        / bytes_per_value;
+    let values: Vec<usize> = match magic.as_str() {
+        "P3" => (0..count)
+            .map(|_| read_number(&mut reader))
+            .collect::<Result<_, _>>()?,
+        "P6" => {
+            // only allocate for the data that is actually there
+            let mut bytes = Vec::new();
+            let len = bytes_per_value * count;
+            reader.by_ref().take(len as u64).read_to_end(&mut bytes)?;
+            if bytes.len() != len {
+                return Err(invalid_data("truncated PPM data"));
+            }
+            bytes
+                .chunks_exact(bytes_per_value)
+                .map(|b| {
+                    b.iter()
+                        .fold(0, |value, byte| (value << 8) | *byte as usize)
+                })
+                .collect()
+        }
+        _ => return Err(invalid_data("unsupported PPM format")),
+    };
+
+    let scale = 1. / max_value as f32;
+    let pixels = values
+        .chunks_exact(3)
+        .map(|c| {
+            Color::new(
+                c[0] as f32 * scale,
+                c[1] as f32 * scale,
+                c[2] as f32 * scale,
+            )
+        })
+        .collect();
+    Ok((width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_bad_sizes() {
+        let (width, height, pixels) = read_ppm(&b"P3 2 1 255 255 0 0 0 0 255\n"[..]).unwrap();
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(pixels[1], Color::new(0., 0., 1.));
+
+        let huge = format!("P6 {} {} 255\n\0\0\0", usize::MAX, 2);
+        let error = read_ppm(huge.as_bytes()).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let truncated = read_ppm(&b"P6 1000000 1000000 255\n\0\0\0"[..])
+            .err()
+            .unwrap();
+        assert_eq!(truncated.kind(), ErrorKind::InvalidData);
+    }
+}
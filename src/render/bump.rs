@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+
+use crate::math::{cross, dot};
+use crate::render::noise::fbm;
+use crate::render::{Hit, Texture, Wrap};
+use crate::types::{Normal, Point, UnitVec3, Vec3};
+
+const OCTAVES: usize = 4;
+
+/// Perturbation of the shading normal of a surface.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Bump {
+    /// Tangent-space normal map, with X along the tangent and Z along the normal.
+    NormalMap {
+        texture: Texture,
+        wrap: Wrap,
+        strength: f32,
+    },
+    /// Procedural bumps of about `scale` wide, following fractal noise.
+    Noise { scale: f32, strength: f32 },
+}
+
+impl Bump {
+    /// Perturbed version of the `outward` normal of the hit.
+    ///
+    /// The whole tangent frame is flipped with the normal on the inside of surfaces, so that
+    /// both sides see the same bumps.
+    pub fn shading_normal(&self, hit: &Hit) -> UnitVec3 {
+        let side = match hit.normal {
+            Normal::Inward(_) => -1.,
+            Normal::Outward(_) => 1.,
+        };
+        let normal = side * hit.normal.outward().get();
+        let n = &normal;
+        // the tangent isn't necessarily orthogonal to normals flipped or perturbed by the caller
+        let t = hit.tangent.get();
+        let tangent = (t - dot(t, n) * n).unit();
+        let bitangent = cross(n, tangent.get());
+
+        let perturbed = match self {
+            Bump::NormalMap {
+                texture,
+                wrap,
+                strength,
+            } => {
+                let c = texture.sample(hit.u, hit.v, *wrap);
+                let (x, y, z) = (2. * c.r - 1., 2. * c.g - 1., 2. * c.b - 1.);
+                strength * x * tangent.get() + strength * y * &bitangent + z.max(1e-3) * n
+            }
+            Bump::Noise { scale, strength } => {
+                let p = &hit.point;
+                let height = |dx: f32, dy: f32, dz: f32| {
+                    let q = Point::new(p.x / scale + dx, p.y / scale + dy, p.z / scale + dz);
+                    fbm(&q, OCTAVES)
+                };
+                let h = 1e-3;
+                let gradient = (strength / (2. * h))
+                    * Vec3::new(
+                        height(h, 0., 0.) - height(-h, 0., 0.),
+                        height(0., h, 0.) - height(0., -h, 0.),
+                        height(0., 0., h) - height(0., 0., -h),
+                    );
+                let surface_gradient = &gradient - dot(&gradient, n) * n;
+                n - surface_gradient
+            }
+        };
+        (side * perturbed).unit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{Color, Colorer, Material};
+
+    #[test]
+    fn both_sides_see_the_same_bumps() {
+        let path = std::env::temp_dir().join("keyell_bump_normal_map.ppm");
+        std::fs::write(&path, "P3 1 1 255 230 80 200\n").unwrap();
+        let bumps = [
+            Bump::NormalMap {
+                texture: Texture::load(path.to_str().unwrap()).unwrap(),
+                wrap: Wrap::Repeat,
+                strength: 1.,
+            },
+            Bump::Noise {
+                scale: 0.3,
+                strength: 0.5,
+            },
+        ];
+
+        let material = Material::Diffuse(Colorer::Solid(Color::WHITE));
+        let geometric = Vec3::new(0., 0.6, 0.8).unit();
+        let hit = |normal| Hit {
+            travel: 1.,
+            distance: 1.,
+            point: Point::new(0.1, 0.2, 0.3),
+            normal,
+            material: &material,
+            u: 0.5,
+            v: 0.5,
+            tangent: UnitVec3::unchecked_from(&Vec3::new(1., 0., 0.)),
+        };
+        for bump in &bumps {
+            let outside = bump.shading_normal(&hit(Normal::Outward(geometric.clone())));
+            let inside = bump.shading_normal(&hit(Normal::Inward(geometric.clone())));
+            let sum = outside.get() + inside.get();
+            assert!(sum.len() < 1e-5, "{:?} {:?}", outside.get(), inside.get());
+            assert!(dot(outside.get(), geometric.get()) < 0.99);
+        }
+    }
+}
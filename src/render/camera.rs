@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::math::deg_to_radians;
+use crate::math::{cross, deg_to_radians, dot};
 use crate::render::Ray;
 use crate::types::{Point, Vec3};
 
@@ -46,4 +46,19 @@ impl Camera {
             direction: &self.to_lower_left_corner + u * &self.horizontal + v * &self.vertical,
         }
     }
+
+    /// Finds the coordinates `(u, v, travel)` of the ray from `get_ray` which reaches `point`,
+    /// if it is in front of the camera.
+    pub fn project(&self, point: &Point) -> Option<(f32, f32, f32)> {
+        let normal = cross(&self.horizontal, &self.vertical);
+        let to_point = point - &self.position;
+        let travel = dot(&to_point, &normal) / dot(&self.to_lower_left_corner, &normal);
+        if travel <= 0. {
+            return None;
+        }
+        let on_plane = to_point / travel - &self.to_lower_left_corner;
+        let u = dot(&on_plane, &self.horizontal) / dot(&self.horizontal, &self.horizontal);
+        let v = dot(&on_plane, &self.vertical) / dot(&self.vertical, &self.vertical);
+        Some((u, v, travel))
+    }
 }
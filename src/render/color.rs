@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 #[repr(C)]
 pub struct Color {
     pub r: f32,
@@ -24,6 +24,65 @@ impl Color {
         }
     }
 
+    /// Perceived brightness of the color, using the Rec. 709 weights.
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    /// Color of the light emitted by a blackbody at the given temperature in kelvins, with unit
+    /// luminance.
+    pub fn blackbody(temperature: f32) -> Self {
+        // Wyman et al., "Simple Analytic Approximations to the CIE XYZ Color Matching Functions"
+        let lobe = |lambda: f32, mean: f32, below: f32, above: f32| {
+            let deviation = if lambda < mean { below } else { above };
+            (-0.5 * ((lambda - mean) / deviation).powi(2)).exp()
+        };
+        let (mut x, mut y, mut z) = (0., 0., 0.);
+        for nanometers in (380..=780).step_by(5) {
+            let lambda = nanometers as f32;
+            // Planck's law, with the wavelength in micrometers
+            let micrometers = lambda / 1000.;
+            let radiance =
+                1. / (micrometers.powi(5) * ((14388. / (micrometers * temperature)).exp() - 1.));
+            x += radiance
+                * (1.056 * lobe(lambda, 599.8, 37.9, 31.0)
+                    + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+                    - 0.065 * lobe(lambda, 501.1, 20.4, 26.2));
+            y += radiance
+                * (0.821 * lobe(lambda, 568.8, 46.9, 40.5)
+                    + 0.286 * lobe(lambda, 530.9, 16.3, 31.1));
+            z += radiance
+                * (1.217 * lobe(lambda, 437.0, 11.8, 36.0)
+                    + 0.681 * lobe(lambda, 459.0, 26.0, 13.8));
+        }
+        Self::from_xyz(x / y, 1., z / y)
+    }
+
+    /// Converts CIE XYZ coordinates to linear sRGB, clamping out of gamut colors.
+    pub fn from_xyz(x: f32, y: f32, z: f32) -> Self {
+        Self::new(
+            (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.),
+            (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.),
+            (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.),
+        )
+    }
+
+    /// Color of `t` in [0, 1] on a black, red, yellow and white ramp.
+    pub fn heatmap(t: f32) -> Self {
+        let t = 3. * t.clamp(0., 1.);
+        Self::new(t.min(1.), (t - 1.).clamp(0., 1.), (t - 2.).clamp(0., 1.))
+    }
+
+    /// Scales the color down so that none of its components exceeds `maximum`, keeping its hue.
+    pub fn limited(self, maximum: f32) -> Self {
+        let largest = self.r.max(self.g).max(self.b);
+        if largest > maximum {
+            (maximum / largest) * self
+        } else {
+            self
+        }
+    }
+
     pub fn random() -> Self {
         Self {
             r: rand::random(),
@@ -88,3 +147,26 @@ impl std::ops::Div<f32> for Color {
         (1. / rhs) * self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blackbody_colors() {
+        // the temperature of daylight is close to white
+        let daylight = Color::blackbody(6500.);
+        assert!((daylight.r - 1.).abs() < 0.05);
+        assert!((daylight.g - 1.).abs() < 0.05);
+        assert!((daylight.b - 1.).abs() < 0.05);
+
+        let candle = Color::blackbody(1900.);
+        assert!(candle.r > candle.g && candle.g > candle.b);
+        let sky = Color::blackbody(10000.);
+        assert!(sky.b > sky.g && sky.g > sky.r);
+
+        for temperature in [1900., 3000., 10000.] {
+            assert!((Color::blackbody(temperature).luminance() - 1.).abs() < 1e-3);
+        }
+    }
+}
@@ -3,15 +3,62 @@ use std::f32::consts::PI;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::render::noise::{fbm, turbulence};
 use crate::render::Color;
 use crate::render::Hit;
-use crate::types::Normal;
+use crate::render::{Environment, Sky, Texture, Wrap};
+use crate::types::{Normal, Point};
 
 #[derive(Clone, Serialize, Deserialize)]
 pub enum Colorer {
-    ZGradient { bottom: Color, top: Color },
+    ZGradient {
+        bottom: Color,
+        top: Color,
+    },
     Solid(Color),
     Bubblegum,
+    /// 3D checkerboard made of cubes of side `scale`.
+    Checker {
+        even: Color,
+        odd: Color,
+        scale: f32,
+    },
+    /// Fractal noise blending two colors, features are about `scale` wide.
+    Noise {
+        low: Color,
+        high: Color,
+        scale: f32,
+        octaves: usize,
+    },
+    /// Veins along the X axis, distorted by turbulence.
+    Marble {
+        base: Color,
+        vein: Color,
+        scale: f32,
+        turbulence: f32,
+    },
+    /// Concentric rings around the Z axis, `scale` apart and distorted by noise.
+    Wood {
+        light: Color,
+        dark: Color,
+        scale: f32,
+        turbulence: f32,
+    },
+    /// Image mapped with the surface coordinates of the hit.
+    Image {
+        texture: Texture,
+        wrap: Wrap,
+    },
+    /// Environment map looked up in the direction of the normal, meant for backgrounds.
+    Environment(Environment),
+    /// Daylight sky looked up in the direction of the normal, meant for backgrounds.
+    Sky(Sky),
+}
+
+const OCTAVES: usize = 6;
+
+fn scaled(point: &Point, scale: f32) -> Point {
+    Point::new(point.x / scale, point.y / scale, point.z / scale)
 }
 
 impl Colorer {
@@ -29,6 +76,50 @@ impl Colorer {
                 let f = |coord: f32| (PI * coord).sin() + 1.;
                 0.5 * Color::new(f(n.get().x), f(n.get().y), f(n.get().z))
             }
+            Colorer::Checker { even, odd, scale } => {
+                // the offset avoids flickering on axis-aligned surfaces at integer coordinates
+                let p = scaled(&hit.point, *scale);
+                let cell = |coord: f32| (coord + 1e-4).floor() as i64;
+                if (cell(p.x) + cell(p.y) + cell(p.z)) % 2 == 0 {
+                    even.clone()
+                } else {
+                    odd.clone()
+                }
+            }
+            Colorer::Noise {
+                low,
+                high,
+                scale,
+                octaves,
+            } => {
+                let t = (0.5 * (fbm(&scaled(&hit.point, *scale), *octaves) + 1.)).clamp(0., 1.);
+                t * high + (1. - t) * low
+            }
+            Colorer::Marble {
+                base,
+                vein,
+                scale,
+                turbulence: strength,
+            } => {
+                let p = scaled(&hit.point, *scale);
+                let phase = PI * p.x + 10. * strength * turbulence(&p, OCTAVES);
+                let t = 0.5 * (phase.sin() + 1.);
+                t * base + (1. - t) * vein
+            }
+            Colorer::Wood {
+                light,
+                dark,
+                scale,
+                turbulence: strength,
+            } => {
+                let p = scaled(&hit.point, *scale);
+                let distance = (p.x.powi(2) + p.y.powi(2)).sqrt() + strength * fbm(&p, OCTAVES);
+                let t = 0.5 * ((2. * PI * distance).sin() + 1.);
+                t.powi(3) * dark + (1. - t.powi(3)) * light
+            }
+            Colorer::Image { texture, wrap } => texture.sample(hit.u, hit.v, *wrap),
+            Colorer::Environment(environment) => environment.color(hit.normal.outward().get()),
+            Colorer::Sky(sky) => sky.color(hit.normal.outward().get()),
         }
     }
 }
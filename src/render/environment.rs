@@ -0,0 +1,166 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::math::deg_to_radians;
+use crate::render::hittable::spherical_uv;
+use crate::render::{Color, Sampler, Texture, Wrap};
+use crate::sampling::Distribution2D;
+use crate::types::Vec3;
+
+/// Equirectangular image surrounding the scene, e.g. a high dynamic range capture of real
+/// surroundings, whose bright regions can be importance sampled.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "EnvironmentSettings", into = "EnvironmentSettings")]
+pub struct Environment {
+    texture: Texture,
+    /// Rotation around the Z axis, in degrees.
+    pub rotation: f32,
+    /// Distribution of texels proportional to their luminance and to the solid angle they cover.
+    distribution: Arc<Distribution2D>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EnvironmentSettings {
+    texture: Texture,
+    rotation: f32,
+}
+
+impl From<EnvironmentSettings> for Environment {
+    fn from(settings: EnvironmentSettings) -> Self {
+        Self::new(settings.texture, settings.rotation)
+    }
+}
+
+impl From<Environment> for EnvironmentSettings {
+    fn from(environment: Environment) -> Self {
+        Self {
+            texture: environment.texture,
+            rotation: environment.rotation,
+        }
+    }
+}
+
+fn rotate(direction: &Vec3, degrees: f32) -> Vec3 {
+    let (sin, cos) = deg_to_radians(degrees).sin_cos();
+    Vec3::new(
+        cos * direction.x - sin * direction.y,
+        sin * direction.x + cos * direction.y,
+        direction.z,
+    )
+}
+
+impl Environment {
+    pub fn new(texture: Texture, rotation: f32) -> Self {
+        let (width, height) = (texture.width(), texture.height());
+        let mut function = Vec::with_capacity(width * height);
+        for y in 0..height {
+            // rows near the poles cover smaller solid angles
+            let latitude = (0.5 - (y as f32 + 0.5) / height as f32) * PI;
+            for x in 0..width {
+                let luminance = texture.texel_at(x, y).luminance().max(0.);
+                function.push(luminance * latitude.cos());
+            }
+        }
+        Self {
+            distribution: Arc::new(Distribution2D::new(&function, width)),
+            texture,
+            rotation,
+        }
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// Color of the environment in the given unit direction.
+    pub fn color(&self, direction: &Vec3) -> Color {
+        let (u, v) = spherical_uv(&rotate(direction, -self.rotation));
+        self.texture.sample(u, v, Wrap::Repeat)
+    }
+
+    /// Samples a unit direction proportionally to the brightness of the environment, along with
+    /// its density.
+    pub fn sample(&self, sampler: &mut dyn Sampler) -> Option<(Vec3, f32)> {
+        let ((u, y), pdf) = self.distribution.sample(sampler.get_2d());
+        // rows go from the top, unlike `v`
+        let latitude = (0.5 - y) * PI;
+        let longitude = (u - 0.5) * 2. * PI;
+        let cos_latitude = latitude.cos();
+        if pdf <= 0. || cos_latitude <= 0. {
+            return None;
+        }
+        let direction = Vec3::new(
+            cos_latitude * longitude.cos(),
+            cos_latitude * longitude.sin(),
+            latitude.sin(),
+        );
+        // the image spans 2π radians of longitude and π of latitude
+        let pdf = pdf / (2. * PI * PI * cos_latitude);
+        Some((rotate(&direction, self.rotation), pdf))
+    }
+
+    /// Density of sampling the given unit direction.
+    pub fn pdf(&self, direction: &Vec3) -> f32 {
+        let local = rotate(direction, -self.rotation);
+        let cos_latitude = (1. - local.z * local.z).max(0.).sqrt();
+        if cos_latitude <= 0. {
+            return 0.;
+        }
+        let (u, v) = spherical_uv(&local);
+        self.distribution.pdf(u, 1. - v) / (2. * PI * PI * cos_latitude)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::sampling::{uniform_sphere, UNIFORM_SPHERE_PDF};
+
+    /// Dim environment with a small bright spot, written as a flat Radiance file.
+    fn sun_texture() -> Texture {
+        let (width, height) = (16, 8);
+        let mut file = format!("#?RADIANCE\n\n-Y {} +X {}\n", height, width).into_bytes();
+        for y in 0..height {
+            for x in 0..width {
+                let exponent = if (x, y) == (5, 2) { 140 } else { 128 };
+                file.extend([128, 100, 50, exponent]);
+            }
+        }
+        let path = std::env::temp_dir().join("keyell_environment_test.hdr");
+        File::create(&path).unwrap().write_all(&file).unwrap();
+        Texture::load(path.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn sample_matches_pdf() {
+        let environment = Environment::new(sun_texture(), 30.);
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        for _ in 0..10_000 {
+            let (direction, pdf) = environment.sample(&mut rng).unwrap();
+            assert!((direction.len() - 1.).abs() < 1e-4);
+            let expected = environment.pdf(&direction);
+            assert!(
+                (pdf - expected).abs() < 1e-3 * expected,
+                "{} != {}",
+                pdf,
+                expected
+            );
+        }
+
+        let samples = 100_000;
+        let integral: f32 = (0..samples)
+            .map(|_| environment.pdf(&uniform_sphere(&mut rng)) / UNIFORM_SPHERE_PDF)
+            .sum::<f32>()
+            / samples as f32;
+        assert!((integral - 1.).abs() < 0.05, "{}", integral);
+    }
+}
@@ -0,0 +1,118 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+/// Reconstruction filter weighting the samples that fall within its radius of a pixel center, in
+/// pixels, along both axes.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Filter {
+    /// Averages the samples equally, a radius of half a pixel keeping them to their own pixel.
+    Box { radius: f32 },
+    /// Weights decrease linearly away from the center.
+    Tent { radius: f32 },
+    /// Gaussian of the given standard deviation, shifted to reach zero at the radius.
+    Gaussian { radius: f32, sigma: f32 },
+    /// Mitchell-Netravali cubic, trading blurring with `b` for ringing with `c`.
+    Mitchell { radius: f32, b: f32, c: f32 },
+    /// Sinc windowed by a wider sinc, the sharpest of the filters but prone to ringing.
+    Lanczos { radius: f32 },
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-5 {
+        1.
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+impl Filter {
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius } => radius,
+        }
+    }
+
+    /// Weight of a sample at the given offset from the center, which can be negative.
+    pub fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        if x > self.radius() {
+            return 0.;
+        }
+        match *self {
+            Filter::Box { .. } => 1.,
+            Filter::Tent { radius } => radius - x,
+            Filter::Gaussian { radius, sigma } => {
+                let gaussian = |x: f32| (-x * x / (2. * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.)
+            }
+            Filter::Mitchell { radius, b, c } => {
+                let x = 2. * x / radius;
+                if x > 1. {
+                    ((-b - 6. * c) * x.powi(3)
+                        + (6. * b + 30. * c) * x.powi(2)
+                        + (-12. * b - 48. * c) * x
+                        + (8. * b + 24. * c))
+                        / 6.
+                } else {
+                    ((12. - 9. * b - 6. * c) * x.powi(3)
+                        + (-18. + 12. * b + 6. * c) * x.powi(2)
+                        + (6. - 2. * b))
+                        / 6.
+                }
+            }
+            Filter::Lanczos { radius } => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partitions_of_unity() {
+        let filters = [
+            Filter::Tent { radius: 1. },
+            Filter::Mitchell {
+                radius: 2.,
+                b: 1. / 3.,
+                c: 1. / 3.,
+            },
+        ];
+        for filter in filters {
+            for offset in [0., 0.1, 0.25, 0.5, 0.9] {
+                let sum: f32 = (-3..=3)
+                    .map(|k| filter.evaluate_1d(offset + k as f32))
+                    .sum();
+                assert!((sum - 1.).abs() < 1e-4, "{:?} {} {}", filter, offset, sum);
+            }
+        }
+
+        for filter in [
+            Filter::default(),
+            Filter::Gaussian {
+                radius: 1.5,
+                sigma: 0.5,
+            },
+            Filter::Lanczos { radius: 2. },
+        ] {
+            assert!(filter.evaluate(0., 0.) > 0.);
+            assert_eq!(filter.evaluate(filter.radius() + 0.01, 0.), 0.);
+        }
+    }
+}
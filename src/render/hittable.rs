@@ -1,20 +1,46 @@
-use std::f32::{EPSILON, INFINITY};
+use std::f32::consts::PI;
 
-use crate::math::dot;
-use crate::render::{Material, Ray};
-use crate::types::{Normal, Point, UnitVec3};
+use crate::math::{dot, orthonormal_basis};
+use crate::render::{Color, Colorer, Environment, Light, Material, Ray, Sampler};
+use crate::types::{Normal, Point, UnitVec3, Vec3};
 
 use serde::{Deserialize, Serialize};
 
 pub struct Hit<'a> {
+    /// Parameter of the ray at the hit, which scales with the length of its direction.
     pub travel: f32,
+    /// Distance along the ray from its origin to the hit.
+    pub distance: f32,
     pub point: Point,
     pub normal: Normal,
     pub material: &'a Material,
+    /// Surface coordinates, usually in [0, 1] but unbounded for infinite surfaces.
+    pub u: f32,
+    pub v: f32,
+    /// Direction in which `u` increases along the surface.
+    pub tangent: UnitVec3,
+}
+
+/// Latitude-longitude coordinates of a unit direction, with the poles along the Z axis.
+pub fn spherical_uv(direction: &Vec3) -> (f32, f32) {
+    let u = 0.5 + direction.y.atan2(direction.x) / (2. * PI);
+    let v = 0.5 + direction.z.clamp(-1., 1.).asin() / PI;
+    (u, v)
+}
+
+/// Direction in which the `u` coordinate of `spherical_uv` increases.
+pub fn spherical_tangent(direction: &Vec3) -> UnitVec3 {
+    let tangent = Vec3::new(-direction.y, direction.x, 0.);
+    if tangent.len() > 1e-6 {
+        tangent.unit()
+    } else {
+        // the poles have no natural tangent
+        UnitVec3::unchecked_from(&orthonormal_basis(direction).0)
+    }
 }
 
 pub trait Hittable {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit>;
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>>;
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -24,8 +50,32 @@ pub struct Sphere {
     pub material: Material,
 }
 
+impl Sphere {
+    /// Hit at a point of the surface, by a ray going along `direction`.
+    pub fn hit_at(&self, point: Point, direction: &Vec3, travel: f32) -> Hit<'_> {
+        let normal_vec = (&point - &self.center) / self.radius;
+        let (u, v) = spherical_uv(&normal_vec);
+        let tangent = spherical_tangent(&normal_vec);
+        let normal = if dot(direction, &normal_vec) > 0. {
+            Normal::Inward(UnitVec3::unchecked_from(&normal_vec))
+        } else {
+            Normal::Outward(UnitVec3::unchecked_from(&normal_vec))
+        };
+        Hit {
+            travel,
+            distance: travel * direction.len(),
+            normal,
+            point,
+            material: &self.material,
+            u,
+            v,
+            tangent,
+        }
+    }
+}
+
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
         let oc = &ray.origin - &self.center;
         let a = dot(&ray.direction, &ray.direction);
         let half_b = dot(&oc, &ray.direction);
@@ -37,22 +87,7 @@ impl Hittable for Sphere {
             return None;
         }
 
-        // computes useful useful values about the hit
-        let compute_hit = |travel: f32| {
-            let point = ray.at(travel);
-            let normal_vec = (&point - &self.center) / self.radius;
-            let normal = if dot(&ray.direction, &normal_vec) > 0. {
-                Normal::Inward(UnitVec3::unchecked_from(&normal_vec))
-            } else {
-                Normal::Outward(UnitVec3::unchecked_from(&normal_vec))
-            };
-            Some(Hit {
-                travel,
-                normal,
-                point,
-                material: &self.material,
-            })
-        };
+        let compute_hit = |travel: f32| Some(self.hit_at(ray.at(travel), &ray.direction, travel));
 
         // try first solution
         let t = (-half_b - disc.sqrt()) / a;
@@ -76,13 +111,19 @@ pub struct Background {
 }
 
 impl Hittable for Background {
-    fn hit(&self, ray: &Ray, _t_min: f32, t_max: f32) -> Option<Hit> {
-        if t_max == INFINITY {
+    fn hit(&self, ray: &Ray, _t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        if t_max == f32::INFINITY {
+            let direction = ray.direction.unit();
+            let (u, v) = spherical_uv(direction.get());
             Some(Hit {
                 travel: t_max,
+                distance: t_max,
                 point: ray.at(t_max),
                 material: &self.material,
-                normal: Normal::Inward((-&ray.direction).unit()),
+                normal: Normal::Inward(-&direction),
+                u,
+                v,
+                tangent: spherical_tangent(direction.get()),
             })
         } else {
             None
@@ -90,6 +131,60 @@ impl Hittable for Background {
     }
 }
 
+impl Default for Background {
+    /// Black background, emitting no light.
+    fn default() -> Self {
+        Self {
+            material: Material::light(Colorer::Solid(Color::BLACK)),
+        }
+    }
+}
+
+impl Background {
+    /// Environment map of the background, if it can be importance sampled.
+    fn environment(&self) -> Option<&Environment> {
+        match &self.material {
+            Material::Light {
+                colorer: Colorer::Environment(environment),
+                temperature: None,
+                ..
+            } => Some(environment),
+            _ => None,
+        }
+    }
+
+    /// Samples a unit direction towards the bright regions of the background, along with its
+    /// density, if the background can be importance sampled.
+    pub fn sample(&self, sampler: &mut dyn Sampler) -> Option<(Vec3, f32)> {
+        self.environment()?.sample(sampler)
+    }
+
+    /// Density of sampling the given unit direction with `sample`.
+    pub fn pdf(&self, direction: &Vec3) -> f32 {
+        self.environment()
+            .map_or(0., |environment| environment.pdf(direction))
+    }
+
+    /// Sun matching a sky background, as bright as the sky relative to its intensity.
+    pub fn sun(&self) -> Option<Light> {
+        match &self.material {
+            Material::Light {
+                colorer: Colorer::Sky(sky),
+                intensity: sky_intensity,
+                temperature: None,
+                ..
+            } => {
+                let mut sun = sky.sun()?;
+                if let Light::Directional { intensity, .. } = &mut sun {
+                    *intensity *= sky_intensity;
+                }
+                Some(sun)
+            }
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Plane {
     pub point: Point,
@@ -98,10 +193,10 @@ pub struct Plane {
 }
 
 impl Hittable for Plane {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
         let normal = self.normal.outward().get().clone();
         let denom = dot(&normal, &ray.direction);
-        if denom.abs() <= EPSILON {
+        if denom.abs() <= f32::EPSILON {
             return None;
         }
         let diff = &self.point - &ray.origin;
@@ -109,10 +204,23 @@ impl Hittable for Plane {
         if travel < t_min || travel > t_max {
             return None;
         }
+        let point = ray.at(travel);
+        let (tangent, bitangent) = orthonormal_basis(&normal);
+        let offset = &point - &self.point;
         Some(Hit {
             travel,
-            point: ray.at(travel),
-            normal: self.normal.clone(),
+            distance: travel * ray.direction.len(),
+            u: dot(&offset, &tangent),
+            v: dot(&offset, &bitangent),
+            tangent: UnitVec3::unchecked_from(&tangent),
+            point,
+            // like closed surfaces, the back of planes, opposite to their set normal, is their
+            // inside
+            normal: if denom > 0. {
+                Normal::Inward(UnitVec3::unchecked_from(&normal))
+            } else {
+                Normal::Outward(UnitVec3::unchecked_from(&normal))
+            },
             material: &self.material,
         })
     }
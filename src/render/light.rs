@@ -0,0 +1,195 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::math::{deg_to_radians, dot, Frame};
+use crate::render::{Color, Sampler};
+use crate::types::{Point, Vec3};
+
+/// Light without any surface, which is only reached through shadow rays.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Light {
+    /// Light emitted equally in all directions from a point.
+    Point {
+        position: Point,
+        color: Color,
+        intensity: f32,
+    },
+    /// Point light restricted to a cone around its direction, fading out between the inner and
+    /// outer angles, in degrees.
+    Spot {
+        position: Point,
+        direction: Vec3,
+        color: Color,
+        intensity: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+    /// Light coming from infinitely far away, like sunlight, traveling along its direction. A
+    /// non-zero angular radius, in degrees, softens shadows.
+    Directional {
+        direction: Vec3,
+        color: Color,
+        intensity: f32,
+        angular_radius: f32,
+    },
+}
+
+/// Light reaching a point from a light.
+pub struct LightSample {
+    /// Unit direction towards the light.
+    pub wi: Vec3,
+    /// Distance to the light, infinite for directional lights.
+    pub distance: f32,
+    /// Light reaching the point, which the BSDF value along `wi` scales.
+    pub radiance: Color,
+}
+
+/// Normalized direction of a light, straight down if it has no length.
+fn unit_direction(direction: &Vec3) -> Vec3 {
+    let len = direction.len();
+    if len > 0. && len.is_finite() {
+        direction / len
+    } else {
+        Vec3::new(0., 0., -1.)
+    }
+}
+
+fn smoothstep(t: f32) -> f32 {
+    let t = t.clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+impl Light {
+    /// Samples the light reaching `point`, without checking for occlusion. Returns `None` if
+    /// none does.
+    pub fn sample(&self, point: &Point, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        match self {
+            Light::Point {
+                position,
+                color,
+                intensity,
+            } => {
+                let to_light = position - point;
+                let distance = to_light.len();
+                Some(LightSample {
+                    wi: to_light / distance,
+                    distance,
+                    radiance: (intensity / distance.powi(2)) * color,
+                })
+            }
+            Light::Spot {
+                position,
+                direction,
+                color,
+                intensity,
+                inner_angle,
+                outer_angle,
+            } => {
+                let to_light = position - point;
+                let distance = to_light.len();
+                let wi = to_light / distance;
+
+                let cos_theta = -dot(&wi, &unit_direction(direction));
+                let cos_inner = deg_to_radians(*inner_angle).cos();
+                let cos_outer = deg_to_radians(*outer_angle).cos();
+                let falloff = if cos_inner <= cos_outer {
+                    if cos_theta >= cos_outer {
+                        1.
+                    } else {
+                        0.
+                    }
+                } else {
+                    smoothstep((cos_theta - cos_outer) / (cos_inner - cos_outer))
+                };
+                if falloff <= 0. {
+                    return None;
+                }
+
+                Some(LightSample {
+                    wi,
+                    distance,
+                    radiance: (falloff * intensity / distance.powi(2)) * color,
+                })
+            }
+            Light::Directional {
+                direction,
+                color,
+                intensity,
+                angular_radius,
+            } => {
+                let towards_light = -unit_direction(direction);
+                let wi = if *angular_radius > 0. {
+                    // uniformly sample the cone subtended by the light
+                    let cos_max = deg_to_radians(*angular_radius).cos();
+                    let (u, v) = sampler.get_2d();
+                    let cos_theta = 1. - u * (1. - cos_max);
+                    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+                    let phi = 2. * PI * v;
+                    let local = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                    Frame::new(&towards_light).to_world(&local)
+                } else {
+                    towards_light
+                };
+                Some(LightSample {
+                    wi,
+                    distance: f32::INFINITY,
+                    radiance: *intensity * color,
+                })
+            }
+        }
+    }
+
+    /// Unit direction the light shines along, if it has one.
+    pub fn direction(&self) -> Option<Vec3> {
+        match self {
+            Light::Point { .. } => None,
+            Light::Spot { direction, .. } | Light::Directional { direction, .. } => {
+                Some(unit_direction(direction))
+            }
+        }
+    }
+
+    /// Position of the light, if it has one.
+    pub fn position(&self) -> Option<&Point> {
+        match self {
+            Light::Point { position, .. } | Light::Spot { position, .. } => Some(position),
+            Light::Directional { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn zero_directions_point_down() {
+        let zero = || Vec3::new(0., 0., 0.);
+        let lights = [
+            Light::Spot {
+                position: Point::new(0., 0., 1.),
+                direction: zero(),
+                color: Color::WHITE,
+                intensity: 1.,
+                inner_angle: 20.,
+                outer_angle: 30.,
+            },
+            Light::Directional {
+                direction: zero(),
+                color: Color::WHITE,
+                intensity: 1.,
+                angular_radius: 0.5,
+            },
+        ];
+        let mut rng = SmallRng::seed_from_u64(0);
+        for light in &lights {
+            let sample = light.sample(&Point::new(0., 0., 0.), &mut rng).unwrap();
+            assert!(sample.wi.z > 0.99, "{:?}", sample.wi);
+            assert!(sample.radiance.luminance() > 0.);
+        }
+    }
+}
@@ -1,38 +1,30 @@
-use rand::rngs::SmallRng;
+use std::f32::consts::PI;
 
-use crate::math::{dot, same_orientation};
+use crate::math::{dot, orthonormal_basis, same_orientation, Frame};
 use crate::physics::{reflect, refract};
-use crate::render::{Color, Colorer, Hit, Ray};
-use crate::types::{Normal, UnitVec3};
+use crate::render::microfacet::{
+    fresnel_conductor, fresnel_dielectric, reflect_around, refract_through, Ggx,
+};
+use crate::render::{Bump, Color, Colorer, ComplexIor, Hit, Principled, Sampler};
+use crate::sampling::{
+    cosine_hemisphere, cosine_hemisphere_pdf, uniform_sphere, UNIFORM_SPHERE_PDF,
+};
+use crate::types::{Normal, UnitVec3, Vec3};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-pub enum Interaction {
-    Source(Source),
-    Bounce(Bounce),
-    Nothing,
-}
-
-impl Interaction {
-    pub fn bounce(scattered: Ray, attenuation: Color) -> Self {
-        Self::Bounce(Bounce {
-            scattered,
-            attenuation,
-        })
-    }
-
-    pub fn source(color: Color) -> Self {
-        Self::Source(Source { color })
-    }
-}
-
-pub struct Bounce {
-    pub scattered: Ray,
-    pub attenuation: Color,
-}
-
-pub struct Source {
-    pub color: Color,
+/// Direction sampled by a material, towards which the ray scatters.
+pub struct BsdfSample {
+    /// Unit direction the light is coming from.
+    pub wi: Vec3,
+    /// Value of `eval` divided by `pdf`, by which the light coming from `wi` is multiplied.
+    pub weight: Color,
+    /// Density of `wi` with respect to solid angles, or the probability of picking it for
+    /// specular samples.
+    pub pdf: f32,
+    /// Whether `wi` was picked among a discrete set of directions, for which `eval` and `pdf`
+    /// are zero.
+    pub specular: bool,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -45,61 +37,477 @@ pub enum Material {
     Dielectric {
         refraction_index: f32,
         colorer: Colorer,
+        /// Absorption coefficients of the inside of the material, per unit of distance.
+        #[serde(default)]
+        absorption: Color,
+    },
+    /// Emitter of light, only from the outside of closed surfaces unless it is two-sided. Planes
+    /// emit towards the side their normal points to.
+    ///
+    /// Scenes made before lights had settings store a bare colorer, which is still accepted.
+    #[serde(deserialize_with = "deserialize_light")]
+    Light {
+        colorer: Colorer,
+        /// Multiplier of the emitted color.
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+        #[serde(default = "default_two_sided")]
+        two_sided: bool,
+        /// Blackbody temperature in kelvins, whose color replaces the colorer's.
+        #[serde(default)]
+        temperature: Option<f32>,
+    },
+    /// Phase function of media scattering light equally in all directions.
+    Isotropic(Colorer),
+    /// Phase function of media scattering light mostly forward (positive asymmetry) or backward
+    /// (negative asymmetry).
+    HenyeyGreenstein {
+        colorer: Colorer,
+        asymmetry: f32,
+    },
+    /// Rough metal, modeled with GGX microfacets.
+    Conductor {
+        colorer: Colorer,
+        roughness: f32,
+        ior: ComplexIor,
+    },
+    /// Frosted glass, modeled with GGX microfacets. The refraction index follows the convention
+    /// of `Dielectric`.
+    RoughDielectric {
+        refraction_index: f32,
+        roughness: f32,
+        colorer: Colorer,
+    },
+    Principled(Box<Principled>),
+    /// Material whose shading normal is perturbed by a normal or bump map.
+    Bumped {
+        material: Box<Material>,
+        bump: Bump,
+    },
+}
+
+const fn default_intensity() -> f32 {
+    1.
+}
+
+const fn default_two_sided() -> bool {
+    true
+}
+
+/// Fields of `Material::Light`, in either of their serialized forms.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LightFields {
+    Settings {
+        colorer: Colorer,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+        #[serde(default = "default_two_sided")]
+        two_sided: bool,
+        #[serde(default)]
+        temperature: Option<f32>,
     },
-    Light(Colorer),
+    Colorer(Colorer),
+}
+
+fn deserialize_light<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<(Colorer, f32, bool, Option<f32>), D::Error> {
+    Ok(match LightFields::deserialize(deserializer)? {
+        LightFields::Settings {
+            colorer,
+            intensity,
+            two_sided,
+            temperature,
+        } => (colorer, intensity, two_sided, temperature),
+        LightFields::Colorer(colorer) => (colorer, default_intensity(), default_two_sided(), None),
+    })
+}
+
+/// Shading normal on the side of `wo`.
+fn facing_normal(hit: &Hit, wo: &Vec3) -> Vec3 {
+    let outward = hit.normal.outward().get().clone();
+    if same_orientation(wo, &outward) {
+        outward
+    } else {
+        -outward
+    }
+}
+
+/// Local shading frame around the normal on the side of `wo`.
+pub(super) fn shading_frame(hit: &Hit, wo: &Vec3) -> Frame {
+    Frame::new(&facing_normal(hit, wo))
+}
+
+/// Index of refraction of the side of the surface the ray comes from, over the index of the
+/// other side.
+pub(super) fn refraction_ratio(hit: &Hit, refraction_index: f32) -> f32 {
+    match hit.normal {
+        Normal::Inward(_) => 1. / refraction_index,
+        Normal::Outward(_) => refraction_index,
+    }
+}
+
+/// Density of the directions of `reflected + fuzz * s`, `s` being uniformly distributed on the
+/// unit sphere.
+fn fuzzy_reflection_pdf(reflected: &Vec3, fuzz: f32, wi: &Vec3) -> f32 {
+    // `wi` crosses the sphere of radius `fuzz` around the reflection up to twice, the area
+    // density of the sphere is converted to solid angles at both crossings
+    let b = dot(wi, reflected);
+    let discriminant = b * b - dot(reflected, reflected) + fuzz * fuzz;
+    if discriminant <= 0. {
+        return 0.;
+    }
+    let root = discriminant.sqrt();
+    let cos = root / fuzz;
+    [b - root, b + root]
+        .iter()
+        .filter(|t| **t > 0.)
+        .map(|t| t * t / (4. * PI * fuzz * fuzz * cos))
+        .sum()
+}
+
+/// Fraction of light going through `distance` of a medium absorbing it with the given
+/// coefficients, following the Beer-Lambert law.
+fn beer_lambert(absorption: &Color, distance: f32) -> Color {
+    Color::new(
+        (-absorption.r * distance).exp(),
+        (-absorption.g * distance).exp(),
+        (-absorption.b * distance).exp(),
+    )
+}
+
+/// Samples the cosine of the angle between the incoming and scattered directions.
+fn sample_henyey_greenstein(asymmetry: f32, sampler: &mut dyn Sampler) -> f32 {
+    let u = sampler.get_1d();
+    if asymmetry.abs() < 1e-3 {
+        return 1. - 2. * u;
+    }
+    let g = asymmetry;
+    let s = (1. - g * g) / (1. - g + 2. * g * u);
+    ((1. + g * g - s * s) / (2. * g)).clamp(-1., 1.)
+}
+
+fn henyey_greenstein(cos_theta: f32, asymmetry: f32) -> f32 {
+    let g = asymmetry;
+    (1. - g * g) / (4. * PI * (1. + g * g - 2. * g * cos_theta).powf(1.5))
+}
+
+fn bumped<'a>(hit: &Hit, material: &'a Material, bump: &Bump) -> Hit<'a> {
+    Hit {
+        travel: hit.travel,
+        distance: hit.distance,
+        point: hit.point.clone(),
+        normal: hit.normal.with_outward(bump.shading_normal(hit)),
+        material,
+        u: hit.u,
+        v: hit.v,
+        tangent: hit.tangent.clone(),
+    }
 }
 
 impl Material {
-    pub fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut SmallRng) -> Interaction {
+    /// Two-sided light of unit intensity.
+    pub const fn light(colorer: Colorer) -> Self {
+        Material::Light {
+            colorer,
+            intensity: default_intensity(),
+            two_sided: default_two_sided(),
+            temperature: None,
+        }
+    }
+
+    /// Light emitted by the surface.
+    pub fn emitted(&self, hit: &Hit) -> Color {
         match self {
-            Material::Diffuse(colorer) => {
-                let scatter_direction = hit.normal.outward().get() + UnitVec3::random(rng).get();
-                let scattered = Ray {
-                    origin: hit.point.clone(),
-                    direction: scatter_direction,
+            Material::Light {
+                colorer,
+                intensity,
+                two_sided,
+                temperature,
+            } => {
+                if !two_sided && matches!(hit.normal, Normal::Inward(_)) {
+                    return Color::BLACK;
+                }
+                let color = match temperature {
+                    Some(temperature) => Color::blackbody(*temperature),
+                    None => colorer.color(hit),
                 };
-                Interaction::bounce(scattered, colorer.color(hit))
+                *intensity * color
+            }
+            Material::Bumped { material, bump } => material.emitted(&bumped(hit, material, bump)),
+            _ => Color::BLACK,
+        }
+    }
+
+    /// Samples the direction light scatters from, `wo` being the unit direction it scatters
+    /// towards. Returns `None` if the light is absorbed.
+    pub fn sample(&self, hit: &Hit, wo: &Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        match self {
+            Material::Diffuse(colorer) => {
+                let frame = shading_frame(hit, wo);
+                let wi = cosine_hemisphere(sampler);
+                Some(BsdfSample {
+                    pdf: cosine_hemisphere_pdf(wi.z),
+                    wi: frame.to_world(&wi),
+                    weight: colorer.color(hit),
+                    specular: false,
+                })
             }
             Material::Metal { colorer, fuzz } => {
-                let reflected = reflect(&ray.direction.unit(), &hit.normal.outward());
-                let scattered = Ray {
-                    origin: hit.point.clone(),
-                    direction: reflected + *fuzz * UnitVec3::random(rng).get(),
-                };
-                if same_orientation(&scattered.direction, hit.normal.outward().get()) {
-                    Interaction::bounce(scattered, colorer.color(hit))
-                } else {
-                    Interaction::Nothing
+                let normal = facing_normal(hit, wo);
+                let reflected = reflect_around(wo, &normal);
+                if *fuzz <= 0. {
+                    return Some(BsdfSample {
+                        wi: reflected,
+                        weight: colorer.color(hit),
+                        pdf: 1.,
+                        specular: true,
+                    });
                 }
+                let direction = &reflected + *fuzz * uniform_sphere(sampler);
+                if !same_orientation(&direction, &normal) {
+                    return None;
+                }
+                let wi = direction.unit().get().clone();
+                Some(BsdfSample {
+                    pdf: fuzzy_reflection_pdf(&reflected, *fuzz, &wi),
+                    wi,
+                    weight: colorer.color(hit),
+                    specular: false,
+                })
             }
             Material::Dielectric {
                 refraction_index,
                 colorer,
+                absorption,
             } => {
-                let refraction_ratio = match hit.normal {
-                    Normal::Inward(_) => 1. / refraction_index,
-                    Normal::Outward(_) => *refraction_index,
-                };
-                let unit_direction = ray.direction.unit();
+                let refraction_ratio = refraction_ratio(hit, *refraction_index);
+                let unit_direction = UnitVec3::unchecked_from(&-wo);
                 let outward_normal = hit.normal.outward();
 
-                let cos_theta = dot(&-unit_direction.get(), outward_normal.get());
+                let cos_theta = dot(wo, outward_normal.get());
                 let sin_theta = (1. - cos_theta.powi(2)).sqrt();
                 let can_refract = refraction_ratio * sin_theta <= 1.;
 
-                let direction = if can_refract {
+                let wi = if can_refract {
                     refract(&unit_direction, &outward_normal, refraction_ratio)
                 } else {
-                    reflect(&ray.direction.unit(), &hit.normal.outward())
+                    reflect(&unit_direction, &outward_normal)
                 };
-
-                let scattered = Ray {
-                    origin: hit.point.clone(),
-                    direction,
+                // rays leaving the material traveled inside of it
+                let transmittance = match hit.normal {
+                    Normal::Inward(_) => beer_lambert(absorption, hit.distance),
+                    Normal::Outward(_) => Color::WHITE,
                 };
-                Interaction::bounce(scattered, colorer.color(hit))
+                Some(BsdfSample {
+                    wi,
+                    weight: transmittance * colorer.color(hit),
+                    pdf: 1.,
+                    specular: true,
+                })
+            }
+            Material::Light { .. } => None,
+            Material::Isotropic(colorer) => Some(BsdfSample {
+                wi: uniform_sphere(sampler),
+                weight: colorer.color(hit),
+                pdf: UNIFORM_SPHERE_PDF,
+                specular: false,
+            }),
+            Material::HenyeyGreenstein { colorer, asymmetry } => {
+                let forward = -wo;
+                let (tangent, bitangent) = orthonormal_basis(&forward);
+                let cos_theta = sample_henyey_greenstein(*asymmetry, sampler);
+                let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+                let phi = 2. * PI * sampler.get_1d();
+                let wi: Vec3 = sin_theta * phi.cos() * &tangent
+                    + sin_theta * phi.sin() * &bitangent
+                    + cos_theta * &forward;
+                Some(BsdfSample {
+                    wi,
+                    weight: colorer.color(hit),
+                    pdf: henyey_greenstein(cos_theta, *asymmetry),
+                    specular: false,
+                })
+            }
+            Material::Conductor {
+                colorer,
+                roughness,
+                ior,
+            } => {
+                let frame = shading_frame(hit, wo);
+                let wo = frame.to_local(wo);
+                let ggx = Ggx::from_roughness(*roughness);
+                let m = ggx.sample_visible(&wo, sampler.get_2d());
+                let wi = reflect_around(&wo, &m);
+                if wi.z <= 0. {
+                    return None;
+                }
+                let fresnel = fresnel_conductor(dot(&wo, &m), ior);
+                let masking = ggx.g2(&wo, &wi) / ggx.g1(&wo);
+                Some(BsdfSample {
+                    pdf: ggx.reflection_pdf(&wo, &wi),
+                    wi: frame.to_world(&wi),
+                    weight: masking * (fresnel * colorer.color(hit)),
+                    specular: false,
+                })
+            }
+            Material::RoughDielectric {
+                refraction_index,
+                roughness,
+                colorer,
+            } => {
+                let refraction_ratio = refraction_ratio(hit, *refraction_index);
+                let frame = shading_frame(hit, wo);
+                let wo = frame.to_local(wo);
+                let ggx = Ggx::from_roughness(*roughness);
+                let m = ggx.sample_visible(&wo, sampler.get_2d());
+
+                let wi = if sampler.get_1d() < fresnel_dielectric(dot(&wo, &m), refraction_ratio) {
+                    Some(reflect_around(&wo, &m)).filter(|wi| wi.z > 0.)
+                } else {
+                    refract_through(&wo, &m, refraction_ratio).filter(|wi| wi.z < 0.)
+                }?;
+
+                let (_, pdf) = ggx.dielectric(&wo, &wi, refraction_ratio);
+                let masking = ggx.g2(&wo, &wi) / ggx.g1(&wo);
+                Some(BsdfSample {
+                    wi: frame.to_world(&wi),
+                    weight: masking * colorer.color(hit),
+                    pdf,
+                    specular: false,
+                })
+            }
+            Material::Principled(principled) => principled.sample(hit, wo, sampler),
+            Material::Bumped { material, bump } => {
+                material.sample(&bumped(hit, material, bump), wo, sampler)
+            }
+        }
+    }
+
+    /// BSDF, or phase function for media, times the absolute cosine of the angle between `wi`
+    /// and the shading normal for surfaces.
+    pub fn eval(&self, hit: &Hit, wi: &Vec3, wo: &Vec3) -> Color {
+        match self {
+            Material::Diffuse(colorer) => {
+                let cos = dot(wi, &facing_normal(hit, wo));
+                if cos <= 0. {
+                    return Color::BLACK;
+                }
+                cos / PI * colorer.color(hit)
+            }
+            Material::Metal { colorer, .. } | Material::Isotropic(colorer) => {
+                // every sampled direction has the same weight
+                self.pdf(hit, wi, wo) * colorer.color(hit)
+            }
+            Material::HenyeyGreenstein { colorer, asymmetry } => {
+                henyey_greenstein(dot(&-wo, wi), *asymmetry) * colorer.color(hit)
+            }
+            Material::Conductor {
+                colorer,
+                roughness,
+                ior,
+            } => {
+                let frame = shading_frame(hit, wo);
+                let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+                let ggx = Ggx::from_roughness(*roughness);
+                let value = ggx.reflection(&wo, &wi);
+                if value <= 0. {
+                    return Color::BLACK;
+                }
+                let m = (&wo + &wi).unit();
+                let fresnel = fresnel_conductor(dot(&wo, m.get()), ior);
+                value * (fresnel * colorer.color(hit))
+            }
+            Material::RoughDielectric {
+                refraction_index,
+                roughness,
+                colorer,
+            } => {
+                let frame = shading_frame(hit, wo);
+                let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+                let ggx = Ggx::from_roughness(*roughness);
+                let refraction_ratio = refraction_ratio(hit, *refraction_index);
+                let (value, _) = ggx.dielectric(&wo, &wi, refraction_ratio);
+                value * colorer.color(hit)
+            }
+            Material::Principled(principled) => principled.eval(hit, wi, wo),
+            Material::Bumped { material, bump } => {
+                material.eval(&bumped(hit, material, bump), wi, wo)
+            }
+            Material::Dielectric { .. } | Material::Light { .. } => Color::BLACK,
+        }
+    }
+
+    /// Density with which `sample` picks `wi`, with respect to solid angles.
+    pub fn pdf(&self, hit: &Hit, wi: &Vec3, wo: &Vec3) -> f32 {
+        match self {
+            Material::Diffuse(_) => cosine_hemisphere_pdf(dot(wi, &facing_normal(hit, wo))),
+            Material::Metal { fuzz, .. } => {
+                let normal = facing_normal(hit, wo);
+                if *fuzz <= 0. || !same_orientation(wi, &normal) {
+                    return 0.;
+                }
+                fuzzy_reflection_pdf(&reflect_around(wo, &normal), *fuzz, wi)
             }
-            Material::Light(colorer) => Interaction::source(colorer.color(hit)),
+            Material::Isotropic(_) => UNIFORM_SPHERE_PDF,
+            Material::HenyeyGreenstein { asymmetry, .. } => {
+                henyey_greenstein(dot(&-wo, wi), *asymmetry)
+            }
+            Material::Conductor { roughness, .. } => {
+                let frame = shading_frame(hit, wo);
+                let ggx = Ggx::from_roughness(*roughness);
+                ggx.reflection_pdf(&frame.to_local(wo), &frame.to_local(wi))
+            }
+            Material::RoughDielectric {
+                refraction_index,
+                roughness,
+                ..
+            } => {
+                let frame = shading_frame(hit, wo);
+                let ggx = Ggx::from_roughness(*roughness);
+                let refraction_ratio = refraction_ratio(hit, *refraction_index);
+                let (_, pdf) =
+                    ggx.dielectric(&frame.to_local(wo), &frame.to_local(wi), refraction_ratio);
+                pdf
+            }
+            Material::Principled(principled) => principled.pdf(hit, wi, wo),
+            Material::Bumped { material, bump } => {
+                material.pdf(&bumped(hit, material, bump), wi, wo)
+            }
+            Material::Dielectric { .. } | Material::Light { .. } => 0.,
+        }
+    }
+
+    /// Color of the surface at the hit regardless of lighting, which guides denoising.
+    pub fn albedo(&self, hit: &Hit) -> Color {
+        match self {
+            Material::Diffuse(colorer)
+            | Material::Metal { colorer, .. }
+            | Material::Dielectric { colorer, .. }
+            | Material::Light { colorer, .. }
+            | Material::Isotropic(colorer)
+            | Material::HenyeyGreenstein { colorer, .. }
+            | Material::Conductor { colorer, .. }
+            | Material::RoughDielectric { colorer, .. } => colorer.color(hit),
+            Material::Principled(principled) => principled.base_color.color(hit),
+            Material::Bumped { material, bump } => material.albedo(&bumped(hit, material, bump)),
+        }
+    }
+
+    /// Index of the kind of material, identifying e.g. all dielectrics in material id buffers.
+    pub fn kind_id(&self) -> u32 {
+        match self {
+            Material::Diffuse(_) => 0,
+            Material::Metal { .. } => 1,
+            Material::Dielectric { .. } => 2,
+            Material::Light { .. } => 3,
+            Material::Isotropic(_) => 4,
+            Material::HenyeyGreenstein { .. } => 5,
+            Material::Conductor { .. } => 6,
+            Material::RoughDielectric { .. } => 7,
+            Material::Principled(_) => 8,
+            Material::Bumped { material, .. } => material.kind_id(),
         }
     }
 
@@ -108,7 +516,226 @@ impl Material {
             Material::Diffuse(colorer)
             | Material::Metal { colorer, .. }
             | Material::Dielectric { colorer, .. }
-            | Material::Light(colorer) => colorer.clone(),
+            | Material::Light { colorer, .. }
+            | Material::Isotropic(colorer)
+            | Material::HenyeyGreenstein { colorer, .. }
+            | Material::Conductor { colorer, .. }
+            | Material::RoughDielectric { colorer, .. } => colorer.clone(),
+            Material::Principled(principled) => principled.base_color.clone(),
+            Material::Bumped { material, .. } => material.get_colorer(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::render::ComplexIor;
+    use crate::types::Point;
+
+    const SAMPLES: usize = 200_000;
+
+    fn solid(value: f32) -> Colorer {
+        Colorer::Solid(Color::grey(value))
+    }
+
+    fn hit(material: &Material, normal: Normal) -> Hit<'_> {
+        Hit {
+            travel: 1.,
+            distance: 1.,
+            point: Point::new(0., 0., 0.),
+            normal,
+            material,
+            u: 0.5,
+            v: 0.5,
+            tangent: UnitVec3::unchecked_from(&Vec3::new(1., 0., 0.)),
+        }
+    }
+
+    /// Checks that sampled weights and densities agree with `eval` and `pdf`, and that the
+    /// average weight matches the integral of `eval` over the sphere.
+    fn check_consistency(material: &Material, normal: Normal) {
+        let hit = hit(material, normal);
+        let wo = Vec3::new(0.3, -0.2, 0.8).unit().get().clone();
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let mut albedo = 0.;
+        let mut accepted = 0.;
+        for _ in 0..SAMPLES {
+            let Some(sample) = material.sample(&hit, &wo, &mut rng) else {
+                continue;
+            };
+            let pdf = material.pdf(&hit, &sample.wi, &wo);
+            let eval = material.eval(&hit, &sample.wi, &wo).luminance();
+            let weight = sample.weight.luminance();
+            assert!(
+                (pdf - sample.pdf).abs() <= 1e-2 * pdf,
+                "{} != {}",
+                pdf,
+                sample.pdf
+            );
+            assert!(
+                (eval - weight * pdf).abs() <= 1e-2 * eval,
+                "{} != {} * {}",
+                eval,
+                weight,
+                pdf
+            );
+            albedo += weight;
+            accepted += 1.;
+        }
+
+        let mut integrated_albedo = 0.;
+        let mut integrated_pdf = 0.;
+        for _ in 0..SAMPLES {
+            let wi = uniform_sphere(&mut rng);
+            integrated_albedo += 4. * PI * material.eval(&hit, &wi, &wo).luminance();
+            integrated_pdf += 4. * PI * material.pdf(&hit, &wi, &wo);
+        }
+
+        let mean = |sum: f32| sum / SAMPLES as f32;
+        let (albedo, integrated_albedo) = (mean(albedo), mean(integrated_albedo));
+        let (accepted, integrated_pdf) = (mean(accepted), mean(integrated_pdf));
+        assert!(
+            (albedo - integrated_albedo).abs() < 0.02,
+            "{} != {}",
+            albedo,
+            integrated_albedo
+        );
+        assert!(
+            (accepted - integrated_pdf).abs() < 0.02,
+            "{} != {}",
+            accepted,
+            integrated_pdf
+        );
+    }
+
+    #[test]
+    fn sample_matches_eval_and_pdf() {
+        let up = || UnitVec3::unchecked_from(&Vec3::new(0., 0., 1.));
+        let down = || UnitVec3::unchecked_from(&Vec3::new(0., 0., -1.));
+        let materials = [
+            Material::Diffuse(solid(0.5)),
+            Material::Metal {
+                colorer: solid(0.8),
+                fuzz: 0.5,
+            },
+            Material::Isotropic(solid(0.8)),
+            Material::HenyeyGreenstein {
+                colorer: solid(0.8),
+                asymmetry: 0.6,
+            },
+            Material::Conductor {
+                colorer: solid(1.),
+                roughness: 0.5,
+                ior: ComplexIor::GOLD,
+            },
+            Material::RoughDielectric {
+                refraction_index: 0.67,
+                roughness: 0.5,
+                colorer: solid(1.),
+            },
+            Material::Principled(Box::new(Principled {
+                metallic: solid(0.3),
+                clearcoat: solid(1.),
+                sheen: solid(0.5),
+                transmission: solid(0.5),
+                ..Principled::new(solid(0.7))
+            })),
+        ];
+        for material in &materials {
+            check_consistency(material, Normal::Outward(up()));
+            // rays leaving dielectrics can be totally reflected
+            check_consistency(material, Normal::Inward(down()));
         }
     }
+
+    #[test]
+    fn henyey_greenstein_asymmetry() {
+        // the mean cosine between the incoming and scattered directions is the asymmetry
+        for asymmetry in [-0.7, 0., 0.3, 0.9] {
+            let material = Material::HenyeyGreenstein {
+                colorer: solid(1.),
+                asymmetry,
+            };
+            let hit = hit(&material, Normal::Outward(Vec3::new(0., 0., 1.).unit()));
+            let wo = Vec3::new(0., 0.6, 0.8);
+            let mut rng = SmallRng::seed_from_u64(0);
+            let mean: f32 = (0..SAMPLES)
+                .map(|_| {
+                    let sample = material.sample(&hit, &wo, &mut rng).unwrap();
+                    dot(&sample.wi, &-&wo)
+                })
+                .sum::<f32>()
+                / SAMPLES as f32;
+            assert!((mean - asymmetry).abs() < 0.01, "{} {}", mean, asymmetry);
+        }
+    }
+
+    #[test]
+    fn deserializes_lights_without_settings() {
+        let light: Material =
+            serde_json::from_str(r#"{"Light": {"Solid": {"r": 2, "g": 1, "b": 0}}}"#).unwrap();
+        let Material::Light {
+            colorer: Colorer::Solid(color),
+            intensity,
+            two_sided,
+            temperature,
+        } = light
+        else {
+            panic!("expected a solid light");
+        };
+        assert_eq!(color, Color::new(2., 1., 0.));
+        assert_eq!((intensity, two_sided, temperature), (1., true, None));
+
+        let light: Material = serde_json::from_str(
+            r#"{"Light": {"colorer": "Bubblegum", "intensity": 3, "two_sided": false}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            light,
+            Material::Light {
+                colorer: Colorer::Bubblegum,
+                intensity,
+                two_sided: false,
+                temperature: None,
+            } if intensity == 3.
+        ));
+        let round_trip: Material =
+            serde_json::from_str(&serde_json::to_string(&light).unwrap()).unwrap();
+        assert!(matches!(
+            round_trip,
+            Material::Light {
+                two_sided: false,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn dielectric_absorption() {
+        let material = Material::Dielectric {
+            refraction_index: 1.,
+            colorer: solid(1.),
+            absorption: Color::new(0., 2_f32.ln(), 4_f32.ln()),
+        };
+        let normal = UnitVec3::unchecked_from(&Vec3::new(0., 0., -1.));
+        let wo = Vec3::new(0., 0., 1.);
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        // entering the material doesn't absorb anything
+        let entering = hit(&material, Normal::Outward(-&normal));
+        let sample = material.sample(&entering, &wo, &mut rng).unwrap();
+        assert_eq!(sample.weight, Color::WHITE);
+
+        // leaving it absorbs over the distance travelled inside
+        let leaving = hit(&material, Normal::Inward(normal));
+        let sample = material.sample(&leaving, &wo, &mut rng).unwrap();
+        assert!((sample.weight.r - 1.).abs() < 1e-6);
+        assert!((sample.weight.g - 0.5).abs() < 1e-6);
+        assert!((sample.weight.b - 0.25).abs() < 1e-6);
+    }
 }
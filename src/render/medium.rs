@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+
+use crate::math::orthonormal_basis;
+use crate::render::{Hit, Hittable, Material, Ray, Sampler, Sdf, Sphere};
+use crate::types::{Normal, UnitVec3};
+
+/// Volume that rays can scatter in, rather than only at its surface.
+pub trait Medium {
+    /// Samples the distance at which the ray scatters inside the medium, if it does so before
+    /// `t_max`.
+    fn sample(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Hit<'_>>;
+
+    /// Estimates the fraction of light going through the medium between `t_min` and `t_max`.
+    fn transmittance(&self, ray: &Ray, t_min: f32, t_max: f32, sampler: &mut dyn Sampler) -> f32;
+}
+
+/// Closed shapes of scenes which can delimit a medium, their material is ignored.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Boundary {
+    Sphere(Sphere),
+    Sdf(Sdf),
+}
+
+impl Hittable for Boundary {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        match self {
+            Boundary::Sphere(sphere) => sphere.hit(ray, t_min, t_max),
+            Boundary::Sdf(sdf) => sdf.hit(ray, t_min, t_max),
+        }
+    }
+}
+
+/// Samples a free-flight distance in a medium of the given density, returning the ray travel
+/// of the scattering event if it happens before `t_max`.
+fn free_flight(
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+    density: f32,
+    sampler: &mut dyn Sampler,
+) -> Option<f32> {
+    let len = ray.direction.len();
+    let distance = -(1. - sampler.get_1d()).ln() / density;
+    let travel = t_min + distance / len;
+    (travel < t_max).then_some(travel)
+}
+
+pub(super) fn scattering_hit<'a>(ray: &Ray, travel: f32, material: &'a Material) -> Hit<'a> {
+    // scattering events have no surface, the normal and tangent are arbitrary
+    let normal = (-&ray.direction).unit();
+    let tangent = UnitVec3::unchecked_from(&orthonormal_basis(normal.get()).0);
+    Hit {
+        travel,
+        distance: travel * ray.direction.len(),
+        point: ray.at(travel),
+        normal: Normal::Outward(normal),
+        material,
+        u: 0.,
+        v: 0.,
+        tangent,
+    }
+}
+
+/// Homogeneous medium, like smoke or fog, filling a closed boundary.
+///
+/// Any closed hittable can bound the medium, scenes use the serializable `Boundary` shapes.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ConstantMedium<B = Boundary> {
+    pub boundary: B,
+    pub density: f32,
+    /// Phase function of the medium, e.g. `Material::Isotropic`.
+    pub material: Material,
+}
+
+impl<B: Hittable> ConstantMedium<B> {
+    /// Returns the range of travels for which the ray is inside the boundary.
+    fn clip(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let first = self.boundary.hit(ray, t_min, f32::INFINITY)?;
+        let (enter, exit) = match first.normal {
+            // the ray starts inside the boundary
+            Normal::Inward(_) => (t_min, first.travel),
+            Normal::Outward(_) => {
+                let second = self.boundary.hit(ray, first.travel, f32::INFINITY)?;
+                (first.travel, second.travel)
+            }
+        };
+        let exit = exit.min(t_max);
+        (enter < exit).then_some((enter, exit))
+    }
+}
+
+impl<B: Hittable> Medium for ConstantMedium<B> {
+    fn sample(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Hit<'_>> {
+        let (enter, exit) = self.clip(ray, t_min, t_max)?;
+        let travel = free_flight(ray, enter, exit, self.density, sampler)?;
+        Some(scattering_hit(ray, travel, &self.material))
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f32, t_max: f32, _sampler: &mut dyn Sampler) -> f32 {
+        match self.clip(ray, t_min, t_max) {
+            Some((enter, exit)) => (-self.density * (exit - enter) * ray.direction.len()).exp(),
+            None => 1.,
+        }
+    }
+}
+
+/// Homogeneous medium filling the whole scene.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Fog {
+    pub density: f32,
+    /// Distance past which rays are considered to have left the fog, so that the background
+    /// remains visible.
+    pub extent: f32,
+    pub material: Material,
+}
+
+impl Medium for Fog {
+    fn sample(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Hit<'_>> {
+        let t_max = t_max.min(t_min + self.extent / ray.direction.len());
+        let travel = free_flight(ray, t_min, t_max, self.density, sampler)?;
+        Some(scattering_hit(ray, travel, &self.material))
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f32, t_max: f32, _sampler: &mut dyn Sampler) -> f32 {
+        let distance = (t_max - t_min) * ray.direction.len();
+        (-self.density * distance.min(self.extent)).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::render::{Color, Colorer};
+    use crate::types::{Point, Vec3};
+
+    #[test]
+    fn constant_medium_attenuation() {
+        let material = || Material::Isotropic(Colorer::Solid(Color::WHITE));
+        let medium = ConstantMedium {
+            boundary: Sphere {
+                center: Point::new(0., 0., 0.),
+                radius: 1.,
+                material: material(),
+            },
+            density: 0.7,
+            material: material(),
+        };
+        // the direction isn't normalized, travels are half the distances
+        let ray = Ray {
+            origin: Point::new(-5., 0., 0.),
+            direction: Vec3::new(2., 0., 0.),
+        };
+        let mut rng = SmallRng::seed_from_u64(0);
+        let expected = (-0.7_f32 * 2.).exp();
+
+        let transmittance = medium.transmittance(&ray, 0.001, f32::INFINITY, &mut rng);
+        assert!((transmittance - expected).abs() < 1e-5, "{}", transmittance);
+        // rays stopping halfway through only go through half of the medium
+        let halfway = medium.transmittance(&ray, 0.001, 2.5, &mut rng);
+        assert!((halfway - expected.sqrt()).abs() < 1e-5, "{}", halfway);
+
+        // free-flight sampling lets the same fraction of rays through
+        let samples = 100_000;
+        let mut escaped = 0;
+        for _ in 0..samples {
+            match medium.sample(&ray, 0.001, f32::INFINITY, &mut rng) {
+                Some(hit) => assert!(hit.point.x.abs() <= 1. + 1e-4, "{:?}", hit.point),
+                None => escaped += 1,
+            }
+        }
+        let escaped = escaped as f32 / samples as f32;
+        assert!(
+            (escaped - expected).abs() < 0.01,
+            "{} {}",
+            escaped,
+            expected
+        );
+    }
+}
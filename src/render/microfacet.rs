@@ -0,0 +1,215 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::math::{cross, dot};
+use crate::render::Color;
+use crate::types::Vec3;
+
+/// Complex index of refraction of a conductor, per color channel.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ComplexIor {
+    pub eta: Color,
+    pub k: Color,
+}
+
+impl ComplexIor {
+    pub const GOLD: Self = Self {
+        eta: Color::new(0.143, 0.374, 1.442),
+        k: Color::new(3.983, 2.385, 1.603),
+    };
+    pub const COPPER: Self = Self {
+        eta: Color::new(0.200, 0.924, 1.102),
+        k: Color::new(3.912, 2.452, 2.142),
+    };
+    pub const ALUMINUM: Self = Self {
+        eta: Color::new(1.657, 0.880, 0.521),
+        k: Color::new(9.224, 6.270, 4.837),
+    };
+    pub const SILVER: Self = Self {
+        eta: Color::new(0.155, 0.117, 0.138),
+        k: Color::new(4.828, 3.122, 2.147),
+    };
+}
+
+/// Fresnel reflectance of a conductor, for each color channel.
+pub fn fresnel_conductor(cos_i: f32, ior: &ComplexIor) -> Color {
+    let channel = |eta: f32, k: f32| {
+        let cos2 = cos_i.clamp(0., 1.).powi(2);
+        let sin2 = 1. - cos2;
+        let t0 = eta * eta - k * k - sin2;
+        let a2b2 = (t0 * t0 + 4. * eta * eta * k * k).sqrt();
+        let t1 = a2b2 + cos2;
+        let a = (0.5 * (a2b2 + t0)).max(0.).sqrt();
+        let t2 = 2. * cos_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rp + rs)
+    };
+    Color::new(
+        channel(ior.eta.r, ior.k.r),
+        channel(ior.eta.g, ior.k.g),
+        channel(ior.eta.b, ior.k.b),
+    )
+}
+
+/// Fresnel reflectance of a dielectric interface, `refraction_ratio` being the index of the
+/// incident medium over the index of the transmitted one.
+pub fn fresnel_dielectric(cos_i: f32, refraction_ratio: f32) -> f32 {
+    let cos_i = cos_i.clamp(0., 1.);
+    let sin2_t = refraction_ratio.powi(2) * (1. - cos_i * cos_i);
+    if sin2_t >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let parallel = (cos_i - refraction_ratio * cos_t) / (cos_i + refraction_ratio * cos_t);
+    let perpendicular = (refraction_ratio * cos_i - cos_t) / (refraction_ratio * cos_i + cos_t);
+    0.5 * (parallel.powi(2) + perpendicular.powi(2))
+}
+
+/// Refracts `wo`, pointing away from the surface, through the microfacet `m` of the same side.
+pub fn refract_through(wo: &Vec3, m: &Vec3, refraction_ratio: f32) -> Option<Vec3> {
+    let cos_i = dot(wo, m);
+    let sin2_t = refraction_ratio.powi(2) * (1. - cos_i * cos_i).max(0.);
+    if sin2_t >= 1. {
+        return None;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    Some((refraction_ratio * cos_i - cos_t) * m - refraction_ratio * wo)
+}
+
+/// Reflects `wo`, pointing away from the surface, around `m`.
+pub fn reflect_around(wo: &Vec3, m: &Vec3) -> Vec3 {
+    2. * dot(wo, m) * m - wo
+}
+
+/// GGX (Trowbridge-Reitz) distribution of microfacet normals, in a local space where the
+/// macro-surface normal is the Z axis.
+pub struct Ggx {
+    alpha: f32,
+}
+
+impl Ggx {
+    pub fn from_roughness(roughness: f32) -> Self {
+        Self {
+            alpha: roughness.powi(2).max(1e-3),
+        }
+    }
+
+    /// Density of microfacets with normal `m`.
+    pub fn d(&self, m: &Vec3) -> f32 {
+        if m.z <= 0. {
+            return 0.;
+        }
+        let a2 = self.alpha.powi(2);
+        let cos2 = m.z.powi(2);
+        let tan2 = (1. - cos2) / cos2;
+        a2 / (PI * cos2.powi(2) * (a2 + tan2).powi(2))
+    }
+
+    fn lambda(&self, w: &Vec3) -> f32 {
+        let cos2 = w.z.powi(2);
+        if cos2 == 0. {
+            return f32::INFINITY;
+        }
+        let tan2 = (1. - cos2) / cos2;
+        0.5 * ((1. + self.alpha.powi(2) * tan2).sqrt() - 1.)
+    }
+
+    /// Fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: &Vec3) -> f32 {
+        1. / (1. + self.lambda(w))
+    }
+
+    /// Fraction of microfacets visible from both `wo` and `wi`, height-correlated.
+    pub fn g2(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        1. / (1. + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of normals `m` visible from `wo`.
+    pub fn visible_d(&self, wo: &Vec3, m: &Vec3) -> f32 {
+        if wo.z <= 0. {
+            return 0.;
+        }
+        self.g1(wo) * dot(wo, m).max(0.) * self.d(m) / wo.z
+    }
+
+    /// Reflection BRDF without the Fresnel term, times the cosine of `wi`.
+    pub fn reflection(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        if wo.z <= 0. || wi.z <= 0. {
+            return 0.;
+        }
+        let m = (wo + wi).unit();
+        self.d(m.get()) * self.g2(wo, wi) / (4. * wo.z)
+    }
+
+    /// Density of `wi` when reflecting `wo` around sampled visible normals.
+    pub fn reflection_pdf(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        if wo.z <= 0. || wi.z <= 0. {
+            return 0.;
+        }
+        let m = (wo + wi).unit();
+        self.visible_d(wo, m.get()) / (4. * dot(wo, m.get()))
+    }
+
+    /// BSDF times the cosine of `wi`, and density of `wi`, of a rough dielectric interface whose
+    /// reflection or refraction is picked according to the Fresnel reflectance.
+    ///
+    /// Refracted radiance is not scaled by the squared refraction ratio, like for the smooth
+    /// `Material::Dielectric`.
+    pub fn dielectric(&self, wo: &Vec3, wi: &Vec3, refraction_ratio: f32) -> (f32, f32) {
+        if wo.z <= 0. || wi.z == 0. {
+            return (0., 0.);
+        }
+        if wi.z > 0. {
+            let m = (wo + wi).unit();
+            let fresnel = fresnel_dielectric(dot(wo, m.get()), refraction_ratio);
+            return (
+                fresnel * self.reflection(wo, wi),
+                fresnel * self.reflection_pdf(wo, wi),
+            );
+        }
+
+        // generalized half vector of refraction, Walter et al. 2007
+        let m = -(refraction_ratio * wo + wi);
+        let m = if m.z < 0. { -m } else { m }.unit();
+        let m = m.get();
+        let (cos_o, cos_i) = (dot(wo, m), dot(wi, m));
+        if cos_o <= 0. || cos_i >= 0. {
+            return (0., 0.);
+        }
+        let fresnel = fresnel_dielectric(cos_o, refraction_ratio);
+        let jacobian = -cos_i / (refraction_ratio * cos_o + cos_i).powi(2);
+        let pdf = (1. - fresnel) * self.visible_d(wo, m) * jacobian;
+        (pdf * self.g2(wo, wi) / self.g1(wo), pdf)
+    }
+
+    /// Samples a normal visible from `wo`, which must be above the surface.
+    ///
+    /// Heitz, "Sampling the GGX Distribution of Visible Normals", 2018.
+    pub fn sample_visible(&self, wo: &Vec3, u: (f32, f32)) -> Vec3 {
+        let vh = Vec3::new(self.alpha * wo.x, self.alpha * wo.y, wo.z).unit();
+        let vh = vh.get();
+        let len2 = vh.x.powi(2) + vh.y.powi(2);
+        let t1 = if len2 > 0. {
+            Vec3::new(-vh.y, vh.x, 0.) / len2.sqrt()
+        } else {
+            Vec3::new(1., 0., 0.)
+        };
+        let t2 = cross(vh, &t1);
+
+        let r = u.0.sqrt();
+        let phi = 2. * PI * u.1;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1. + vh.z);
+        let p2 = (1. - s) * (1. - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = p1 * &t1 + p2 * &t2 + (1. - p1 * p1 - p2 * p2).max(0.).sqrt() * vh;
+
+        Vec3::new(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(1e-6))
+            .unit()
+            .get()
+            .clone()
+    }
+}
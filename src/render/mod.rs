@@ -7,6 +7,31 @@ pub use camera::{Camera, Canvas, Degrees};
 mod color;
 pub use color::Color;
 mod material;
-pub use material::{Bounce, Interaction, Material, Source};
+pub use material::{BsdfSample, Material};
 mod colorer;
+mod noise;
 pub use colorer::Colorer;
+mod sdf;
+pub use sdf::{Sdf, SdfNode};
+mod medium;
+pub use medium::{Boundary, ConstantMedium, Fog, Medium};
+mod voxel;
+pub use voxel::{VoxelGrid, VoxelMedium};
+mod texture;
+pub use texture::{Texture, Wrap};
+mod environment;
+pub use environment::Environment;
+mod sky;
+pub use sky::Sky;
+mod bump;
+pub use bump::Bump;
+mod microfacet;
+pub use microfacet::ComplexIor;
+mod principled;
+pub use principled::Principled;
+mod filter;
+pub use filter::Filter;
+mod sampler;
+pub use sampler::{Sampler, SamplerKind};
+mod light;
+pub use light::{Light, LightSample};
@@ -0,0 +1,257 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::math::dot;
+use crate::render::material::{refraction_ratio, shading_frame};
+use crate::render::microfacet::{fresnel_dielectric, reflect_around, refract_through, Ggx};
+use crate::render::{BsdfSample, Color, Colorer, Hit, Sampler};
+use crate::sampling::{cosine_hemisphere, cosine_hemisphere_pdf};
+use crate::types::Vec3;
+
+const CLEARCOAT_REFRACTION_INDEX: f32 = 1.5;
+const CLEARCOAT_ROUGHNESS: f32 = 0.1;
+
+/// Uber material blending diffuse, specular, transmission, clear coat and sheen lobes, after
+/// Burley, "Physically Based Shading at Disney", 2012.
+///
+/// Every parameter is driven by a colorer so that it can be textured, scalar parameters use the
+/// luminance of the color.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Principled {
+    pub base_color: Colorer,
+    pub metallic: Colorer,
+    pub roughness: Colorer,
+    /// Reflectance of non-metallic surfaces at normal incidence, 1 being 8%.
+    pub specular: Colorer,
+    pub clearcoat: Colorer,
+    /// Color of the grazing highlights of cloth-like surfaces.
+    pub sheen: Colorer,
+    pub transmission: Colorer,
+    /// Refraction index of transmissive surfaces, following the convention of
+    /// `Material::Dielectric`.
+    pub refraction_index: f32,
+}
+
+#[derive(Clone, Copy)]
+enum Lobe {
+    Diffuse,
+    Specular,
+    Transmission,
+    Clearcoat,
+}
+
+/// Parameters of a principled material evaluated at a hit.
+struct Parameters {
+    base_color: Color,
+    metallic: f32,
+    roughness: f32,
+    specular: f32,
+    clearcoat: f32,
+    sheen: Color,
+    transmission: f32,
+    refraction_ratio: f32,
+}
+
+fn schlick_weight(cos: f32) -> f32 {
+    (1. - cos.clamp(0., 1.)).powi(5)
+}
+
+fn mix(a: Color, b: Color, t: f32) -> Color {
+    (1. - t) * a + t * b
+}
+
+impl Parameters {
+    /// Weights of the lobes, which they are also picked proportionally to.
+    fn lobes(&self) -> [(Lobe, f32); 4] {
+        let (metallic, transmission) = (self.metallic, self.transmission);
+        // the transmission lobe handles the reflections of transmissive surfaces on its own
+        [
+            (Lobe::Diffuse, (1. - metallic) * (1. - transmission)),
+            (Lobe::Specular, 1. - (1. - metallic) * transmission),
+            (Lobe::Transmission, (1. - metallic) * transmission),
+            (Lobe::Clearcoat, 0.25 * self.clearcoat),
+        ]
+    }
+
+    /// BSDF of a lobe times the cosine of `wi`, in the local shading frame.
+    fn eval(&self, lobe: Lobe, wo: &Vec3, wi: &Vec3) -> Color {
+        match lobe {
+            Lobe::Diffuse => {
+                if wo.z <= 0. || wi.z <= 0. {
+                    return Color::BLACK;
+                }
+                let cos_d = dot(wi, (wi + wo).unit().get());
+                let fd90 = 0.5 + 2. * self.roughness * cos_d.powi(2);
+                let retro = (1. + (fd90 - 1.) * schlick_weight(wi.z))
+                    * (1. + (fd90 - 1.) * schlick_weight(wo.z));
+                let sheen = schlick_weight(cos_d) * self.sheen.clone();
+                wi.z * (retro / PI * self.base_color.clone() + sheen)
+            }
+            Lobe::Specular => {
+                let value = Ggx::from_roughness(self.roughness).reflection(wo, wi);
+                if value <= 0. {
+                    return Color::BLACK;
+                }
+                let m = (wo + wi).unit();
+                let specular = Color::grey(0.08 * self.specular);
+                let f0 = mix(specular, self.base_color.clone(), self.metallic);
+                value * mix(f0, Color::WHITE, schlick_weight(dot(wo, m.get())))
+            }
+            Lobe::Transmission => {
+                let ggx = Ggx::from_roughness(self.roughness);
+                let (value, _) = ggx.dielectric(wo, wi, self.refraction_ratio);
+                if wi.z < 0. {
+                    value * self.base_color.clone()
+                } else {
+                    Color::grey(value)
+                }
+            }
+            Lobe::Clearcoat => {
+                let value = Ggx::from_roughness(CLEARCOAT_ROUGHNESS).reflection(wo, wi);
+                if value <= 0. {
+                    return Color::BLACK;
+                }
+                let m = (wo + wi).unit();
+                let fresnel = fresnel_dielectric(dot(wo, m.get()), 1. / CLEARCOAT_REFRACTION_INDEX);
+                Color::grey(value * fresnel)
+            }
+        }
+    }
+
+    /// Density of `wi` when sampling a lobe, in the local shading frame.
+    fn pdf(&self, lobe: Lobe, wo: &Vec3, wi: &Vec3) -> f32 {
+        match lobe {
+            Lobe::Diffuse => {
+                if wo.z <= 0. {
+                    return 0.;
+                }
+                cosine_hemisphere_pdf(wi.z)
+            }
+            Lobe::Specular => Ggx::from_roughness(self.roughness).reflection_pdf(wo, wi),
+            Lobe::Transmission => {
+                let ggx = Ggx::from_roughness(self.roughness);
+                ggx.dielectric(wo, wi, self.refraction_ratio).1
+            }
+            Lobe::Clearcoat => Ggx::from_roughness(CLEARCOAT_ROUGHNESS).reflection_pdf(wo, wi),
+        }
+    }
+
+    /// Samples a direction from a lobe, in the local shading frame.
+    fn sample(&self, lobe: Lobe, wo: &Vec3, sampler: &mut dyn Sampler) -> Option<Vec3> {
+        let reflect = |roughness: f32, sampler: &mut dyn Sampler| {
+            let ggx = Ggx::from_roughness(roughness);
+            let m = ggx.sample_visible(wo, sampler.get_2d());
+            Some(reflect_around(wo, &m)).filter(|wi| wi.z > 0.)
+        };
+        match lobe {
+            Lobe::Diffuse => Some(cosine_hemisphere(sampler)),
+            Lobe::Specular => reflect(self.roughness, sampler),
+            Lobe::Transmission => {
+                let ggx = Ggx::from_roughness(self.roughness);
+                let m = ggx.sample_visible(wo, sampler.get_2d());
+                if sampler.get_1d() < fresnel_dielectric(dot(wo, &m), self.refraction_ratio) {
+                    Some(reflect_around(wo, &m)).filter(|wi| wi.z > 0.)
+                } else {
+                    refract_through(wo, &m, self.refraction_ratio).filter(|wi| wi.z < 0.)
+                }
+            }
+            Lobe::Clearcoat => reflect(CLEARCOAT_ROUGHNESS, sampler),
+        }
+    }
+
+    fn eval_all(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        self.lobes()
+            .iter()
+            .filter(|(_, weight)| *weight > 0.)
+            .fold(Color::BLACK, |sum, (lobe, weight)| {
+                sum + *weight * self.eval(*lobe, wo, wi)
+            })
+    }
+
+    fn pdf_all(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        let lobes = self.lobes();
+        let total: f32 = lobes.iter().map(|(_, weight)| weight).sum();
+        lobes
+            .iter()
+            .filter(|(_, weight)| *weight > 0.)
+            .map(|(lobe, weight)| weight / total * self.pdf(*lobe, wo, wi))
+            .sum()
+    }
+}
+
+impl Principled {
+    pub fn new(base_color: Colorer) -> Self {
+        Self {
+            base_color,
+            metallic: Colorer::Solid(Color::BLACK),
+            roughness: Colorer::Solid(Color::grey(0.5)),
+            specular: Colorer::Solid(Color::grey(0.5)),
+            clearcoat: Colorer::Solid(Color::BLACK),
+            sheen: Colorer::Solid(Color::BLACK),
+            transmission: Colorer::Solid(Color::BLACK),
+            refraction_index: 0.67,
+        }
+    }
+
+    fn parameters(&self, hit: &Hit) -> Parameters {
+        let scalar = |colorer: &Colorer| colorer.color(hit).luminance().clamp(0., 1.);
+        Parameters {
+            base_color: self.base_color.color(hit),
+            metallic: scalar(&self.metallic),
+            roughness: scalar(&self.roughness),
+            specular: scalar(&self.specular),
+            clearcoat: scalar(&self.clearcoat),
+            sheen: self.sheen.color(hit),
+            transmission: scalar(&self.transmission),
+            refraction_ratio: refraction_ratio(hit, self.refraction_index),
+        }
+    }
+
+    pub(super) fn sample(
+        &self,
+        hit: &Hit,
+        wo: &Vec3,
+        sampler: &mut dyn Sampler,
+    ) -> Option<BsdfSample> {
+        let parameters = self.parameters(hit);
+        let lobes = parameters.lobes();
+        let total: f32 = lobes.iter().map(|(_, weight)| weight).sum();
+        let mut choice = sampler.get_1d() * total;
+        let lobe = lobes
+            .iter()
+            .find(|(_, weight)| {
+                choice -= weight;
+                choice < 0.
+            })
+            .map_or(Lobe::Specular, |(lobe, _)| *lobe);
+
+        let frame = shading_frame(hit, wo);
+        let wo = frame.to_local(wo);
+        let wi = parameters.sample(lobe, &wo, sampler)?;
+
+        // the sample could have come from any lobe, weigh it with the whole mixture
+        let pdf = parameters.pdf_all(&wo, &wi);
+        if pdf <= 0. {
+            return None;
+        }
+        Some(BsdfSample {
+            weight: parameters.eval_all(&wo, &wi) / pdf,
+            wi: frame.to_world(&wi),
+            pdf,
+            specular: false,
+        })
+    }
+
+    pub(super) fn eval(&self, hit: &Hit, wi: &Vec3, wo: &Vec3) -> Color {
+        let frame = shading_frame(hit, wo);
+        self.parameters(hit)
+            .eval_all(&frame.to_local(wo), &frame.to_local(wi))
+    }
+
+    pub(super) fn pdf(&self, hit: &Hit, wi: &Vec3, wo: &Vec3) -> f32 {
+        let frame = shading_frame(hit, wo);
+        self.parameters(hit)
+            .pdf_all(&frame.to_local(wo), &frame.to_local(wi))
+    }
+}
@@ -0,0 +1,387 @@
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// Dimensions used by the camera, to jitter samples within their pixel.
+const CAMERA_DIMENSIONS: usize = 2;
+/// Dimensions reserved for each bounce, so that they line up across samples of a pixel unless a
+/// bounce uses more of them.
+const BOUNCE_DIMENSIONS: usize = 8;
+
+/// Source of the uniform samples in [0, 1) used to render pixels.
+///
+/// Samples are drawn from successive dimensions, which low-discrepancy samplers spread evenly
+/// across the samples of a pixel.
+pub trait Sampler {
+    /// Starts drawing the `index`-th sample of the pixel, from its first dimension.
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize);
+
+    /// Skips to the dimensions reserved for the given bounce, unless previous ones went past
+    /// them.
+    fn start_bounce(&mut self, bounce: usize);
+
+    fn get_1d(&mut self) -> f32;
+
+    fn get_2d(&mut self) -> (f32, f32);
+}
+
+/// Independent samples, the random number generator ignoring pixels and dimensions.
+impl Sampler for SmallRng {
+    fn start_pixel_sample(&mut self, _pixel: (usize, usize), _index: usize) {}
+
+    fn start_bounce(&mut self, _bounce: usize) {}
+
+    fn get_1d(&mut self) -> f32 {
+        self.gen()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.gen(), self.gen())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum SamplerKind {
+    #[default]
+    Independent,
+    /// Jittered samples, each in a different stratum of every dimension.
+    Stratified,
+    /// Halton sequence with randomly permuted digits.
+    Halton,
+    /// Sobol sequence with hash-based Owen scrambling, padded to any number of dimensions.
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn sampler(self, samples_per_pixel: usize, seed: u64) -> Box<dyn Sampler + Send> {
+        let state = SampleState {
+            seed,
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0,
+        };
+        match self {
+            SamplerKind::Independent => Box::new(SmallRng::seed_from_u64(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler {
+                samples_per_pixel: samples_per_pixel.max(1),
+                state,
+            }),
+            SamplerKind::Halton => Box::new(HaltonSampler { state }),
+            SamplerKind::Sobol => Box::new(SobolSampler { state }),
+        }
+    }
+}
+
+/// SplitMix64 finalizer.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+fn hash(a: u64, b: u64) -> u64 {
+    mix(a ^ mix(b).wrapping_add(0x9e3779b97f4a7c15))
+}
+
+fn hashed_float(hash: u64) -> f32 {
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+fn to_float(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1u32 << 24) as f32
+}
+
+/// Element `i` of a random permutation of `0..n` picked by `seed`, after Kensler, "Correlated
+/// Multi-Jittered Sampling", 2013.
+fn permutation_element(i: u32, n: u32, seed: u32) -> u32 {
+    let mut mask = n.wrapping_sub(1);
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+    let mut i = i;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & mask) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & mask) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & mask) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= mask;
+        i ^= i >> 5;
+        // cycle walking, values past `n` are permuted again
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % n
+}
+
+/// Pixel sample and dimension being drawn, common to all low-discrepancy samplers.
+struct SampleState {
+    seed: u64,
+    pixel_seed: u64,
+    index: usize,
+    dimension: usize,
+}
+
+impl SampleState {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize) {
+        self.pixel_seed = hash(hash(self.seed, pixel.0 as u64), pixel.1 as u64);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn start_bounce(&mut self, bounce: usize) {
+        let first = CAMERA_DIMENSIONS + bounce * BOUNCE_DIMENSIONS;
+        self.dimension = self.dimension.max(first);
+    }
+
+    /// Consumes `count` dimensions, returning the first one.
+    fn next(&mut self, count: usize) -> usize {
+        self.dimension += count;
+        self.dimension - count
+    }
+
+    /// Seed specific to the pixel and dimension, shared by all samples of the pixel.
+    fn dimension_seed(&self, dimension: usize) -> u64 {
+        hash(self.pixel_seed, dimension as u64)
+    }
+}
+
+struct StratifiedSampler {
+    samples_per_pixel: usize,
+    state: SampleState,
+}
+
+impl StratifiedSampler {
+    /// Jittered position in a stratum of `0..count` assigned to the current sample.
+    fn stratum(&self, count: usize, seed: u64) -> (usize, f32) {
+        let index = (self.state.index % count) as u32;
+        let stratum = permutation_element(index, count as u32, seed as u32);
+        let jitter = hashed_float(hash(seed, self.state.index as u64));
+        (stratum as usize, jitter)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize) {
+        self.state.start_pixel_sample(pixel, index);
+    }
+
+    fn start_bounce(&mut self, bounce: usize) {
+        self.state.start_bounce(bounce);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.state.next(1);
+        let seed = self.state.dimension_seed(dimension);
+        let (stratum, jitter) = self.stratum(self.samples_per_pixel, seed);
+        (stratum as f32 + jitter) / self.samples_per_pixel as f32
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let dimension = self.state.next(2);
+        let seed = self.state.dimension_seed(dimension);
+        // the grid can have more cells than samples, in which case some are left out
+        let columns = (self.samples_per_pixel as f32).sqrt() as usize;
+        let rows = self.samples_per_pixel.div_ceil(columns);
+        let (cell, jitter_x) = self.stratum(columns * rows, seed);
+        let jitter_y = hashed_float(hash(seed ^ 1, self.state.index as u64));
+        (
+            ((cell % columns) as f32 + jitter_x) / columns as f32,
+            ((cell / columns) as f32 + jitter_y) / rows as f32,
+        )
+    }
+}
+
+/// Bases of the dimensions of the Halton sequence, later dimensions use independent samples.
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+/// Radical inverse of `index` in the given base, each digit being permuted depending on its
+/// position.
+fn scrambled_radical_inverse(base: u32, index: usize, seed: u64) -> f32 {
+    let mut index = index as u64;
+    let mut result = 0.;
+    let mut weight = 1. / base as f64;
+    let mut position = 0;
+    // the permutations also apply to the infinite trailing zeros, until they become negligible
+    while weight > 1e-9 {
+        let digit = (index % base as u64) as u32;
+        let permuted = permutation_element(digit, base, hash(seed, position) as u32);
+        result += permuted as f64 * weight;
+        index /= base as u64;
+        weight /= base as f64;
+        position += 1;
+    }
+    (result as f32).min(1. - f32::EPSILON)
+}
+
+struct HaltonSampler {
+    state: SampleState,
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize) {
+        self.state.start_pixel_sample(pixel, index);
+    }
+
+    fn start_bounce(&mut self, bounce: usize) {
+        self.state.start_bounce(bounce);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.state.next(1);
+        let seed = self.state.dimension_seed(dimension);
+        match PRIMES.get(dimension) {
+            Some(base) => scrambled_radical_inverse(*base, self.state.index, seed),
+            None => hashed_float(hash(seed, self.state.index as u64)),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// Laine-Karras style hash, which keeps lower bits from depending on higher ones.
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+/// Owen scrambling, a random permutation of each digit depending on the ones before it.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// First two dimensions of the Sobol sequence, the first being the van der Corput sequence.
+fn sobol(index: u32, dimension: usize) -> u32 {
+    if dimension == 0 {
+        return index.reverse_bits();
+    }
+    let mut result = 0;
+    let mut direction = 1 << 31;
+    let mut index = index;
+    while index != 0 {
+        if index & 1 == 1 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// Sobol samples padded to any dimension by shuffling, after Burley, "Practical Hash-based Owen
+/// Scrambling", 2020.
+struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    fn sample(&mut self, dimensions: usize) -> [f32; 2] {
+        let dimension = self.state.next(dimensions);
+        let seed = self.state.dimension_seed(dimension) as u32;
+        let index = nested_uniform_scramble(self.state.index as u32, seed);
+        let mut values = [0.; 2];
+        for (dimension, value) in values.iter_mut().enumerate().take(dimensions) {
+            let seed = hash(seed as u64, dimension as u64) as u32;
+            *value = to_float(nested_uniform_scramble(sobol(index, dimension), seed));
+        }
+        values
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize) {
+        self.state.start_pixel_sample(pixel, index);
+    }
+
+    fn start_bounce(&mut self, bounce: usize) {
+        self.state.start_bounce(bounce);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.sample(1)[0]
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let [x, y] = self.sample(2);
+        (x, y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    #[test]
+    fn samples_are_uniform() {
+        for kind in KINDS {
+            let mut sampler = kind.sampler(16, 0);
+            let (mut sum, mut count) = (0., 0);
+            for pixel in 0..64 {
+                for index in 0..16 {
+                    sampler.start_pixel_sample((pixel, 0), index);
+                    for bounce in 0..4 {
+                        sampler.start_bounce(bounce);
+                        let (x, y) = sampler.get_2d();
+                        for value in [sampler.get_1d(), x, y] {
+                            assert!((0. ..1.).contains(&value), "{:?} {}", kind, value);
+                            sum += value;
+                            count += 1;
+                        }
+                    }
+                }
+            }
+            let mean = sum / count as f32;
+            assert!((mean - 0.5).abs() < 0.01, "{:?} {}", kind, mean);
+        }
+    }
+
+    #[test]
+    fn pixel_samples_are_stratified() {
+        const SAMPLES: usize = 16;
+        for kind in [SamplerKind::Stratified, SamplerKind::Sobol] {
+            let mut sampler = kind.sampler(SAMPLES, 0);
+            let mut cells = [0; SAMPLES];
+            let mut strata = [0; SAMPLES];
+            for index in 0..SAMPLES {
+                sampler.start_pixel_sample((3, 7), index);
+                let (x, y) = sampler.get_2d();
+                cells[(4. * x) as usize + 4 * (4. * y) as usize] += 1;
+                strata[(SAMPLES as f32 * sampler.get_1d()) as usize] += 1;
+            }
+            assert_eq!(cells, [1; SAMPLES], "{:?}", kind);
+            assert_eq!(strata, [1; SAMPLES], "{:?}", kind);
+        }
+    }
+}
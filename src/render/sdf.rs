@@ -0,0 +1,490 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use serde::{Deserialize, Serialize};
+
+use crate::math::dot;
+use crate::render::hittable::{spherical_tangent, spherical_uv};
+use crate::render::{Hit, Hittable, Material, Ray};
+use crate::types::{Normal, Point, UnitVec3, Vec3};
+
+const MAX_STEPS: usize = 256;
+const MAX_ESCAPE_STEPS: usize = 32;
+const HIT_EPSILON: f32 = 1e-4;
+const NORMAL_EPSILON: f32 = 1e-4;
+
+/// Expression tree of a signed distance field.
+///
+/// Primitives are centered on the origin, use `Translate` to move them around.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum SdfNode {
+    Sphere {
+        radius: f32,
+    },
+    Box {
+        half_extents: Vec3,
+    },
+    RoundBox {
+        half_extents: Vec3,
+        radius: f32,
+    },
+    /// Torus lying in the XY plane.
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    Mandelbulb {
+        power: f32,
+        iterations: usize,
+    },
+    Translate {
+        offset: Vec3,
+        node: Box<SdfNode>,
+    },
+    Scale {
+        factor: f32,
+        node: Box<SdfNode>,
+    },
+    /// Rotates the node around the Z axis by `amount` radians per unit of height.
+    Twist {
+        amount: f32,
+        node: Box<SdfNode>,
+    },
+    /// Infinitely repeats the node, a zero period disables repetition along that axis.
+    Repeat {
+        period: Vec3,
+        node: Box<SdfNode>,
+    },
+    Union(Box<SdfNode>, Box<SdfNode>),
+    Intersection(Box<SdfNode>, Box<SdfNode>),
+    Subtraction(Box<SdfNode>, Box<SdfNode>),
+    SmoothUnion {
+        a: Box<SdfNode>,
+        b: Box<SdfNode>,
+        smoothness: f32,
+    },
+}
+
+fn max_vec(v: &Vec3, m: f32) -> Vec3 {
+    Vec3::new(v.x.max(m), v.y.max(m), v.z.max(m))
+}
+
+fn abs_vec(v: &Vec3) -> Vec3 {
+    Vec3::new(v.x.abs(), v.y.abs(), v.z.abs())
+}
+
+fn repeat(coord: f32, period: f32) -> f32 {
+    if period == 0. {
+        coord
+    } else {
+        coord - period * (coord / period).round()
+    }
+}
+
+fn mandelbulb(p: &Vec3, power: f32, iterations: usize) -> f32 {
+    let mut z = p.clone();
+    let mut dr = 1.;
+    let mut r = z.len();
+    for _ in 0..iterations {
+        r = z.len();
+        if r > 2. || r == 0. {
+            break;
+        }
+        let theta = (z.z / r).acos() * power;
+        let phi = z.y.atan2(z.x) * power;
+        dr = r.powf(power - 1.) * power * dr + 1.;
+        let zr = r.powf(power);
+        z = Vec3::new(
+            zr * theta.sin() * phi.cos() + p.x,
+            zr * theta.sin() * phi.sin() + p.y,
+            zr * theta.cos() + p.z,
+        );
+    }
+    if r == 0. {
+        return 0.;
+    }
+    0.5 * r.ln() * r / dr
+}
+
+impl SdfNode {
+    pub fn distance(&self, p: &Point) -> f32 {
+        self.distance_vec(&Vec3::new(p.x, p.y, p.z))
+    }
+
+    fn distance_vec(&self, p: &Vec3) -> f32 {
+        match self {
+            SdfNode::Sphere { radius } => p.len() - radius,
+            SdfNode::Box { half_extents } => {
+                let q = abs_vec(p) - half_extents;
+                max_vec(&q, 0.).len() + q.x.max(q.y.max(q.z)).min(0.)
+            }
+            SdfNode::RoundBox {
+                half_extents,
+                radius,
+            } => {
+                let q = abs_vec(p) - half_extents + Vec3::new(*radius, *radius, *radius);
+                max_vec(&q, 0.).len() + q.x.max(q.y.max(q.z)).min(0.) - radius
+            }
+            SdfNode::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = (p.x.powi(2) + p.y.powi(2)).sqrt() - major_radius;
+                (ring.powi(2) + p.z.powi(2)).sqrt() - minor_radius
+            }
+            SdfNode::Mandelbulb { power, iterations } => mandelbulb(p, *power, *iterations),
+            SdfNode::Translate { offset, node } => node.distance_vec(&(p - offset)),
+            SdfNode::Scale { factor, node } => node.distance_vec(&(p / *factor)) * factor,
+            SdfNode::Twist { amount, node } => {
+                let angle = amount * p.z;
+                let (sin, cos) = angle.sin_cos();
+                let q = Vec3::new(cos * p.x - sin * p.y, sin * p.x + cos * p.y, p.z);
+                // twisting stretches space, shrink the distance so that tracing doesn't overshoot
+                let stretch = (1. + (amount * (p.x.powi(2) + p.y.powi(2)).sqrt()).powi(2)).sqrt();
+                node.distance_vec(&q) / stretch
+            }
+            SdfNode::Repeat { period, node } => {
+                let q = Vec3::new(
+                    repeat(p.x, period.x),
+                    repeat(p.y, period.y),
+                    repeat(p.z, period.z),
+                );
+                node.distance_vec(&q)
+            }
+            SdfNode::Union(a, b) => a.distance_vec(p).min(b.distance_vec(p)),
+            SdfNode::Intersection(a, b) => a.distance_vec(p).max(b.distance_vec(p)),
+            SdfNode::Subtraction(a, b) => a.distance_vec(p).max(-b.distance_vec(p)),
+            SdfNode::SmoothUnion { a, b, smoothness } => {
+                let da = a.distance_vec(p);
+                let db = b.distance_vec(p);
+                if *smoothness <= 0. {
+                    return da.min(db);
+                }
+                let h = (0.5 + 0.5 * (db - da) / smoothness).clamp(0., 1.);
+                db * (1. - h) + da * h - smoothness * h * (1. - h)
+            }
+        }
+    }
+
+    /// Sphere enclosing the surface, as its center and radius, or `None` if it is unbounded.
+    fn bounds(&self) -> Option<(Vec3, f32)> {
+        match self {
+            SdfNode::Sphere { radius } => Some((Vec3::new(0., 0., 0.), *radius)),
+            SdfNode::Box { half_extents } | SdfNode::RoundBox { half_extents, .. } => {
+                Some((Vec3::new(0., 0., 0.), half_extents.len()))
+            }
+            SdfNode::Torus {
+                major_radius,
+                minor_radius,
+            } => Some((Vec3::new(0., 0., 0.), major_radius + minor_radius)),
+            // points further than 2 from the origin escape right away
+            SdfNode::Mandelbulb { .. } => Some((Vec3::new(0., 0., 0.), 2.)),
+            SdfNode::Translate { offset, node } => {
+                let (center, radius) = node.bounds()?;
+                Some((center + offset, radius))
+            }
+            SdfNode::Scale { factor, node } => {
+                let (center, radius) = node.bounds()?;
+                Some((*factor * &center, factor.abs() * radius))
+            }
+            SdfNode::Twist { node, .. } => {
+                // the twist sweeps the node around the Z axis
+                let (center, radius) = node.bounds()?;
+                let axis_distance = (center.x.powi(2) + center.y.powi(2)).sqrt() + radius;
+                let radius = (axis_distance.powi(2) + radius.powi(2)).sqrt();
+                Some((Vec3::new(0., 0., center.z), radius))
+            }
+            SdfNode::Repeat { period, node } => {
+                if period.x != 0. || period.y != 0. || period.z != 0. {
+                    None
+                } else {
+                    node.bounds()
+                }
+            }
+            SdfNode::Union(a, b) => Some(enclosing(a.bounds()?, b.bounds()?)),
+            SdfNode::SmoothUnion { a, b, smoothness } => {
+                // blending only brings the surface closer by a quarter of the smoothness
+                let (center, radius) = enclosing(a.bounds()?, b.bounds()?);
+                Some((center, radius + smoothness.max(0.) / 4.))
+            }
+            SdfNode::Intersection(a, b) => match (a.bounds(), b.bounds()) {
+                (Some(a), Some(b)) => Some(if a.1 < b.1 { a } else { b }),
+                (a, b) => a.or(b),
+            },
+            SdfNode::Subtraction(a, _) => a.bounds(),
+        }
+    }
+
+    /// Gradient of the field, estimated with the tetrahedron technique.
+    fn gradient(&self, p: &Point) -> Vec3 {
+        let e = NORMAL_EPSILON * FRAC_1_SQRT_2;
+        let offsets = [
+            Vec3::new(e, -e, -e),
+            Vec3::new(-e, -e, e),
+            Vec3::new(-e, e, -e),
+            Vec3::new(e, e, e),
+        ];
+        offsets.iter().fold(Vec3::new(0., 0., 0.), |acc, offset| {
+            acc + self.distance(&(p + offset)) * offset
+        })
+    }
+}
+
+/// Smallest sphere enclosing both spheres.
+fn enclosing(a: (Vec3, f32), b: (Vec3, f32)) -> (Vec3, f32) {
+    let ((a_center, a_radius), (b_center, b_radius)) = (a, b);
+    let offset = &b_center - &a_center;
+    let distance = offset.len();
+    if distance + b_radius <= a_radius {
+        return (a_center, a_radius);
+    }
+    if distance + a_radius <= b_radius {
+        return (b_center, b_radius);
+    }
+    let radius = (distance + a_radius + b_radius) / 2.;
+    let center = a_center + ((radius - a_radius) / distance) * &offset;
+    (center, radius)
+}
+
+/// Surface defined by a signed distance field, rendered by sphere tracing.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Sdf {
+    pub root: SdfNode,
+    pub material: Material,
+}
+
+impl Hittable for Sdf {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit<'_>> {
+        let len = ray.direction.len();
+        // only march along the part of the ray inside the bounds, with some room for the
+        // tolerance of hits
+        let (entry, t_max) = match self.root.bounds() {
+            Some((center, radius)) => {
+                let origin = Vec3::new(ray.origin.x, ray.origin.y, ray.origin.z);
+                let oc = origin - center;
+                let radius = radius + 2. * HIT_EPSILON;
+                let a = dot(&ray.direction, &ray.direction);
+                let half_b = dot(&oc, &ray.direction);
+                let c = dot(&oc, &oc) - radius * radius;
+                let discriminant = half_b * half_b - a * c;
+                if discriminant < 0. {
+                    return None;
+                }
+                let root = discriminant.sqrt();
+                ((-half_b - root) / a, t_max.min((-half_b + root) / a))
+            }
+            None => (t_min, t_max),
+        };
+        let mut t = t_min;
+
+        // rays that start on the surface (e.g. after a bounce) must leave it before tracing
+        for _ in 0..MAX_ESCAPE_STEPS {
+            if self.root.distance(&ray.at(t)).abs() >= 2. * HIT_EPSILON {
+                break;
+            }
+            t += HIT_EPSILON / len;
+        }
+        t = t.max(entry);
+
+        for _ in 0..MAX_STEPS {
+            if t > t_max {
+                return None;
+            }
+            let point = ray.at(t);
+            let distance = self.root.distance(&point).abs();
+            if distance < HIT_EPSILON {
+                let gradient = self.root.gradient(&point).unit();
+                // there is no natural parametrization of arbitrary fields, use the normal instead
+                let (u, v) = spherical_uv(gradient.get());
+                let tangent = spherical_tangent(gradient.get());
+                let normal = if dot(&ray.direction, gradient.get()) > 0. {
+                    Normal::Inward(UnitVec3::unchecked_from(gradient.get()))
+                } else {
+                    Normal::Outward(gradient)
+                };
+                return Some(Hit {
+                    travel: t,
+                    distance: t * len,
+                    point,
+                    normal,
+                    material: &self.material,
+                    u,
+                    v,
+                    tangent,
+                });
+            }
+            t += distance / len;
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{Color, Colorer};
+
+    fn sphere(radius: f32) -> Box<SdfNode> {
+        Box::new(SdfNode::Sphere { radius })
+    }
+
+    #[test]
+    fn primitive_distances() {
+        let cases = [
+            (*sphere(1.), Point::new(0., 0., 3.), 2.),
+            (*sphere(1.), Point::new(0., 0., 0.), -1.),
+            (
+                SdfNode::Box {
+                    half_extents: Vec3::new(1., 2., 3.),
+                },
+                Point::new(4., 0., 0.),
+                3.,
+            ),
+            (
+                SdfNode::Box {
+                    half_extents: Vec3::new(1., 2., 3.),
+                },
+                Point::new(0., 0., 0.),
+                -1.,
+            ),
+            (
+                SdfNode::RoundBox {
+                    half_extents: Vec3::new(1., 1., 1.),
+                    radius: 0.5,
+                },
+                Point::new(2., 0., 0.),
+                1.,
+            ),
+            (
+                SdfNode::Torus {
+                    major_radius: 2.,
+                    minor_radius: 0.5,
+                },
+                Point::new(2., 0., 1.),
+                0.5,
+            ),
+            (
+                SdfNode::Translate {
+                    offset: Vec3::new(0., 5., 0.),
+                    node: sphere(1.),
+                },
+                Point::new(0., 5., 2.),
+                1.,
+            ),
+            (
+                SdfNode::Scale {
+                    factor: 2.,
+                    node: sphere(1.),
+                },
+                Point::new(3., 0., 0.),
+                1.,
+            ),
+            (
+                SdfNode::Repeat {
+                    period: Vec3::new(4., 0., 0.),
+                    node: sphere(1.),
+                },
+                Point::new(8., 0., 2.),
+                1.,
+            ),
+            (
+                SdfNode::Union(sphere(1.), sphere(2.)),
+                Point::new(3., 0., 0.),
+                1.,
+            ),
+            (
+                SdfNode::Intersection(sphere(1.), sphere(2.)),
+                Point::new(3., 0., 0.),
+                2.,
+            ),
+            (
+                SdfNode::Subtraction(sphere(2.), sphere(1.)),
+                Point::new(0., 0., 0.),
+                1.,
+            ),
+        ];
+        for (node, point, expected) in cases.iter() {
+            let distance = node.distance(point);
+            assert!(
+                (distance - expected).abs() < 1e-5,
+                "{:?} {} {}",
+                point,
+                distance,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn sphere_tracing_converges() {
+        let sdf = Sdf {
+            root: SdfNode::Translate {
+                offset: Vec3::new(0., 5., 0.),
+                node: sphere(1.),
+            },
+            material: Material::Diffuse(Colorer::Solid(Color::WHITE)),
+        };
+
+        // the travel is a ray parameter, which scales with the length of the direction
+        for length in [1., 0.5, 3.] {
+            let ray = Ray {
+                origin: Point::new(0., 0., 0.),
+                direction: Vec3::new(0., length, 0.),
+            };
+            let hit = sdf.hit(&ray, 0.001, f32::INFINITY).unwrap();
+            assert!((hit.travel * length - 4.).abs() < 1e-3, "{}", hit.travel);
+            assert!((hit.distance - 4.).abs() < 1e-3, "{}", hit.distance);
+            assert!((hit.point.y - 4.).abs() < 1e-3, "{:?}", hit.point);
+            let normal = hit.normal.outward();
+            assert!(matches!(hit.normal, Normal::Outward(_)));
+            assert!((normal.get().y + 1.).abs() < 1e-3, "{:?}", normal.get());
+        }
+
+        // rays starting on the surface leave it and hit its far side from inside
+        let ray = Ray {
+            origin: Point::new(0., 4., 0.),
+            direction: Vec3::new(0., 1., 0.),
+        };
+        let hit = sdf.hit(&ray, 0.001, f32::INFINITY).unwrap();
+        assert!((hit.point.y - 6.).abs() < 1e-3, "{:?}", hit.point);
+        assert!(matches!(hit.normal, Normal::Inward(_)));
+
+        let miss = Ray {
+            origin: Point::new(0., 0., 0.),
+            direction: Vec3::new(0., 1., 0.5),
+        };
+        assert!(sdf.hit(&miss, 0.001, f32::INFINITY).is_none());
+        assert!(sdf.hit(&ray, 0.001, 1.).is_none());
+    }
+
+    #[test]
+    fn distant_fields_are_hit() {
+        let sdf = Sdf {
+            root: SdfNode::Union(
+                Box::new(SdfNode::Translate {
+                    offset: Vec3::new(0., 500., 0.),
+                    node: sphere(1.),
+                }),
+                Box::new(SdfNode::Translate {
+                    offset: Vec3::new(0., -500., 0.),
+                    node: Box::new(SdfNode::Scale {
+                        factor: 2.,
+                        node: sphere(1.),
+                    }),
+                }),
+            ),
+            material: Material::Diffuse(Colorer::Solid(Color::WHITE)),
+        };
+        let (center, radius) = sdf.root.bounds().unwrap();
+        assert!((center.y + 0.5).abs() < 1e-3 && (radius - 501.5).abs() < 1e-3);
+
+        for (y, expected) in [(1., 499.), (-1., 498.)] {
+            let ray = Ray {
+                origin: Point::new(0., 0., 0.),
+                direction: Vec3::new(0., y, 0.),
+            };
+            let hit = sdf.hit(&ray, 0.001, f32::INFINITY).unwrap();
+            assert!((hit.travel - expected).abs() < 1e-2, "{}", hit.travel);
+        }
+    }
+}
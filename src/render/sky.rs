@@ -0,0 +1,155 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::math::{deg_to_radians, dot};
+use crate::render::{Color, Light};
+use crate::types::Vec3;
+
+/// Angular radius of the sun seen from the ground, in degrees.
+const SUN_ANGULAR_RADIUS: f32 = 0.27;
+/// Illuminance of the sun outside of the atmosphere, in kilolux.
+const SUN_ILLUMINANCE: f32 = 127.;
+/// Radiance of a luminance of one kilocandela per square meter.
+const SCALE: f32 = 0.1;
+
+/// Analytic daylight sky, after Preetham et al., "A Practical Analytic Model for Daylight", 1999.
+///
+/// The sun itself is not part of the sky, it is lit by the directional light returned by `sun`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sky {
+    /// Angle of the sun above the horizon, in degrees.
+    pub elevation: f32,
+    /// Angle of the sun around the Z axis, from the X axis, in degrees.
+    pub azimuth: f32,
+    /// Haziness of the atmosphere, from 2 for a clear sky to 10 for a hazy one.
+    pub turbidity: f32,
+}
+
+/// Perez distribution of the sky luminance or chromaticity, relative to its zenith value.
+fn perez(coefficients: [f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = coefficients;
+    (1. + a * (b / cos_theta).exp()) * (1. + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+impl Sky {
+    /// Zenith angle of the sun, which is not modeled below the horizon.
+    fn sun_zenith(&self) -> f32 {
+        PI / 2. - deg_to_radians(self.elevation.clamp(0., 90.))
+    }
+
+    /// Unit direction towards the sun.
+    pub fn sun_direction(&self) -> Vec3 {
+        let elevation = deg_to_radians(self.elevation);
+        let azimuth = deg_to_radians(self.azimuth);
+        Vec3::new(
+            elevation.cos() * azimuth.cos(),
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+        )
+    }
+
+    /// Color of the sky in the given unit direction, directions below the horizon seeing the
+    /// horizon.
+    pub fn color(&self, direction: &Vec3) -> Color {
+        let t = self.turbidity;
+        let theta_s = self.sun_zenith();
+        let cos_theta = direction.z.max(1e-3);
+        let gamma = dot(direction, &self.sun_direction()).clamp(-1., 1.).acos();
+
+        let luminance = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        let x = [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ];
+        let y = [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ];
+
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let polynomial =
+            |c: [f32; 4]| c[0] * theta_s.powi(3) + c[1] * theta_s.powi(2) + c[2] * theta_s + c[3];
+        let zenith_x = t * t * polynomial([0.00166, -0.00375, 0.00209, 0.])
+            + t * polynomial([-0.02903, 0.06377, -0.03202, 0.00394])
+            + polynomial([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y = t * t * polynomial([0.00275, -0.00610, 0.00317, 0.])
+            + t * polynomial([-0.04214, 0.08970, -0.04153, 0.00516])
+            + polynomial([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let relative =
+            |coefficients| perez(coefficients, cos_theta, gamma) / perez(coefficients, 1., theta_s);
+        let big_y = zenith_luminance * relative(luminance);
+        let x = zenith_x * relative(x);
+        let y = zenith_y * relative(y);
+
+        SCALE * Color::from_xyz(x / y * big_y, big_y, (1. - x - y) / y * big_y)
+    }
+
+    /// Directional light of the sun, attenuated by the atmosphere, if it is above the horizon.
+    pub fn sun(&self) -> Option<Light> {
+        if self.elevation <= 0. {
+            return None;
+        }
+        let theta_s = self.sun_zenith();
+        // relative optical mass of the atmosphere, Kasten's formula
+        let mass = 1. / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        // Rayleigh and aerosol transmittances at a representative wavelength in micrometers
+        let transmittance = |lambda: f32| {
+            let rayleigh = -0.008735 * lambda.powf(-4.08) * mass;
+            let aerosol = -beta * lambda.powf(-1.3) * mass;
+            (rayleigh + aerosol).exp()
+        };
+        Some(Light::Directional {
+            direction: -self.sun_direction(),
+            color: Color::new(
+                transmittance(0.65),
+                transmittance(0.57),
+                transmittance(0.475),
+            ),
+            intensity: SCALE * SUN_ILLUMINANCE,
+            angular_radius: SUN_ANGULAR_RADIUS,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sky(elevation: f32) -> Sky {
+        Sky {
+            elevation,
+            azimuth: 90.,
+            turbidity: 3.,
+        }
+    }
+
+    #[test]
+    fn daylight_colors() {
+        let zenith = sky(45.).color(&Vec3::new(0., 0., 1.));
+        assert!(zenith.b > zenith.r, "{:?}", zenith);
+
+        let sun_color = |elevation| match sky(elevation).sun() {
+            Some(Light::Directional { color, .. }) => color,
+            _ => panic!("Expected a directional sun"),
+        };
+        let (high, low) = (sun_color(60.), sun_color(5.));
+        assert!(low.r / low.b > high.r / high.b, "{:?} {:?}", high, low);
+        assert!(sky(-10.).sun().is_none());
+    }
+}
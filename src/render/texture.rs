@@ -0,0 +1,170 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hdr::read_hdr;
+use crate::ppm::read_ppm;
+use crate::render::Color;
+
+/// How texture coordinates outside of [0, 1] are mapped back into the texture.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Wrap {
+    Repeat,
+    Mirror,
+    Clamp,
+}
+
+impl Wrap {
+    fn apply(self, i: isize, size: usize) -> usize {
+        let size = size as isize;
+        let wrapped = match self {
+            Wrap::Repeat => i.rem_euclid(size),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+            Wrap::Clamp => i.clamp(0, size - 1),
+        };
+        wrapped as usize
+    }
+}
+
+/// Image loaded from a PNG, PPM or Radiance HDR file, referenced by path in scenes.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Texture {
+    path: String,
+    width: usize,
+    height: usize,
+    /// Texels from top to bottom, left to right.
+    texels: Arc<Vec<Color>>,
+}
+
+fn open(path: &str) -> Result<File, Error> {
+    File::open(path).map_err(|e| Error::new(e.kind(), format!("{path}: {e}")))
+}
+
+fn load_png(path: &str) -> Result<(usize, usize, Vec<Color>), Error> {
+    let invalid = |e| Error::new(ErrorKind::InvalidData, format!("{path}: {e}"));
+
+    let mut decoder = png::Decoder::new(open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(invalid)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(invalid)?;
+    let bytes = &buffer[..info.buffer_size()];
+
+    let value = |b: u8| b as f32 / 255.;
+    let texels = match info.color_type {
+        png::ColorType::Rgb => bytes
+            .chunks_exact(3)
+            .map(|c| Color::new(value(c[0]), value(c[1]), value(c[2])))
+            .collect(),
+        png::ColorType::Rgba => bytes
+            .chunks_exact(4)
+            .map(|c| Color::new(value(c[0]), value(c[1]), value(c[2])))
+            .collect(),
+        png::ColorType::Grayscale => bytes.iter().map(|g| Color::grey(value(*g))).collect(),
+        png::ColorType::GrayscaleAlpha => bytes
+            .chunks_exact(2)
+            .map(|c| Color::grey(value(c[0])))
+            .collect(),
+        png::ColorType::Indexed => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{path}: unexpected indexed colors"),
+            ))
+        }
+    };
+    Ok((info.width as usize, info.height as usize, texels))
+}
+
+impl Texture {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let (width, height, texels) = match extension.as_deref() {
+            Some("png") => load_png(path)?,
+            Some("ppm") => read_ppm(BufReader::new(open(path)?))?,
+            Some("hdr") => read_hdr(BufReader::new(open(path)?))?,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{path}: unsupported texture format"),
+                ))
+            }
+        };
+        if width == 0 || height == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{path}: empty texture"),
+            ));
+        }
+
+        Ok(Self {
+            path: String::from(path),
+            width,
+            height,
+            texels: Arc::new(texels),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub(super) fn width(&self) -> usize {
+        self.width
+    }
+
+    pub(super) fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Unfiltered color of the texel in the given column and row, from the top.
+    pub(super) fn texel_at(&self, x: usize, y: usize) -> &Color {
+        &self.texels[x + y * self.width]
+    }
+
+    fn texel(&self, x: isize, y: isize, wrap: Wrap) -> &Color {
+        let x = wrap.apply(x, self.width);
+        let y = wrap.apply(y, self.height);
+        &self.texels[x + y * self.width]
+    }
+
+    /// Bilinearly filtered color at the given texture coordinates, `v` going up.
+    pub fn sample(&self, u: f32, v: f32, wrap: Wrap) -> Color {
+        let x = u * self.width as f32 - 0.5;
+        let y = (1. - v) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+
+        let row = |y| (1. - fx) * self.texel(x0, y, wrap) + fx * self.texel(x0 + 1, y, wrap);
+        (1. - fy) * row(y0) + fy * row(y0 + 1)
+    }
+}
+
+impl TryFrom<String> for Texture {
+    type Error = Error;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        Self::load(&path)
+    }
+}
+
+impl From<Texture> for String {
+    fn from(texture: Texture) -> Self {
+        texture.path
+    }
+}
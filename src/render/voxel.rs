@@ -0,0 +1,324 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::render::medium::scattering_hit;
+use crate::render::{Hit, Material, Medium, Ray, Sampler};
+use crate::types::Point;
+
+const MAGIC: &[u8; 4] = b"KVOL";
+
+/// Dense grid of densities, referenced by path in scenes.
+///
+/// Only the path is serialized, grids are loaded again when scenes are deserialized. Render
+/// servers receiving a scene must thus be able to read the grid at the same path.
+///
+/// Grid files start with the `KVOL` magic, followed by the little-endian `u32` resolution along
+/// X, Y and Z, then by the little-endian `f32` densities with X varying fastest and Z slowest.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct VoxelGrid {
+    path: String,
+    resolution: [usize; 3],
+    densities: Arc<Vec<f32>>,
+    max_density: f32,
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, Error> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+impl VoxelGrid {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let file = File::open(path).map_err(|e| Error::new(e.kind(), format!("{path}: {e}")))?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{path} is not a voxel grid"),
+            ));
+        }
+
+        let resolution = [
+            read_u32(&mut reader)? as usize,
+            read_u32(&mut reader)? as usize,
+            read_u32(&mut reader)? as usize,
+        ];
+        let invalid =
+            |message: &str| Error::new(ErrorKind::InvalidData, format!("{path}: {message}"));
+        if resolution.contains(&0) {
+            return Err(invalid("empty voxel grid"));
+        }
+        // check the size against the file before allocating it
+        let len = (resolution.iter())
+            .try_fold(4_usize, |len, &r| len.checked_mul(r))
+            .filter(|&len| len as u64 == file_len - (MAGIC.len() + 12) as u64)
+            .ok_or_else(|| invalid("resolution does not match the file size"))?;
+        let mut bytes = vec![0u8; len];
+        reader.read_exact(&mut bytes)?;
+        let densities: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+
+        Ok(Self {
+            path: String::from(path),
+            resolution,
+            max_density: densities.iter().fold(0., |max, d| d.max(max)),
+            densities: Arc::new(densities),
+        })
+    }
+
+    pub fn save(path: &str, resolution: [usize; 3], densities: &[f32]) -> Result<(), Error> {
+        debug_assert_eq!(resolution.iter().product::<usize>(), densities.len());
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        for r in resolution {
+            writer.write_all(&(r as u32).to_le_bytes())?;
+        }
+        for d in densities {
+            writer.write_all(&d.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f32 {
+        let [w, h, _] = self.resolution;
+        self.densities[x + w * (y + h * z)]
+    }
+
+    /// Trilinearly interpolated density at normalized grid coordinates.
+    pub fn density(&self, u: f32, v: f32, w: f32) -> f32 {
+        if !(0. ..=1.).contains(&u) || !(0. ..=1.).contains(&v) || !(0. ..=1.).contains(&w) {
+            return 0.;
+        }
+
+        // voxel values are located at the center of the voxels
+        let lookup = |coord: f32, resolution: usize| {
+            let c = (coord * resolution as f32 - 0.5).max(0.);
+            let i = (c as usize).min(resolution - 1);
+            (i, (i + 1).min(resolution - 1), c - i as f32)
+        };
+        let [rx, ry, rz] = self.resolution;
+        let (x0, x1, fx) = lookup(u, rx);
+        let (y0, y1, fy) = lookup(v, ry);
+        let (z0, z1, fz) = lookup(w, rz);
+
+        let lerp = |a: f32, b: f32, t: f32| a + t * (b - a);
+        let plane = |z| {
+            lerp(
+                lerp(self.voxel(x0, y0, z), self.voxel(x1, y0, z), fx),
+                lerp(self.voxel(x0, y1, z), self.voxel(x1, y1, z), fx),
+                fy,
+            )
+        };
+        lerp(plane(z0), plane(z1), fz)
+    }
+}
+
+impl TryFrom<String> for VoxelGrid {
+    type Error = Error;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        Self::load(&path)
+    }
+}
+
+impl From<VoxelGrid> for String {
+    fn from(grid: VoxelGrid) -> Self {
+        grid.path
+    }
+}
+
+/// Heterogeneous medium, like clouds or simulated smoke, whose density is read from a voxel grid
+/// stretched over an axis-aligned box.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct VoxelMedium {
+    pub grid: VoxelGrid,
+    pub min: Point,
+    pub max: Point,
+    pub density_scale: f32,
+    /// Phase function of the medium, e.g. `Material::Isotropic`.
+    pub material: Material,
+}
+
+impl VoxelMedium {
+    /// Returns the range of travels for which the ray is inside the box.
+    fn clip(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let mut enter = t_min;
+        let mut exit = t_max;
+        let axes = [
+            (ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+            (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+            (ray.origin.z, ray.direction.z, self.min.z, self.max.z),
+        ];
+        for (origin, direction, min, max) in axes {
+            let inverse = 1. / direction;
+            let (t0, t1) = ((min - origin) * inverse, (max - origin) * inverse);
+            enter = enter.max(t0.min(t1));
+            exit = exit.min(t0.max(t1));
+        }
+        (enter < exit).then_some((enter, exit))
+    }
+
+    fn density(&self, point: &Point) -> f32 {
+        let relative = |p: f32, min: f32, max: f32| (p - min) / (max - min);
+        self.density_scale
+            * self.grid.density(
+                relative(point.x, self.min.x, self.max.x),
+                relative(point.y, self.min.y, self.max.y),
+                relative(point.z, self.min.z, self.max.z),
+            )
+    }
+
+    fn majorant(&self) -> f32 {
+        self.density_scale * self.grid.max_density
+    }
+
+    /// Steps to the next tentative collision against the majorant density.
+    fn step(&self, ray: &Ray, travel: f32, sampler: &mut dyn Sampler) -> f32 {
+        let distance = -(1. - sampler.get_1d()).ln() / self.majorant();
+        travel + distance / ray.direction.len()
+    }
+}
+
+impl Medium for VoxelMedium {
+    fn sample(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Hit<'_>> {
+        if self.majorant() <= 0. {
+            return None;
+        }
+        let (enter, exit) = self.clip(ray, t_min, t_max)?;
+
+        // delta tracking: tentative collisions are real with probability density / majorant
+        let mut travel = enter;
+        loop {
+            travel = self.step(ray, travel, sampler);
+            if travel >= exit {
+                return None;
+            }
+            if sampler.get_1d() * self.majorant() < self.density(&ray.at(travel)) {
+                return Some(scattering_hit(ray, travel, &self.material));
+            }
+        }
+    }
+
+    fn transmittance(&self, ray: &Ray, t_min: f32, t_max: f32, sampler: &mut dyn Sampler) -> f32 {
+        if self.majorant() <= 0. {
+            return 1.;
+        }
+        let Some((enter, exit)) = self.clip(ray, t_min, t_max) else {
+            return 1.;
+        };
+
+        // ratio tracking: every tentative collision attenuates by the null collision probability
+        let mut transmittance = 1.;
+        let mut travel = enter;
+        loop {
+            travel = self.step(ray, travel, sampler);
+            if travel >= exit {
+                return transmittance;
+            }
+            transmittance *= 1. - self.density(&ray.at(travel)) / self.majorant();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::render::{Color, Colorer};
+    use crate::types::Vec3;
+
+    #[test]
+    fn grid_round_trip() {
+        let path = std::env::temp_dir().join("keyell_grid_round_trip.kvol");
+        let path = path.to_str().unwrap();
+        let densities: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        VoxelGrid::save(path, [2, 2, 2], &densities).unwrap();
+
+        let grid = VoxelGrid::load(path).unwrap();
+        assert_eq!(grid.resolution, [2, 2, 2]);
+        assert_eq!(grid.max_density, 7.);
+        assert_eq!(grid.density(0.25, 0.25, 0.25), 0.);
+        assert_eq!(grid.density(0.75, 0.75, 0.75), 7.);
+        assert_eq!(grid.density(0.5, 0.5, 0.5), 3.5);
+        assert_eq!(grid.density(1.5, 0.5, 0.5), 0.);
+    }
+
+    #[test]
+    fn rejects_bad_resolutions() {
+        let path = std::env::temp_dir().join("keyell_grid_bad_resolution.kvol");
+        let path = path.to_str().unwrap();
+        let header = |resolution: [u32; 3]| {
+            let mut bytes = MAGIC.to_vec();
+            for r in resolution {
+                bytes.extend_from_slice(&r.to_le_bytes());
+            }
+            bytes.extend_from_slice(&1_f32.to_le_bytes());
+            bytes
+        };
+        for resolution in [[0, 1, 1], [1, 1, 2], [u32::MAX, u32::MAX, u32::MAX]] {
+            std::fs::write(path, header(resolution)).unwrap();
+            let error = VoxelGrid::load(path).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+        std::fs::write(path, header([1, 1, 1])).unwrap();
+        assert!(VoxelGrid::load(path).is_ok());
+    }
+
+    #[test]
+    fn uniform_grid_tracking() {
+        let path = std::env::temp_dir().join("keyell_grid_uniform.kvol");
+        let path = path.to_str().unwrap();
+        VoxelGrid::save(path, [2, 2, 2], &[1.; 8]).unwrap();
+        let medium = VoxelMedium {
+            grid: VoxelGrid::load(path).unwrap(),
+            min: Point::new(-1., -1., -1.),
+            max: Point::new(1., 1., 1.),
+            density_scale: 0.5,
+            material: Material::Isotropic(Colorer::Solid(Color::WHITE)),
+        };
+        let ray = Ray {
+            origin: Point::new(-5., 0., 0.),
+            direction: Vec3::new(1., 0., 0.),
+        };
+        let expected = (-0.5_f32 * 2.).exp();
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        // both estimators are unbiased, and binary in uniform media
+        let samples = 100_000;
+        let transmittance = (0..samples)
+            .map(|_| medium.transmittance(&ray, 0.001, f32::INFINITY, &mut rng))
+            .sum::<f32>()
+            / samples as f32;
+        assert!((transmittance - expected).abs() < 0.01, "{}", transmittance);
+        let escaped = (0..samples)
+            .filter(|_| (medium.sample(&ray, 0.001, f32::INFINITY, &mut rng)).is_none())
+            .count();
+        let escaped = escaped as f32 / samples as f32;
+        assert!(
+            (escaped - expected).abs() < 0.01,
+            "{} {}",
+            escaped,
+            expected
+        );
+    }
+}
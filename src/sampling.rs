@@ -0,0 +1,233 @@
+use std::f32::consts::PI;
+
+use crate::render::Sampler;
+use crate::types::Vec3;
+
+pub const UNIFORM_SPHERE_PDF: f32 = 1. / (4. * PI);
+
+/// Uniformly samples a direction on the unit sphere.
+pub fn uniform_sphere(sampler: &mut dyn Sampler) -> Vec3 {
+    let (u, v) = sampler.get_2d();
+    let z = 1. - 2. * u;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * v;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Samples a direction in the hemisphere around the Z axis, with a density proportional to its
+/// cosine with the axis.
+pub fn cosine_hemisphere(sampler: &mut dyn Sampler) -> Vec3 {
+    // Malley's method: project uniform samples of the unit disk onto the hemisphere
+    let (u, v) = sampler.get_2d();
+    let phi = 2. * PI * v;
+    let r = u.sqrt();
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1. - u).max(0.).sqrt())
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: f32) -> f32 {
+    cos_theta.max(0.) / PI
+}
+
+/// Weight of a sample in multiple importance sampling, given its density under the technique
+/// that generated it and under the other one.
+pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0. {
+        return 0.;
+    }
+    a / (a + b)
+}
+
+/// Piecewise-constant distribution over [0, 1), proportional to a function sampled over equally
+/// sized segments.
+pub struct Distribution1D {
+    function: Vec<f32>,
+    /// Cumulative distribution at the start of each segment, followed by 1.
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    /// Falls back to a uniform distribution if the function is zero everywhere.
+    pub fn new(function: Vec<f32>) -> Self {
+        let n = function.len() as f32;
+        let mut cdf = vec![0.];
+        for f in &function {
+            cdf.push(cdf[cdf.len() - 1] + f.max(0.) / n);
+        }
+        let integral = cdf[cdf.len() - 1];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0. {
+                *c / integral
+            } else {
+                i as f32 / n
+            };
+        }
+        Self {
+            function,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    fn segment_pdf(&self, index: usize) -> f32 {
+        if self.integral > 0. {
+            self.function[index].max(0.) / self.integral
+        } else {
+            1.
+        }
+    }
+
+    /// Returns the point for the uniform sample `u`, its density and the index of its segment.
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let segments = self.function.len();
+        // the last segment starting before `u` cannot be empty
+        let index = (self.cdf.partition_point(|c| *c <= u).max(1) - 1).min(segments - 1);
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0. {
+            ((u - self.cdf[index]) / width).clamp(0., 1.)
+        } else {
+            0.
+        };
+        let x = ((index as f32 + offset) / segments as f32).min(1. - f32::EPSILON);
+        (x, self.segment_pdf(index), index)
+    }
+
+    pub fn pdf(&self, x: f32) -> f32 {
+        let segments = self.function.len();
+        self.segment_pdf(((x * segments as f32) as usize).min(segments - 1))
+    }
+}
+
+/// Piecewise-constant distribution over [0, 1)², proportional to a function sampled over a
+/// grid of equally sized cells.
+pub struct Distribution2D {
+    /// Distributions of `x` in each row.
+    conditionals: Vec<Distribution1D>,
+    /// Distribution of `y`, which picks the row.
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// Rows of `width` values of `function` are laid out one after the other.
+    pub fn new(function: &[f32], width: usize) -> Self {
+        let conditionals: Vec<_> = function
+            .chunks_exact(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditionals.iter().map(|c| c.integral()).collect());
+        Self {
+            conditionals,
+            marginal,
+        }
+    }
+
+    /// Returns the point `(x, y)` for the uniform samples `u`, and its density.
+    pub fn sample(&self, u: (f32, f32)) -> ((f32, f32), f32) {
+        let (y, pdf_y, row) = self.marginal.sample(u.1);
+        let (x, pdf_x, _) = self.conditionals[row].sample(u.0);
+        ((x, y), pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, x: f32, y: f32) -> f32 {
+        let rows = self.conditionals.len();
+        let row = ((y * rows as f32) as usize).min(rows - 1);
+        self.marginal.pdf(y) * self.conditionals[row].pdf(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    const SAMPLES: usize = 100_000;
+    const BINS: usize = 10;
+    // 99.9th percentile of the chi-square distribution with `BINS * BINS - 1` degrees of freedom
+    const CRITICAL_VALUE: f32 = 148.2;
+
+    /// Pearson's chi-square statistic of directions falling into `BINS * BINS` bins which are
+    /// equally likely if the directions are correctly distributed, `to_unit_square` mapping
+    /// directions to bins.
+    fn chi_square(
+        sample: fn(&mut dyn Sampler) -> Vec3,
+        to_unit_square: fn(&Vec3) -> (f32, f32),
+    ) -> f32 {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut counts = vec![0usize; BINS * BINS];
+        for _ in 0..SAMPLES {
+            let direction = sample(&mut rng);
+            assert!((direction.len() - 1.).abs() < 1e-5);
+            let (u, v) = to_unit_square(&direction);
+            let bin = |x: f32| ((x * BINS as f32) as usize).min(BINS - 1);
+            counts[bin(u) + BINS * bin(v)] += 1;
+        }
+        let expected = SAMPLES as f32 / counts.len() as f32;
+        counts
+            .iter()
+            .map(|count| (*count as f32 - expected).powi(2) / expected)
+            .sum()
+    }
+
+    fn azimuth(direction: &Vec3) -> f32 {
+        direction.y.atan2(direction.x) / (2. * PI) + 0.5
+    }
+
+    #[test]
+    fn uniform_sphere_distribution() {
+        // by Archimedes' hat-box theorem, heights are uniformly distributed on the sphere
+        let statistic = chi_square(uniform_sphere, |d| ((d.z + 1.) / 2., azimuth(d)));
+        assert!(statistic < CRITICAL_VALUE, "{}", statistic);
+    }
+
+    #[test]
+    fn cosine_hemisphere_distribution() {
+        let statistic = chi_square(cosine_hemisphere, |d| {
+            assert!(d.z >= 0.);
+            // squared sines are uniformly distributed under a cosine-weighted density
+            (1. - d.z * d.z, azimuth(d))
+        });
+        assert!(statistic < CRITICAL_VALUE, "{}", statistic);
+    }
+
+    #[test]
+    fn distribution_2d_follows_function() {
+        const WIDTH: usize = 4;
+        let function = [
+            1., 0., 2., 3., //
+            0., 0., 0., 0., //
+            4., 1., 1., 0.5, //
+        ];
+        let total: f32 = function.iter().sum();
+        let distribution = Distribution2D::new(&function, WIDTH);
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut counts = [0usize; 12];
+        for _ in 0..SAMPLES {
+            let ((x, y), pdf) = distribution.sample((rng.gen(), rng.gen()));
+            assert!((pdf - distribution.pdf(x, y)).abs() < 1e-4);
+            let cell = (x * WIDTH as f32) as usize + WIDTH * (y * 3.) as usize;
+            // densities are per unit area, cells being a twelfth of it
+            assert!((pdf - function[cell] / total * 12.).abs() < 1e-4);
+            counts[cell] += 1;
+        }
+
+        let mut statistic = 0.;
+        for (count, f) in counts.iter().zip(&function) {
+            let expected = SAMPLES as f32 * f / total;
+            if expected == 0. {
+                assert_eq!(*count, 0);
+            } else {
+                statistic += (*count as f32 - expected).powi(2) / expected;
+            }
+        }
+        // 99.9th percentile of the chi-square distribution with 6 degrees of freedom
+        assert!(statistic < 22.46, "{}", statistic);
+    }
+}
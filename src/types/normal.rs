@@ -15,4 +15,12 @@ impl Normal {
             Normal::Outward(v) => v.clone(),
         }
     }
+
+    /// Replaces the vector returned by `outward`, keeping the side of the surface.
+    pub fn with_outward(&self, outward: UnitVec3) -> Self {
+        match self {
+            Normal::Inward(_) => Normal::Inward(-&outward),
+            Normal::Outward(_) => Normal::Outward(outward),
+        }
+    }
 }
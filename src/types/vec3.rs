@@ -1,8 +1,10 @@
-use rand::{rngs::SmallRng, Rng};
+use rand::rngs::SmallRng;
 use serde::{Deserialize, Serialize};
 use std::ops::{Add, Div, Mul, Neg, Sub};
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+use crate::sampling;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -35,13 +37,9 @@ impl UnitVec3 {
         &self.0
     }
 
+    /// Uniformly distributed unit vector.
     pub fn random(rng: &mut SmallRng) -> Self {
-        let random_vector = Vec3 {
-            x: rng.gen_range(-1. ..1.),
-            y: rng.gen_range(-1. ..1.),
-            z: rng.gen_range(-1. ..1.),
-        };
-        random_vector.unit()
+        Self(sampling::uniform_sphere(rng))
     }
 }
 